@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::db::DB;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+// upper bounds of the histogram buckets, the last one catches the rest
+const TTL_BUCKETS: &[(&str, u64)] = &[
+    ("ttl_lt_1m", MINUTE),
+    ("ttl_lt_1h", HOUR),
+    ("ttl_lt_1d", DAY),
+    ("ttl_lt_7d", 7 * DAY),
+    ("ttl_ge_7d", u64::MAX),
+];
+const SIZE_BUCKETS: &[(&str, usize)] = &[
+    ("size_le_16", 16),
+    ("size_le_64", 64),
+    ("size_le_256", 256),
+    ("size_le_1k", 1024),
+    ("size_le_4k", 4 * 1024),
+    ("size_le_16k", 16 * 1024),
+    ("size_gt_16k", usize::MAX),
+];
+
+/// Walks up to `samples` live keys and reports key counts per type,
+/// persistent vs volatile keys, and TTL and value size histograms.
+pub fn keyspace_report(db: &DB, samples: usize) -> Vec<(String, String)> {
+    let mut sampled = 0;
+    let mut volatile = 0;
+    let mut total_size = 0;
+    let mut max_size = 0;
+    let mut ttls = vec![0; TTL_BUCKETS.len()];
+    let mut sizes = vec![0; SIZE_BUCKETS.len()];
+    let mut types = BTreeMap::new();
+
+    db.visit(samples, |_key, value, ttl| {
+        sampled += 1;
+        *types.entry(value.type_name()).or_insert(0) += 1;
+        total_size += value.len();
+        max_size = max_size.max(value.len());
+        let size = SIZE_BUCKETS.iter().position(|(_, max)| value.len() <= *max);
+        sizes[size.unwrap()] += 1;
+
+        if let Some(ttl) = ttl {
+            volatile += 1;
+            let bucket = TTL_BUCKETS
+                .iter()
+                .position(|(_, max)| ttl < Duration::from_secs(*max));
+            ttls[bucket.unwrap_or(TTL_BUCKETS.len() - 1)] += 1;
+        }
+    });
+
+    let mut report = vec![("keys_sampled".to_string(), sampled.to_string())];
+    for (name, count) in types {
+        report.push((format!("type_{name}"), count.to_string()));
+    }
+    report.push(("persistent".to_string(), (sampled - volatile).to_string()));
+    report.push(("volatile".to_string(), volatile.to_string()));
+    for ((name, _), count) in TTL_BUCKETS.iter().zip(ttls) {
+        report.push((name.to_string(), count.to_string()));
+    }
+    let avg_size = total_size.checked_div(sampled).unwrap_or(0);
+    report.push(("size_avg".to_string(), avg_size.to_string()));
+    report.push(("size_max".to_string(), max_size.to_string()));
+    for ((name, _), count) in SIZE_BUCKETS.iter().zip(sizes) {
+        report.push((name.to_string(), count.to_string()));
+    }
+    report
+}
@@ -0,0 +1,335 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use thiserror::Error;
+
+use crate::command::Command;
+use crate::db::{Value, DB};
+use crate::exec;
+use crate::parse::array_bytes;
+use crate::rdb::{self, RdbEntry};
+
+/// How an AOF with an RDB preamble starts
+const RDB_MAGIC: &[u8] = b"REDIS";
+/// Elements per command when a rewrite recreates a collection, as in Redis
+const ITEMS_PER_COMMAND: usize = 64;
+/// Most arguments set aside up front, since the count comes from the file
+const PREALLOC_LIMIT: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum AofError {
+    #[error("unexpected end of file at offset {0}")]
+    Truncated(usize),
+    #[error("bad file format at offset {0}: {1}")]
+    Malformed(usize, String),
+}
+
+/// Result of walking an AOF: the complete commands and how far they reach.
+#[derive(Debug)]
+pub struct Scan {
+    pub commands: Vec<Vec<Vec<u8>>>,
+    /// Length of the prefix made only of complete commands
+    pub valid_len: usize,
+    pub error: Option<AofError>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Result<&'a str, AofError> {
+        let rest = &self.bytes[self.pos..];
+        let Some(end) = rest.windows(2).position(|w| w == b"\r\n") else {
+            return Err(AofError::Truncated(self.bytes.len()));
+        };
+        let line = std::str::from_utf8(&rest[..end])
+            .map_err(|_| AofError::Malformed(self.pos, "invalid utf-8".to_string()))?;
+        self.pos += end + 2;
+        Ok(line)
+    }
+
+    fn number(&mut self, prefix: char) -> Result<usize, AofError> {
+        let start = self.pos;
+        let line = self.line()?;
+        line.strip_prefix(prefix)
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| AofError::Malformed(start, format!("expected '{prefix}<n>'")))
+    }
+
+    fn command(&mut self) -> Result<Vec<Vec<u8>>, AofError> {
+        let count = self.number('*')?;
+        let mut args = Vec::with_capacity(count.min(PREALLOC_LIMIT));
+        for _ in 0..count {
+            let start = self.pos;
+            let len = self.number('$')?;
+            let end = self
+                .pos
+                .checked_add(len)
+                .and_then(|end| end.checked_add(2))
+                .ok_or_else(|| AofError::Malformed(start, "bulk length too large".to_string()))?;
+            if end > self.bytes.len() {
+                return Err(AofError::Truncated(self.bytes.len()));
+            }
+            let arg = &self.bytes[self.pos..self.pos + len];
+            if &self.bytes[self.pos + len..end] != b"\r\n" {
+                return Err(AofError::Malformed(
+                    self.pos + len,
+                    "expected CRLF".to_string(),
+                ));
+            }
+            args.push(arg.to_vec());
+            self.pos += len + 2;
+        }
+        Ok(args)
+    }
+}
+
+/// Parses as many complete commands as possible, stopping at the first error.
+pub fn scan(bytes: &[u8]) -> Scan {
+    let mut reader = Reader { bytes, pos: 0 };
+    let mut commands = vec![];
+    let mut error = None;
+
+    while reader.pos < bytes.len() {
+        let start = reader.pos;
+        match reader.command() {
+            Ok(command) => commands.push(command),
+            Err(err) => {
+                reader.pos = start;
+                error = Some(err);
+                break;
+            }
+        }
+    }
+
+    Scan {
+        commands,
+        valid_len: reader.pos,
+        error,
+    }
+}
+
+/// Loads the AOF at `path` into the dataset: the RDB preamble a rewrite may
+/// have started it with, then the commands after it. Returns how many keys
+/// and commands it held; a missing file holds none. A truncated tail, as a
+/// crash midway through a write leaves, is cut off when `load_truncated`
+/// allows it.
+pub fn load(path: &Path, load_truncated: bool, db: &DB) -> Result<(usize, usize)> {
+    let bytes = match fs::read(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok((0, 0)),
+        result => result?,
+    };
+    let (keys, preamble) = if bytes.starts_with(RDB_MAGIC) {
+        let rdb = rdb::parse(&bytes)
+            .map_err(|err| anyhow!("bad RDB preamble in AOF {}: {err}", path.display()))?;
+        let entries = rdb.databases.into_iter().flat_map(|db| db.entries);
+        (db.load(entries.collect()), rdb.len)
+    } else {
+        (0, 0)
+    };
+
+    let scan = scan(&bytes[preamble..]);
+    match scan.error {
+        None => {}
+        Some(AofError::Truncated(_)) if load_truncated => {
+            let len = preamble + scan.valid_len;
+            eprintln!(
+                "[WARN] AOF {} was truncated, loading the first {len} bytes and cutting the rest",
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(path)?
+                .set_len(len as u64)?;
+        }
+        Some(err) => {
+            return Err(anyhow!(
+                "bad AOF {}: {err}, see --check-aof",
+                path.display()
+            ))
+        }
+    }
+    // the same writes replicas are sent, so they replay the same way
+    for command in &scan.commands {
+        exec::execute(db, &Command::parse(command));
+    }
+    Ok((keys, scan.commands.len()))
+}
+
+/// Commands that recreate the entries, for a rewrite without an RDB
+/// preamble. Streams go in as RESTORE, which keeps their last id and
+/// counters and can make an empty one.
+fn commands(entries: &[RdbEntry]) -> Vec<u8> {
+    let mut out = vec![];
+    for entry in entries {
+        let key = entry.key.as_bytes();
+        let mut collection = |name: &str, items: Vec<Vec<u8>>, per_item: usize| {
+            for chunk in items.chunks(ITEMS_PER_COMMAND * per_item) {
+                let mut args = vec![name.as_bytes().to_vec(), key.to_vec()];
+                args.extend_from_slice(chunk);
+                out.extend(array_bytes(&args));
+            }
+        };
+        let text = |item: &String| item.as_bytes().to_vec();
+        match &entry.value {
+            Value::String(value) => collection("set", vec![value.clone()], 1),
+            Value::List(list) => collection("rpush", list.iter().map(text).collect(), 1),
+            Value::Set(set) => collection("sadd", set.iter().map(text).collect(), 1),
+            Value::Hash(hash) => {
+                let items = hash
+                    .iter()
+                    .flat_map(|(field, value)| [text(field), text(value)]);
+                collection("hset", items.collect(), 2)
+            }
+            Value::ZSet(zset) => {
+                let items = zset.iter().flat_map(|(member, score)| {
+                    [score.to_string().into_bytes(), member.as_bytes().to_vec()]
+                });
+                collection("zadd", items.collect(), 2)
+            }
+            Value::Stream(_) => {
+                let payload = rdb::dump(&entry.value);
+                out.extend(array_bytes(&[b"restore", key, b"0", &payload]));
+            }
+        }
+        if let Some(at) = entry.expires_at {
+            out.extend(array_bytes(&[b"pexpireat", key, at.to_string().as_bytes()]));
+        }
+    }
+    out
+}
+
+/// When appended writes are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fsync {
+    /// after every write
+    Always,
+    /// once a second, by a background task
+    EverySec,
+    /// whenever the OS gets to it
+    No,
+}
+
+impl Fsync {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "always" => Fsync::Always,
+            "no" => Fsync::No,
+            _ => Fsync::EverySec,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Writer {
+    file: File,
+    path: PathBuf,
+    fsync: Fsync,
+    /// Writes appended since a running rewrite took its snapshot, which
+    /// go at the end of the rewritten file
+    rewrite: Option<Vec<u8>>,
+}
+
+/// The append-only file writes are logged to, shared by every connection.
+#[derive(Debug, Clone)]
+pub struct Aof(Arc<Mutex<Writer>>);
+
+impl Aof {
+    /// Opens the file for appending, creating it if needed.
+    pub fn open(path: &Path, fsync: Fsync) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self(Arc::new(Mutex::new(Writer {
+            file,
+            path: path.to_path_buf(),
+            fsync,
+            rewrite: None,
+        }))))
+    }
+
+    /// Appends a write in RESP form. The file is unbuffered, so it reaches
+    /// the OS straight away, and the disk too under `always`.
+    pub fn append(&self, command: &[u8]) {
+        let mut writer = self.0.lock().unwrap();
+        let mut result = writer.file.write_all(command);
+        if writer.fsync == Fsync::Always {
+            result = result.and_then(|_| writer.file.sync_data());
+        }
+        if let Err(err) = result {
+            eprintln!("[ERROR] Writing to the AOF failed: {err}");
+        }
+        if let Some(rewrite) = &mut writer.rewrite {
+            rewrite.extend_from_slice(command);
+        }
+    }
+
+    /// Picks up a policy changed by a config reload.
+    pub fn set_fsync(&self, fsync: Fsync) {
+        self.0.lock().unwrap().fsync = fsync;
+    }
+
+    pub fn sync(&self) {
+        if let Err(err) = self.0.lock().unwrap().file.sync_data() {
+            eprintln!("[ERROR] Syncing the AOF failed: {err}");
+        }
+    }
+
+    pub fn rewriting(&self) -> bool {
+        self.0.lock().unwrap().rewrite.is_some()
+    }
+
+    /// BGREWRITEAOF: replaces the file with the shortest one that recreates
+    /// the dataset, an RDB snapshot with `preamble` or else commands, built
+    /// on a blocking thread. Returns false if a rewrite is already running.
+    pub fn rewrite(&self, db: &DB, preamble: bool) -> bool {
+        let mut writer = self.0.lock().unwrap();
+        if writer.rewrite.is_some() {
+            return false;
+        }
+        // taken under the lock, so writes after it are the ones buffered
+        let entries = db.snapshot();
+        writer.rewrite = Some(vec![]);
+        let path = writer.path.clone();
+        drop(writer);
+
+        println!("[INFO] Background append only file rewriting started");
+        let aof = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let base = if preamble {
+                rdb::write(&entries, None)
+            } else {
+                commands(&entries)
+            };
+            let temp = path.with_file_name(format!("temp-rewriteaof-bg-{}.aof", process::id()));
+            match aof.replace(&temp, &base) {
+                Ok(()) => println!("[INFO] Background AOF rewrite finished successfully"),
+                Err(err) => {
+                    aof.0.lock().unwrap().rewrite = None;
+                    let _ = fs::remove_file(&temp);
+                    eprintln!("[ERROR] Background AOF rewrite failed: {err}");
+                }
+            }
+        });
+        true
+    }
+
+    /// Writes the rewritten file through `temp` and appends to it from then on.
+    fn replace(&self, temp: &Path, base: &[u8]) -> io::Result<()> {
+        let mut file = File::create(temp)?;
+        file.write_all(base)?;
+        // appends wait from here until the new file takes over, so none
+        // are missed
+        let mut writer = self.0.lock().unwrap();
+        file.write_all(writer.rewrite.as_deref().unwrap_or_default())?;
+        file.sync_all()?;
+        fs::rename(temp, &writer.path)?;
+        writer.file = file;
+        writer.rewrite = None;
+        Ok(())
+    }
+}
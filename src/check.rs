@@ -0,0 +1,97 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Result};
+
+use crate::aof::{self, AofError};
+use crate::rdb::{self, Checksum};
+
+/// Validates an RDB file and prints a summary report, like `redis-check-rdb`.
+pub fn check_rdb(path: &str) -> Result<()> {
+    println!("[offset 0] Checking RDB file {path}");
+    let bytes = std::fs::read(path)?;
+    let rdb = rdb::parse(&bytes)?;
+
+    println!("[offset 0] RDB version {}", rdb.version);
+    for (key, value) in &rdb.aux {
+        println!("[info] AUX FIELD {key} = '{value}'");
+    }
+
+    for db in &rdb.databases {
+        let mut seen = HashSet::new();
+        let mut types: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for entry in &db.entries {
+            if !seen.insert(&entry.key) {
+                return Err(anyhow!("duplicated key '{}' in db{}", entry.key, db.index));
+            }
+            let (count, len) = types.entry(entry.value.type_name()).or_default();
+            *count += 1;
+            *len += entry.value.len();
+        }
+        let expires = db.entries.iter().filter(|e| e.expires_at.is_some()).count();
+
+        println!(
+            "[info] db{index}: keys={keys} expires={expires}",
+            index = db.index,
+            keys = db.entries.len()
+        );
+        for (name, (count, len)) in types {
+            println!("[info]   {name}: {count} keys, total length {len}");
+        }
+    }
+
+    match rdb.checksum {
+        Checksum::Valid(crc) => println!("[offset {}] Checksum OK ({crc:016x})", bytes.len()),
+        Checksum::Disabled => println!("[offset {}] Checksum disabled", bytes.len()),
+        Checksum::Missing => println!("[offset {}] No checksum trailer", bytes.len()),
+    }
+    println!("\\o/ RDB looks OK! \\o/");
+    Ok(())
+}
+
+/// Validates an AOF, optionally truncating it to the last complete command,
+/// like `redis-check-aof [--fix]`.
+pub fn check_aof(path: &str, fix: bool) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    // a rewritten AOF may start with an RDB snapshot
+    let preamble = if bytes.starts_with(b"REDIS") {
+        let rdb = rdb::parse(&bytes).map_err(|err| anyhow!("RDB preamble is not valid: {err}"))?;
+        println!("The AOF appears to start with an RDB preamble, checking it");
+        println!("RDB preamble is OK, proceeding with AOF tail");
+        rdb.len
+    } else {
+        0
+    };
+    let scan = aof::scan(&bytes[preamble..]);
+    let valid_len = preamble + scan.valid_len;
+    println!(
+        "AOF analyzed: filename={path}, size={size}, ok_up_to={valid_len}, commands={count}, diff={diff}",
+        size = bytes.len(),
+        count = scan.commands.len(),
+        diff = bytes.len() - valid_len
+    );
+
+    let Some(err) = scan.error else {
+        println!("AOF {path} is valid");
+        return Ok(());
+    };
+    match err {
+        AofError::Truncated(_) => println!("AOF {path} has a truncated tail: {err}"),
+        AofError::Malformed(..) => println!("AOF {path} is not valid: {err}"),
+    }
+
+    if !fix {
+        return Err(anyhow!(
+            "AOF is not valid. Use the --fix option to try fixing it."
+        ));
+    }
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(valid_len as u64)?;
+    println!(
+        "Successfully truncated AOF {path} from {from} to {valid_len} bytes",
+        from = bytes.len()
+    );
+    Ok(())
+}
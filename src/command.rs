@@ -1,4 +1,12 @@
-use std::time::Duration;
+use std::ops::Bound;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dataset::{Conflict, Format, Remote, Source};
+use crate::db::{
+    geohash, in_bounds, Aggregate, BitField, BitFieldOp, End, ExpireFlags, Expiry, GeoFrom,
+    GeoOrder, GeoQuery, GeoShape, Overflow, SetCondition, SetOp, SortOptions, StreamId, XAddId,
+    ZAddFlags, ZRange,
+};
 
 #[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
 pub enum Replconf {
@@ -8,74 +16,2889 @@ pub enum Replconf {
     Ack(String),
 }
 
-#[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
+/// What OBJECT reports about a key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectField {
+    Encoding,
+    RefCount,
+    IdleTime,
+    Freq,
+}
+
+#[derive(Debug, PartialEq)]
 pub enum Command {
     Ping,
     Echo(String),
     Set {
         key: String,
         value: String,
-        ex: Option<Duration>,
+        condition: Option<SetCondition>,
+        /// no expiration when `None`
+        expiry: Option<Expiry>,
+        /// reply with the old value
+        get: bool,
+    },
+    /// DEL, or UNLINK when `lazy` is set
+    Del {
+        keys: Vec<String>,
+        lazy: bool,
+    },
+    Exists {
+        keys: Vec<String>,
+    },
+    Touch {
+        keys: Vec<String>,
+    },
+    Keys {
+        pattern: String,
+    },
+    Type {
+        key: String,
+    },
+    Copy {
+        source: String,
+        destination: String,
+        replace: bool,
+    },
+    Object {
+        field: ObjectField,
+        key: String,
+    },
+    Dump {
+        key: String,
+    },
+    Sort {
+        key: String,
+        options: Box<SortOptions>,
+        store: Option<String>,
+    },
+    /// RESTORE, with the expiration made absolute
+    Restore {
+        key: String,
+        payload: Vec<u8>,
+        at: Option<SystemTime>,
+        replace: bool,
+    },
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        /// only keys of this type, as TYPE names it
+        type_name: Option<String>,
+    },
+    /// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT, with the time made absolute
+    Expire {
+        key: String,
+        at: SystemTime,
+        flags: ExpireFlags,
+    },
+    /// SET NX, replying 1 or 0
+    SetNx {
+        key: String,
+        value: String,
     },
     Get {
         key: String,
     },
+    /// INCR, DECR, INCRBY and DECRBY
+    Incr {
+        key: String,
+        delta: i64,
+    },
+    IncrByFloat {
+        key: String,
+        delta: f64,
+    },
+    Append {
+        key: String,
+        value: String,
+    },
+    MGet {
+        keys: Vec<String>,
+    },
+    /// MSET, or MSETNX when `if_absent` is set
+    MSet {
+        pairs: Vec<(String, String)>,
+        if_absent: bool,
+    },
+    StrLen {
+        key: String,
+    },
+    Push {
+        key: String,
+        values: Vec<String>,
+        end: End,
+    },
+    /// `count` is `None` for the single element form, which replies with a bulk string
+    Pop {
+        key: String,
+        count: Option<usize>,
+        end: End,
+    },
+    /// Pops from the first of `keys` holding a list
+    MPop {
+        keys: Vec<String>,
+        end: End,
+        count: usize,
+    },
+    /// Waits for the first of `keys` to have an element, forever when `timeout` is `None`.
+    /// `count` is `None` for BLPOP/BRPOP, which reply with a single element.
+    BlockingPop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+        end: End,
+        count: Option<usize>,
+    },
+    Move {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+    },
+    BlockingMove {
+        source: String,
+        destination: String,
+        from: End,
+        to: End,
+        timeout: Option<Duration>,
+    },
+    LRange {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    LLen {
+        key: String,
+    },
+    /// `count` is `None` when the reply is a single index rather than an array
+    LPos {
+        key: String,
+        element: String,
+        rank: i64,
+        count: Option<usize>,
+        maxlen: usize,
+    },
+    LInsert {
+        key: String,
+        before: bool,
+        pivot: String,
+        element: String,
+    },
+    LSet {
+        key: String,
+        index: i64,
+        element: String,
+    },
+    LRem {
+        key: String,
+        count: i64,
+        element: String,
+    },
+    LTrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    HSet {
+        key: String,
+        pairs: Vec<(String, String)>,
+    },
+    HSetNx {
+        key: String,
+        field: String,
+        value: String,
+    },
+    HIncrBy {
+        key: String,
+        field: String,
+        delta: i64,
+    },
+    HIncrByFloat {
+        key: String,
+        field: String,
+        delta: f64,
+    },
+    HGet {
+        key: String,
+        field: String,
+    },
+    HMGet {
+        key: String,
+        fields: Vec<String>,
+    },
+    HDel {
+        key: String,
+        fields: Vec<String>,
+    },
+    HGetAll {
+        key: String,
+    },
+    HExists {
+        key: String,
+        field: String,
+    },
+    HLen {
+        key: String,
+    },
+    HKeys {
+        key: String,
+    },
+    HVals {
+        key: String,
+    },
+    HScan {
+        key: String,
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+    },
+    /// `count` is `None` for the single field form, which replies with a bulk string
+    HRandField {
+        key: String,
+        count: Option<i64>,
+        with_values: bool,
+    },
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    SMembers {
+        key: String,
+    },
+    SIsMember {
+        key: String,
+        member: String,
+    },
+    SCard {
+        key: String,
+    },
+    /// `count` is `None` for the single member form, which replies with a bulk string
+    SPop {
+        key: String,
+        count: Option<usize>,
+    },
+    SRandMember {
+        key: String,
+        count: Option<i64>,
+    },
+    SMove {
+        source: String,
+        destination: String,
+        member: String,
+    },
+    SMIsMember {
+        key: String,
+        members: Vec<String>,
+    },
+    /// `limit` of zero means no limit
+    SInterCard {
+        keys: Vec<String>,
+        limit: usize,
+    },
+    /// SINTER, SUNION and SDIFF
+    SetOp {
+        op: SetOp,
+        keys: Vec<String>,
+    },
+    /// SINTERSTORE, SUNIONSTORE and SDIFFSTORE
+    SetOpStore {
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+    },
+    ZAdd {
+        key: String,
+        flags: ZAddFlags,
+        pairs: Vec<(f64, String)>,
+    },
+    ZScore {
+        key: String,
+        member: String,
+    },
+    ZMScore {
+        key: String,
+        members: Vec<String>,
+    },
+    ZCard {
+        key: String,
+    },
+    ZRandMember {
+        key: String,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    ZIncrBy {
+        key: String,
+        delta: f64,
+        member: String,
+    },
+    /// ZRANK and ZREVRANK
+    ZRank {
+        key: String,
+        member: String,
+        rev: bool,
+        with_score: bool,
+    },
+    ZRange {
+        key: String,
+        start: i64,
+        stop: i64,
+        rev: bool,
+        with_scores: bool,
+    },
+    /// ZRANGEBYSCORE and ZREVRANGEBYSCORE, LIMIT is folded into `offset` and `count`
+    ZRangeByScore {
+        key: String,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        with_scores: bool,
+        offset: usize,
+        count: usize,
+    },
+    /// ZRANGEBYLEX and ZREVRANGEBYLEX
+    ZRangeByLex {
+        key: String,
+        min: Bound<String>,
+        max: Bound<String>,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    },
+    ZRangeStore {
+        destination: String,
+        source: String,
+        range: Box<ZRange>,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    },
+    /// ZCOUNT and ZLEXCOUNT
+    ZCount {
+        key: String,
+        range: ZRange,
+    },
+    /// ZREMRANGEBYRANK, ZREMRANGEBYSCORE and ZREMRANGEBYLEX
+    ZRemRange {
+        key: String,
+        range: ZRange,
+    },
+    /// ZUNION, ZINTER and ZDIFF
+    ZCombine {
+        op: SetOp,
+        keys: Vec<String>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+        with_scores: bool,
+    },
+    /// ZUNIONSTORE, ZINTERSTORE and ZDIFFSTORE
+    ZCombineStore {
+        op: SetOp,
+        destination: String,
+        keys: Vec<String>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    },
+    XAdd {
+        key: String,
+        id: XAddId,
+        fields: Vec<(String, String)>,
+        /// false with NOMKSTREAM
+        create: bool,
+    },
+    XRead {
+        keys: Vec<String>,
+        /// `None` for `$`, the stream's last id when the command runs
+        ids: Vec<Option<StreamId>>,
+        count: usize,
+        /// `Some` with BLOCK, where zero waits forever
+        block: Option<Duration>,
+    },
+    XSetId {
+        key: String,
+        id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    },
+    XInfoStream {
+        key: String,
+    },
+    SetBit {
+        key: String,
+        offset: u64,
+        bit: bool,
+    },
+    GetBit {
+        key: String,
+        offset: u64,
+    },
+    BitCount {
+        key: String,
+        range: Option<(i64, i64)>,
+        /// the range is in bits rather than bytes
+        bits: bool,
+    },
+    BitField {
+        key: String,
+        ops: Vec<BitFieldOp>,
+    },
+    GeoPos {
+        key: String,
+        members: Vec<String>,
+    },
+    /// `unit` is the length of the reply's unit in meters
+    GeoDist {
+        key: String,
+        from: String,
+        to: String,
+        unit: f64,
+    },
+    /// Also GEORADIUS and GEORADIUSBYMEMBER without STORE
+    GeoSearch {
+        key: String,
+        query: GeoQuery,
+        unit: f64,
+        with_coord: bool,
+        with_dist: bool,
+        with_hash: bool,
+    },
+    /// Also GEORADIUS and GEORADIUSBYMEMBER with STORE or STOREDIST, which
+    /// scores members by distance in `unit` rather than by hash
+    GeoSearchStore {
+        destination: String,
+        source: String,
+        query: Box<GeoQuery>,
+        unit: f64,
+        store_dist: bool,
+    },
     Info,
     Replconf(Replconf),
-    Psync,
-    Err,
+    Psync {
+        replid: String,
+        offset: i64,
+    },
+    Sync,
+    /// Carries the error message sent back to the client
+    Err(String),
     Wait(usize, u64),
+    Export {
+        filename: String,
+        format: Format,
+    },
+    Import {
+        source: Source,
+        conflict: Conflict,
+    },
+    KeyStats {
+        samples: usize,
+    },
+    Save,
+    BgSave,
+    LastSave,
+    BgRewriteAof,
+    ConfigGet {
+        pattern: String,
+    },
+    ConfigSet {
+        name: String,
+        value: String,
+    },
+    /// The master's host and port, none to become a master
+    ReplicaOf(Option<(String, String)>),
+    /// `save` overrides whether the save points ask for a final save
+    Shutdown {
+        save: Option<bool>,
+        now: bool,
+        force: bool,
+    },
+    /// Only the default user exists, so a username must name it
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
 }
 
+/// Every command name understood, to tell bad arity from unknown commands
+const COMMANDS: &[&str] = &[
+    "ping",
+    "echo",
+    "set",
+    "get",
+    "del",
+    "unlink",
+    "exists",
+    "touch",
+    "keys",
+    "type",
+    "copy",
+    "object",
+    "dump",
+    "restore",
+    "sort",
+    "scan",
+    "expire",
+    "pexpire",
+    "expireat",
+    "pexpireat",
+    "setnx",
+    "setex",
+    "psetex",
+    "getset",
+    "incr",
+    "decr",
+    "incrby",
+    "decrby",
+    "incrbyfloat",
+    "append",
+    "strlen",
+    "mget",
+    "mset",
+    "msetnx",
+    "lpush",
+    "rpush",
+    "lpop",
+    "rpop",
+    "blpop",
+    "brpop",
+    "lmpop",
+    "blmpop",
+    "lrange",
+    "lpos",
+    "llen",
+    "linsert",
+    "lset",
+    "lrem",
+    "ltrim",
+    "lmove",
+    "rpoplpush",
+    "blmove",
+    "hset",
+    "hsetnx",
+    "hincrby",
+    "hincrbyfloat",
+    "hget",
+    "hmget",
+    "hdel",
+    "hgetall",
+    "hexists",
+    "hlen",
+    "hkeys",
+    "hvals",
+    "hscan",
+    "hrandfield",
+    "sadd",
+    "srem",
+    "smembers",
+    "sismember",
+    "scard",
+    "spop",
+    "srandmember",
+    "smove",
+    "smismember",
+    "sintercard",
+    "sinter",
+    "sunion",
+    "sdiff",
+    "sinterstore",
+    "sunionstore",
+    "sdiffstore",
+    "zadd",
+    "zscore",
+    "zmscore",
+    "zcard",
+    "zrandmember",
+    "zincrby",
+    "zrank",
+    "zrevrank",
+    "zrange",
+    "zrangebyscore",
+    "zrevrangebyscore",
+    "zrangebylex",
+    "zrevrangebylex",
+    "zrangestore",
+    "zcount",
+    "zlexcount",
+    "zremrangebyrank",
+    "zremrangebyscore",
+    "zremrangebylex",
+    "zunion",
+    "zinter",
+    "zdiff",
+    "zunionstore",
+    "zinterstore",
+    "zdiffstore",
+    "setbit",
+    "getbit",
+    "bitcount",
+    "bitfield",
+    "geoadd",
+    "geopos",
+    "geodist",
+    "geosearch",
+    "geosearchstore",
+    "georadius",
+    "georadiusbymember",
+    "georadius_ro",
+    "georadiusbymember_ro",
+    "xadd",
+    "xread",
+    "xsetid",
+    "xinfo",
+    "info",
+    "replconf",
+    "psync",
+    "sync",
+    "wait",
+    "export",
+    "import",
+    "keystats",
+    "save",
+    "bgsave",
+    "lastsave",
+    "bgrewriteaof",
+    "config",
+    "replicaof",
+    "slaveof",
+    "shutdown",
+    "auth",
+];
+
+const SYNTAX_ERROR: &str = "ERR syntax error";
+const NOT_UTF8: &str = "ERR arguments must be valid UTF-8";
+const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+const NOT_A_FLOAT: &str = "ERR value is not a valid float";
+const OUT_OF_RANGE: &str = "ERR value is out of range";
+/// Most items SRANDMEMBER, HRANDFIELD and ZRANDMEMBER repeat for a negative
+/// count, since the reply is built whole
+const MAX_RANDOM_REPEATS: u64 = 16 * 1024 * 1024;
+const BAD_BIT_OFFSET: &str = "ERR bit offset is not an integer or out of range";
+const BAD_GEO_UNIT: &str = "ERR unsupported unit provided. please use M, KM, FT, MI";
+const BAD_BITFIELD_TYPE: &str =
+    "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.";
+const INVALID_STREAM_ID: &str = "ERR Invalid stream ID specified as stream command argument";
+
 impl Command {
-    pub(crate) fn parse(input: &[String]) -> Command {
-        let input_lower: Vec<String> = input.iter().map(|s| s.to_lowercase()).collect();
-        let input_lower: Vec<&str> = input_lower.iter().map(|s| s.as_ref()).collect();
+    /// Command names and keywords are case-insensitive, keys and values keep their case.
+    /// Arguments must be UTF-8, except the payload RESTORE takes.
+    pub(crate) fn parse(input: &[Vec<u8>]) -> Command {
+        let Some((name, args)) = input.split_first() else {
+            return Command::Err("ERR empty command".to_string());
+        };
+        let Ok(name) = std::str::from_utf8(name) else {
+            return Command::Err(NOT_UTF8.to_string());
+        };
+        let name = name.to_lowercase();
+        if name == "restore" {
+            return restore(args);
+        }
+        let Ok(args) = args
+            .iter()
+            .map(|arg| std::str::from_utf8(arg))
+            .collect::<Result<Vec<&str>, _>>()
+        else {
+            return Command::Err(NOT_UTF8.to_string());
+        };
+        let keyword = |arg: &str| arg.to_lowercase();
 
-        match input_lower.as_slice() {
+        match (name.as_str(), args.as_slice()) {
             // ping
-            ["ping"] => Command::Ping,
+            ("ping", []) => Command::Ping,
 
             // echo value
-            ["echo", rest @ ..] => Command::Echo(rest.join(" ")),
+            ("echo", rest) => Command::Echo(rest.join(" ")),
 
-            // set key value [px expire]
-            ["set", key, value, "px", ex] => {
-                let ex_duration = ex.parse::<u64>().map(Duration::from_millis).ok();
-                Command::Set {
+            // set key value [nx|xx] [get] [ex seconds|px ms|exat timestamp|pxat ms-timestamp|keepttl]
+            ("set", [key, value, options @ ..]) => match set_options(options) {
+                Ok((condition, expiry, get)) => Command::Set {
                     key: key.to_string(),
                     value: value.to_string(),
-                    ex: ex_duration,
+                    condition,
+                    expiry,
+                    get,
+                },
+                Err(err) => err,
+            },
+
+            // setnx key value
+            ("setnx", [key, value]) => Command::SetNx {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+
+            // setex key seconds value
+            // psetex key milliseconds value
+            ("setex" | "psetex", [key, time, value]) => {
+                let unit = if name == "setex" { "ex" } else { "px" };
+                match expire_time(time, unit, &name) {
+                    Ok(expiry) => Command::Set {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                        condition: None,
+                        expiry: Some(expiry),
+                        get: false,
+                    },
+                    Err(err) => err,
                 }
             }
-            ["set", key, value] => Command::Set {
+
+            // getset key value
+            ("getset", [key, value]) => Command::Set {
                 key: key.to_string(),
                 value: value.to_string(),
-                ex: None,
+                condition: None,
+                expiry: None,
+                get: true,
             },
 
             // get key
-            ["get", key] => Command::Get {
+            ("get", [key]) => Command::Get {
                 key: key.to_string(),
             },
 
-            // info
-            ["info", _rest @ ..] => Command::Info,
+            // del|unlink key [key ...]
+            ("del" | "unlink", keys) if !keys.is_empty() => Command::Del {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+                lazy: name == "unlink",
+            },
 
-            ["replconf", "listening-port", port] => {
-                Command::Replconf(Replconf::ListeningPort(port.to_string()))
+            // expire|pexpire key time [nx|xx|gt|lt]
+            // expireat|pexpireat key unix-time [nx|xx|gt|lt]
+            ("expire" | "pexpire" | "expireat" | "pexpireat", [key, time, options @ ..]) => {
+                match expire_options(&name, time, options) {
+                    Ok((at, flags)) => Command::Expire {
+                        key: key.to_string(),
+                        at,
+                        flags,
+                    },
+                    Err(err) => err,
+                }
             }
-            ["replconf", "capa", val] => Command::Replconf(Replconf::Capa(val.to_string())),
-            ["replconf", "getack", val] => Command::Replconf(Replconf::GetAck(val.to_string())),
-            ["replconf", "ack", val] => Command::Replconf(Replconf::Ack(val.to_string())),
 
-            ["psync", _rest @ ..] => Command::Psync,
+            // keys pattern
+            ("keys", [pattern]) => Command::Keys {
+                pattern: pattern.to_string(),
+            },
+
+            // type key
+            ("type", [key]) => Command::Type {
+                key: key.to_string(),
+            },
 
-            ["wait", replicas, timeout] => {
-                Command::Wait(replicas.parse().unwrap(), timeout.parse().unwrap())
+            // object encoding|refcount|idletime|freq key
+            ("object", [subcommand, key]) => {
+                let field = match keyword(subcommand).as_str() {
+                    "encoding" => ObjectField::Encoding,
+                    "refcount" => ObjectField::RefCount,
+                    "idletime" => ObjectField::IdleTime,
+                    "freq" => ObjectField::Freq,
+                    _ => {
+                        return Command::Err(format!(
+                            "ERR unknown subcommand '{subcommand}'. Try OBJECT HELP."
+                        ))
+                    }
+                };
+                Command::Object {
+                    field,
+                    key: key.to_string(),
+                }
             }
+            ("object", [subcommand, ..]) => Command::Err(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try OBJECT HELP."
+            )),
 
-            _ => Command::Err,
-        }
+            // dump key
+            ("dump", [key]) => Command::Dump {
+                key: key.to_string(),
+            },
+
+            // sort key [by pattern] [limit offset count] [get pattern ...] [asc|desc] [alpha]
+            //     [store destination]
+            ("sort", [key, options @ ..]) => match sort_options(options) {
+                Ok((options, store)) => Command::Sort {
+                    key: key.to_string(),
+                    options: Box::new(options),
+                    store,
+                },
+                Err(err) => err,
+            },
+
+            // copy source destination [db index] [replace]
+            ("copy", [source, destination, options @ ..]) => {
+                let mut replace = false;
+                let mut options = options;
+                while let Some((option, rest)) = options.split_first() {
+                    options = match (option.to_lowercase().as_str(), rest) {
+                        ("replace", rest) => {
+                            replace = true;
+                            rest
+                        }
+                        // only database 0 is served
+                        ("db", [index, rest @ ..]) => match index.parse::<i64>() {
+                            Ok(0) => rest,
+                            Ok(_) => {
+                                return Command::Err("ERR DB index is out of range".to_string())
+                            }
+                            Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+                        },
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    };
+                }
+                if source == destination {
+                    return Command::Err(
+                        "ERR source and destination objects are the same".to_string(),
+                    );
+                }
+                Command::Copy {
+                    source: source.to_string(),
+                    destination: destination.to_string(),
+                    replace,
+                }
+            }
+
+            // scan cursor [match pattern] [count count] [type type]
+            ("scan", [cursor, options @ ..]) => {
+                let mut type_name = None;
+                let mut rest = vec![];
+                for option in options.chunks(2) {
+                    match option {
+                        [name, value] if name.eq_ignore_ascii_case("type") => {
+                            type_name = Some(value.to_lowercase())
+                        }
+                        _ => rest.extend_from_slice(option),
+                    }
+                }
+                match scan_options(cursor, &rest) {
+                    Ok((cursor, pattern, count)) => Command::Scan {
+                        cursor,
+                        pattern,
+                        count,
+                        type_name,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // exists key [key ...]
+            ("exists", keys) if !keys.is_empty() => Command::Exists {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+
+            // touch key [key ...]
+            ("touch", keys) if !keys.is_empty() => Command::Touch {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+
+            // incr|decr key
+            ("incr" | "decr", [key]) => Command::Incr {
+                key: key.to_string(),
+                delta: if name == "incr" { 1 } else { -1 },
+            },
+
+            // incrby|decrby key delta
+            ("incrby" | "decrby", [key, delta]) => {
+                let Ok(delta) = delta.parse::<i64>() else {
+                    return Command::Err(NOT_AN_INTEGER.to_string());
+                };
+                let delta = if name == "incrby" {
+                    delta
+                } else {
+                    match delta.checked_neg() {
+                        Some(delta) => delta,
+                        None => return Command::Err("ERR decrement would overflow".to_string()),
+                    }
+                };
+                Command::Incr {
+                    key: key.to_string(),
+                    delta,
+                }
+            }
+
+            // incrbyfloat key delta
+            ("incrbyfloat", [key, delta]) => match delta.parse::<f64>() {
+                Ok(delta) if delta.is_finite() => Command::IncrByFloat {
+                    key: key.to_string(),
+                    delta,
+                },
+                _ => Command::Err(NOT_A_FLOAT.to_string()),
+            },
+
+            // append key value
+            ("append", [key, value]) => Command::Append {
+                key: key.to_string(),
+                value: value.to_string(),
+            },
+
+            // strlen key
+            ("strlen", [key]) => Command::StrLen {
+                key: key.to_string(),
+            },
+
+            // mget key [key ...]
+            ("mget", keys) if !keys.is_empty() => Command::MGet {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+
+            // mset|msetnx key value [key value ...]
+            ("mset" | "msetnx", pairs) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+                Command::MSet {
+                    pairs: pairs
+                        .chunks_exact(2)
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect(),
+                    if_absent: name == "msetnx",
+                }
+            }
+
+            // lpush|rpush key value [value ...]
+            ("lpush" | "rpush", [key, values @ ..]) if !values.is_empty() => Command::Push {
+                key: key.to_string(),
+                values: values.iter().map(|value| value.to_string()).collect(),
+                end: if name == "lpush" {
+                    End::Left
+                } else {
+                    End::Right
+                },
+            },
+
+            // lpop|rpop key [count]
+            ("lpop" | "rpop", [key, rest @ ..]) if rest.len() <= 1 => {
+                let count = match rest.first().map(|count| count.parse::<usize>()) {
+                    None => None,
+                    Some(Ok(count)) => Some(count),
+                    Some(Err(_)) => {
+                        return Command::Err(
+                            "ERR value is out of range, must be positive".to_string(),
+                        )
+                    }
+                };
+                Command::Pop {
+                    key: key.to_string(),
+                    count,
+                    end: if name == "lpop" {
+                        End::Left
+                    } else {
+                        End::Right
+                    },
+                }
+            }
+
+            // blpop|brpop key [key ...] timeout
+            ("blpop" | "brpop", [keys @ .., timeout]) if !keys.is_empty() => {
+                let timeout = match block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return err,
+                };
+                Command::BlockingPop {
+                    keys: keys.iter().map(|key| key.to_string()).collect(),
+                    timeout,
+                    end: if name == "blpop" {
+                        End::Left
+                    } else {
+                        End::Right
+                    },
+                    count: None,
+                }
+            }
+
+            // lmpop numkeys key [key ...] left|right [count count]
+            ("lmpop", args) if !args.is_empty() => match mpop(args) {
+                Ok((keys, end, count)) => Command::MPop { keys, end, count },
+                Err(err) => err,
+            },
+
+            // blmpop timeout numkeys key [key ...] left|right [count count]
+            ("blmpop", [timeout, args @ ..]) if !args.is_empty() => {
+                let timeout = match block_timeout(timeout) {
+                    Ok(timeout) => timeout,
+                    Err(err) => return err,
+                };
+                match mpop(args) {
+                    Ok((keys, end, count)) => Command::BlockingPop {
+                        keys,
+                        timeout,
+                        end,
+                        count: Some(count),
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // lmove source destination left|right left|right
+            ("lmove", [source, destination, from, to]) => match (end(from), end(to)) {
+                (Some(from), Some(to)) => Command::Move {
+                    source: source.to_string(),
+                    destination: destination.to_string(),
+                    from,
+                    to,
+                },
+                _ => Command::Err(SYNTAX_ERROR.to_string()),
+            },
+            ("rpoplpush", [source, destination]) => Command::Move {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                from: End::Right,
+                to: End::Left,
+            },
+
+            // blmove source destination left|right left|right timeout
+            ("blmove", [source, destination, from, to, timeout]) => {
+                let (Some(from), Some(to)) = (end(from), end(to)) else {
+                    return Command::Err(SYNTAX_ERROR.to_string());
+                };
+                match block_timeout(timeout) {
+                    Ok(timeout) => Command::BlockingMove {
+                        source: source.to_string(),
+                        destination: destination.to_string(),
+                        from,
+                        to,
+                        timeout,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // lrange key start stop
+            ("lrange", [key, start, stop]) => match (start.parse(), stop.parse()) {
+                (Ok(start), Ok(stop)) => Command::LRange {
+                    key: key.to_string(),
+                    start,
+                    stop,
+                },
+                _ => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // llen key
+            ("llen", [key]) => Command::LLen {
+                key: key.to_string(),
+            },
+
+            // lpos key element [rank rank] [count num] [maxlen len]
+            ("lpos", [key, element, options @ ..]) => {
+                let mut rank = 1;
+                let mut count = None;
+                let mut maxlen = 0;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    let Some(Ok(value)) = options.next().map(|value| value.parse::<i64>()) else {
+                        return Command::Err(SYNTAX_ERROR.to_string());
+                    };
+                    match keyword(option).as_str() {
+                        "rank" if value == 0 => {
+                            return Command::Err(
+                                "ERR RANK can't be zero: use 1 to start from the first match, \
+                                 2 from the second ... or use negative to start from the end of the list"
+                                    .to_string(),
+                            )
+                        }
+                        "rank" => rank = value,
+                        "count" | "maxlen" if value < 0 => {
+                            return Command::Err(format!(
+                                "ERR {} can't be negative",
+                                option.to_uppercase()
+                            ))
+                        }
+                        "count" => count = Some(value as usize),
+                        "maxlen" => maxlen = value as usize,
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    }
+                }
+                Command::LPos {
+                    key: key.to_string(),
+                    element: element.to_string(),
+                    rank,
+                    count,
+                    maxlen,
+                }
+            }
+
+            // linsert key before|after pivot element
+            ("linsert", [key, position, pivot, element]) => {
+                let before = match keyword(position).as_str() {
+                    "before" => true,
+                    "after" => false,
+                    _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                Command::LInsert {
+                    key: key.to_string(),
+                    before,
+                    pivot: pivot.to_string(),
+                    element: element.to_string(),
+                }
+            }
+
+            // lset key index element
+            ("lset", [key, index, element]) => match index.parse() {
+                Ok(index) => Command::LSet {
+                    key: key.to_string(),
+                    index,
+                    element: element.to_string(),
+                },
+                Err(_) => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // lrem key count element
+            ("lrem", [key, count, element]) => match count.parse() {
+                Ok(count) => Command::LRem {
+                    key: key.to_string(),
+                    count,
+                    element: element.to_string(),
+                },
+                Err(_) => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // ltrim key start stop
+            ("ltrim", [key, start, stop]) => match (start.parse(), stop.parse()) {
+                (Ok(start), Ok(stop)) => Command::LTrim {
+                    key: key.to_string(),
+                    start,
+                    stop,
+                },
+                _ => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // hset key field value [field value ...]
+            ("hset", [key, pairs @ ..]) if !pairs.is_empty() => {
+                let chunks = pairs.chunks_exact(2);
+                if !chunks.remainder().is_empty() {
+                    return Command::Err(
+                        "ERR wrong number of arguments for 'hset' command".to_string(),
+                    );
+                }
+                Command::HSet {
+                    key: key.to_string(),
+                    pairs: chunks
+                        .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                        .collect(),
+                }
+            }
+
+            // hsetnx key field value
+            ("hsetnx", [key, field, value]) => Command::HSetNx {
+                key: key.to_string(),
+                field: field.to_string(),
+                value: value.to_string(),
+            },
+
+            // hincrby key field increment
+            ("hincrby", [key, field, delta]) => match delta.parse() {
+                Ok(delta) => Command::HIncrBy {
+                    key: key.to_string(),
+                    field: field.to_string(),
+                    delta,
+                },
+                Err(_) => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // hincrbyfloat key field increment
+            ("hincrbyfloat", [key, field, delta]) => match delta.parse::<f64>() {
+                Ok(delta) if delta.is_finite() => Command::HIncrByFloat {
+                    key: key.to_string(),
+                    field: field.to_string(),
+                    delta,
+                },
+                _ => Command::Err(NOT_A_FLOAT.to_string()),
+            },
+
+            // hget key field
+            ("hget", [key, field]) => Command::HGet {
+                key: key.to_string(),
+                field: field.to_string(),
+            },
+
+            // hmget|hdel key field [field ...]
+            ("hmget", [key, fields @ ..]) if !fields.is_empty() => Command::HMGet {
+                key: key.to_string(),
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            },
+            ("hdel", [key, fields @ ..]) if !fields.is_empty() => Command::HDel {
+                key: key.to_string(),
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            },
+
+            // hgetall key
+            ("hgetall", [key]) => Command::HGetAll {
+                key: key.to_string(),
+            },
+
+            // hexists key field
+            ("hexists", [key, field]) => Command::HExists {
+                key: key.to_string(),
+                field: field.to_string(),
+            },
+
+            // hlen|hkeys|hvals key
+            ("hlen", [key]) => Command::HLen {
+                key: key.to_string(),
+            },
+            ("hkeys", [key]) => Command::HKeys {
+                key: key.to_string(),
+            },
+            ("hvals", [key]) => Command::HVals {
+                key: key.to_string(),
+            },
+
+            // hscan key cursor [match pattern] [count count]
+            ("hscan", [key, cursor, options @ ..]) => match scan_options(cursor, options) {
+                Ok((cursor, pattern, count)) => Command::HScan {
+                    key: key.to_string(),
+                    cursor,
+                    pattern,
+                    count,
+                },
+                Err(err) => err,
+            },
+
+            // hrandfield key [count [withvalues]]
+            ("hrandfield", [key]) => Command::HRandField {
+                key: key.to_string(),
+                count: None,
+                with_values: false,
+            },
+            ("hrandfield", [key, count, rest @ ..]) if rest.len() <= 1 => {
+                let with_values = match rest.first() {
+                    None => false,
+                    Some(option) if keyword(option) == "withvalues" => true,
+                    Some(_) => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                match random_count(count) {
+                    Ok(count) => Command::HRandField {
+                        key: key.to_string(),
+                        count: Some(count),
+                        with_values,
+                    },
+                    Err(err) => Command::Err(err.to_string()),
+                }
+            }
+
+            // sadd|srem key member [member ...]
+            ("sadd", [key, members @ ..]) if !members.is_empty() => Command::SAdd {
+                key: key.to_string(),
+                members: members.iter().map(|member| member.to_string()).collect(),
+            },
+            ("srem", [key, members @ ..]) if !members.is_empty() => Command::SRem {
+                key: key.to_string(),
+                members: members.iter().map(|member| member.to_string()).collect(),
+            },
+
+            // smembers|scard key
+            ("smembers", [key]) => Command::SMembers {
+                key: key.to_string(),
+            },
+            ("scard", [key]) => Command::SCard {
+                key: key.to_string(),
+            },
+
+            // sismember key member
+            ("sismember", [key, member]) => Command::SIsMember {
+                key: key.to_string(),
+                member: member.to_string(),
+            },
+
+            // spop key [count]
+            ("spop", [key, rest @ ..]) if rest.len() <= 1 => {
+                let count = match rest.first().map(|count| count.parse::<usize>()) {
+                    None => None,
+                    Some(Ok(count)) => Some(count),
+                    Some(Err(_)) => {
+                        return Command::Err(
+                            "ERR value is out of range, must be positive".to_string(),
+                        )
+                    }
+                };
+                Command::SPop {
+                    key: key.to_string(),
+                    count,
+                }
+            }
+
+            // srandmember key [count]
+            ("srandmember", [key, rest @ ..]) if rest.len() <= 1 => {
+                let count = match rest.first().map(|count| random_count(count)) {
+                    None => None,
+                    Some(Ok(count)) => Some(count),
+                    Some(Err(err)) => return Command::Err(err.to_string()),
+                };
+                Command::SRandMember {
+                    key: key.to_string(),
+                    count,
+                }
+            }
+
+            // smove source destination member
+            ("smove", [source, destination, member]) => Command::SMove {
+                source: source.to_string(),
+                destination: destination.to_string(),
+                member: member.to_string(),
+            },
+
+            // smismember key member [member ...]
+            ("smismember", [key, members @ ..]) if !members.is_empty() => Command::SMIsMember {
+                key: key.to_string(),
+                members: members.iter().map(|member| member.to_string()).collect(),
+            },
+
+            // sintercard numkeys key [key ...] [limit limit]
+            ("sintercard", [numkeys, rest @ ..]) => {
+                let numkeys = match numkeys.parse::<i64>() {
+                    Ok(numkeys) if numkeys > 0 => numkeys as usize,
+                    Ok(_) => {
+                        return Command::Err("ERR numkeys should be greater than 0".to_string())
+                    }
+                    Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+                };
+                let Some(keys) = rest.get(..numkeys) else {
+                    return Command::Err(
+                        "ERR Number of keys can't be greater than number of args".to_string(),
+                    );
+                };
+                let limit = match &rest[numkeys..] {
+                    [] => 0,
+                    [option, limit] if keyword(option) == "limit" => match limit.parse::<i64>() {
+                        Ok(limit) if limit >= 0 => limit as usize,
+                        Ok(_) => return Command::Err("ERR LIMIT can't be negative".to_string()),
+                        Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+                    },
+                    _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                Command::SInterCard {
+                    keys: keys.iter().map(|key| key.to_string()).collect(),
+                    limit,
+                }
+            }
+
+            // sinter|sunion|sdiff key [key ...]
+            ("sinter" | "sunion" | "sdiff", keys) if !keys.is_empty() => Command::SetOp {
+                op: set_op(&name),
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+
+            // sinterstore|sunionstore|sdiffstore destination key [key ...]
+            ("sinterstore" | "sunionstore" | "sdiffstore", [destination, keys @ ..])
+                if !keys.is_empty() =>
+            {
+                Command::SetOpStore {
+                    op: set_op(name.trim_end_matches("store")),
+                    destination: destination.to_string(),
+                    keys: keys.iter().map(|key| key.to_string()).collect(),
+                }
+            }
+
+            // zadd key [nx|xx] [gt|lt] [ch] [incr] score member [score member ...]
+            ("zadd", [key, rest @ ..]) => {
+                let mut flags = ZAddFlags::default();
+                let mut rest = rest;
+                while let Some((option, tail)) = rest.split_first() {
+                    match keyword(option).as_str() {
+                        "nx" => flags.nx = true,
+                        "xx" => flags.xx = true,
+                        "gt" => flags.gt = true,
+                        "lt" => flags.lt = true,
+                        "ch" => flags.ch = true,
+                        "incr" => flags.incr = true,
+                        _ => break,
+                    }
+                    rest = tail;
+                }
+                let pairs = rest.chunks_exact(2);
+                if rest.is_empty() || !pairs.remainder().is_empty() {
+                    return Command::Err(SYNTAX_ERROR.to_string());
+                }
+                if flags.nx && flags.xx {
+                    return Command::Err(
+                        "ERR XX and NX options at the same time are not compatible".to_string(),
+                    );
+                }
+                if (flags.gt && flags.lt) || (flags.nx && (flags.gt || flags.lt)) {
+                    return Command::Err(
+                        "ERR GT, LT, and/or NX options at the same time are not compatible"
+                            .to_string(),
+                    );
+                }
+                if flags.incr && rest.len() > 2 {
+                    return Command::Err(
+                        "ERR INCR option supports a single increment-element pair".to_string(),
+                    );
+                }
+                let mut parsed = vec![];
+                for pair in pairs {
+                    let Some(score) = score(pair[0]) else {
+                        return Command::Err(NOT_A_FLOAT.to_string());
+                    };
+                    parsed.push((score, pair[1].to_string()));
+                }
+                Command::ZAdd {
+                    key: key.to_string(),
+                    flags,
+                    pairs: parsed,
+                }
+            }
+
+            // zscore key member
+            ("zscore", [key, member]) => Command::ZScore {
+                key: key.to_string(),
+                member: member.to_string(),
+            },
+
+            // zcard key
+            // zmscore key member [member ...]
+            ("zmscore", [key, members @ ..]) if !members.is_empty() => Command::ZMScore {
+                key: key.to_string(),
+                members: members.iter().map(|member| member.to_string()).collect(),
+            },
+
+            ("zcard", [key]) => Command::ZCard {
+                key: key.to_string(),
+            },
+
+            // zrandmember key [count [withscores]]
+            ("zrandmember", [key]) => Command::ZRandMember {
+                key: key.to_string(),
+                count: None,
+                with_scores: false,
+            },
+            ("zrandmember", [key, count, rest @ ..]) if rest.len() <= 1 => {
+                let with_scores = match rest.first() {
+                    None => false,
+                    Some(option) if keyword(option) == "withscores" => true,
+                    Some(_) => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                match random_count(count) {
+                    Ok(count) => Command::ZRandMember {
+                        key: key.to_string(),
+                        count: Some(count),
+                        with_scores,
+                    },
+                    Err(err) => Command::Err(err.to_string()),
+                }
+            }
+
+            // zincrby key increment member
+            ("zincrby", [key, delta, member]) => match score(delta) {
+                Some(delta) => Command::ZIncrBy {
+                    key: key.to_string(),
+                    delta,
+                    member: member.to_string(),
+                },
+                None => Command::Err(NOT_A_FLOAT.to_string()),
+            },
+
+            // zrank|zrevrank key member [withscore]
+            ("zrank" | "zrevrank", [key, member, rest @ ..]) if rest.len() <= 1 => {
+                let with_score = match rest.first() {
+                    None => false,
+                    Some(option) if keyword(option) == "withscore" => true,
+                    Some(_) => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                Command::ZRank {
+                    key: key.to_string(),
+                    member: member.to_string(),
+                    rev: name == "zrevrank",
+                    with_score,
+                }
+            }
+
+            // zrange key start stop [rev] [withscores]
+            ("zrange", [key, start, stop, options @ ..]) => {
+                let (mut rev, mut with_scores) = (false, false);
+                for option in options {
+                    match keyword(option).as_str() {
+                        "rev" => rev = true,
+                        "withscores" => with_scores = true,
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    }
+                }
+                match (start.parse(), stop.parse()) {
+                    (Ok(start), Ok(stop)) => Command::ZRange {
+                        key: key.to_string(),
+                        start,
+                        stop,
+                        rev,
+                        with_scores,
+                    },
+                    _ => Command::Err(NOT_AN_INTEGER.to_string()),
+                }
+            }
+
+            // zrangebyscore key min max [withscores] [limit offset count]
+            // zrevrangebyscore key max min [withscores] [limit offset count]
+            ("zrangebyscore" | "zrevrangebyscore", [key, first, second, options @ ..]) => {
+                let rev = name == "zrevrangebyscore";
+                let (min, max) = if rev {
+                    (second, first)
+                } else {
+                    (first, second)
+                };
+                let (Some(min), Some(max)) = (score_bound(min), score_bound(max)) else {
+                    return Command::Err("ERR min or max is not a float".to_string());
+                };
+                match range_options(options, true) {
+                    Ok((with_scores, offset, count)) => Command::ZRangeByScore {
+                        key: key.to_string(),
+                        min,
+                        max,
+                        rev,
+                        with_scores,
+                        offset,
+                        count,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // zrangebylex key min max [limit offset count]
+            // zrevrangebylex key max min [limit offset count]
+            ("zrangebylex" | "zrevrangebylex", [key, first, second, options @ ..]) => {
+                let rev = name == "zrevrangebylex";
+                let (min, max) = if rev {
+                    (second, first)
+                } else {
+                    (first, second)
+                };
+                let (Some(min), Some(max)) = (lex_bound(min), lex_bound(max)) else {
+                    return Command::Err("ERR min or max not valid string range item".to_string());
+                };
+                match range_options(options, false) {
+                    Ok((_, offset, count)) => Command::ZRangeByLex {
+                        key: key.to_string(),
+                        min,
+                        max,
+                        rev,
+                        offset,
+                        count,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // zrangestore destination source min max [byscore|bylex] [rev]
+            //     [limit offset count]
+            ("zrangestore", [destination, source, first, second, options @ ..]) => {
+                match zrangestore_options(first, second, options) {
+                    Ok((range, rev, offset, count)) => Command::ZRangeStore {
+                        destination: destination.to_string(),
+                        source: source.to_string(),
+                        range: Box::new(range),
+                        rev,
+                        offset,
+                        count,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // zcount key min max
+            ("zcount", [key, min, max]) => match (score_bound(min), score_bound(max)) {
+                (Some(min), Some(max)) => Command::ZCount {
+                    key: key.to_string(),
+                    range: ZRange::Score { min, max },
+                },
+                _ => Command::Err("ERR min or max is not a float".to_string()),
+            },
+
+            // zlexcount key min max
+            ("zlexcount", [key, min, max]) => match (lex_bound(min), lex_bound(max)) {
+                (Some(min), Some(max)) => Command::ZCount {
+                    key: key.to_string(),
+                    range: ZRange::Lex { min, max },
+                },
+                _ => Command::Err("ERR min or max not valid string range item".to_string()),
+            },
+
+            // zremrangebyrank key start stop
+            ("zremrangebyrank", [key, start, stop]) => match (start.parse(), stop.parse()) {
+                (Ok(start), Ok(stop)) => Command::ZRemRange {
+                    key: key.to_string(),
+                    range: ZRange::Rank { start, stop },
+                },
+                _ => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // zremrangebyscore key min max
+            ("zremrangebyscore", [key, min, max]) => match (score_bound(min), score_bound(max)) {
+                (Some(min), Some(max)) => Command::ZRemRange {
+                    key: key.to_string(),
+                    range: ZRange::Score { min, max },
+                },
+                _ => Command::Err("ERR min or max is not a float".to_string()),
+            },
+
+            // zremrangebylex key min max
+            ("zremrangebylex", [key, min, max]) => match (lex_bound(min), lex_bound(max)) {
+                (Some(min), Some(max)) => Command::ZRemRange {
+                    key: key.to_string(),
+                    range: ZRange::Lex { min, max },
+                },
+                _ => Command::Err("ERR min or max not valid string range item".to_string()),
+            },
+
+            // zunion|zinter numkeys key [key ...] [weights weight ...]
+            //     [aggregate sum|min|max] [withscores]
+            // zdiff numkeys key [key ...] [withscores]
+            ("zunion" | "zinter" | "zdiff", [numkeys, rest @ ..]) => {
+                match zcombine_options(&name, numkeys, rest, name != "zdiff", true) {
+                    Ok((keys, weights, aggregate, with_scores)) => Command::ZCombine {
+                        op: set_op(&name),
+                        keys,
+                        weights,
+                        aggregate,
+                        with_scores,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // zunionstore|zinterstore destination numkeys key [key ...]
+            //     [weights weight ...] [aggregate sum|min|max]
+            // zdiffstore destination numkeys key [key ...]
+            ("zunionstore" | "zinterstore" | "zdiffstore", [destination, numkeys, rest @ ..]) => {
+                let weighted = name != "zdiffstore";
+                match zcombine_options(&name, numkeys, rest, weighted, false) {
+                    Ok((keys, weights, aggregate, _)) => Command::ZCombineStore {
+                        op: set_op(name.trim_end_matches("store")),
+                        destination: destination.to_string(),
+                        keys,
+                        weights,
+                        aggregate,
+                    },
+                    Err(err) => err,
+                }
+            }
+
+            // setbit key offset value
+            ("setbit", [key, offset, bit]) => {
+                let Some(offset) = bit_offset(offset) else {
+                    return Command::Err(BAD_BIT_OFFSET.to_string());
+                };
+                let bit = match *bit {
+                    "0" => false,
+                    "1" => true,
+                    _ => {
+                        return Command::Err(
+                            "ERR bit is not an integer or out of range".to_string(),
+                        )
+                    }
+                };
+                Command::SetBit {
+                    key: key.to_string(),
+                    offset,
+                    bit,
+                }
+            }
+
+            // getbit key offset
+            ("getbit", [key, offset]) => match bit_offset(offset) {
+                Some(offset) => Command::GetBit {
+                    key: key.to_string(),
+                    offset,
+                },
+                None => Command::Err(BAD_BIT_OFFSET.to_string()),
+            },
+
+            // bitcount key [start end [byte|bit]]
+            ("bitcount", [key]) => Command::BitCount {
+                key: key.to_string(),
+                range: None,
+                bits: false,
+            },
+            ("bitcount", [key, start, end, unit @ ..]) if unit.len() <= 1 => {
+                let bits = match unit.first().map(|unit| keyword(unit)).as_deref() {
+                    None | Some("byte") => false,
+                    Some("bit") => true,
+                    Some(_) => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                match (start.parse(), end.parse()) {
+                    (Ok(start), Ok(end)) => Command::BitCount {
+                        key: key.to_string(),
+                        range: Some((start, end)),
+                        bits,
+                    },
+                    _ => Command::Err(NOT_AN_INTEGER.to_string()),
+                }
+            }
+            ("bitcount", _) => Command::Err(SYNTAX_ERROR.to_string()),
+
+            // bitfield key [get type offset] [set type offset value]
+            //   [incrby type offset increment] [overflow wrap|sat|fail] ...
+            ("bitfield", [key, rest @ ..]) => match bitfield_ops(rest) {
+                Ok(ops) => Command::BitField {
+                    key: key.to_string(),
+                    ops,
+                },
+                Err(err) => err,
+            },
+
+            // geoadd key [nx|xx] [ch] longitude latitude member [longitude latitude member ...]
+            // stored as zadd with the geohashes as scores, like Redis does
+            ("geoadd", [key, rest @ ..]) => {
+                let mut flags = ZAddFlags::default();
+                let mut rest = rest;
+                while let Some((option, tail)) = rest.split_first() {
+                    match keyword(option).as_str() {
+                        "nx" => flags.nx = true,
+                        "xx" => flags.xx = true,
+                        "ch" => flags.ch = true,
+                        _ => break,
+                    }
+                    rest = tail;
+                }
+                let triples = rest.chunks_exact(3);
+                if rest.is_empty() || !triples.remainder().is_empty() {
+                    return Command::Err(SYNTAX_ERROR.to_string());
+                }
+                if flags.nx && flags.xx {
+                    return Command::Err(
+                        "ERR XX and NX options at the same time are not compatible".to_string(),
+                    );
+                }
+                let mut pairs = vec![];
+                for triple in triples {
+                    let (lon, lat) = match lon_lat(triple[0], triple[1]) {
+                        Ok(point) => point,
+                        Err(err) => return err,
+                    };
+                    pairs.push((geohash(lon, lat) as f64, triple[2].to_string()));
+                }
+                Command::ZAdd {
+                    key: key.to_string(),
+                    flags,
+                    pairs,
+                }
+            }
+
+            // geopos key member [member ...]
+            ("geopos", [key, members @ ..]) => Command::GeoPos {
+                key: key.to_string(),
+                members: members.iter().map(|member| member.to_string()).collect(),
+            },
+
+            // geodist key member1 member2 [m|km|ft|mi]
+            ("geodist", [key, from, to, unit @ ..]) if unit.len() <= 1 => {
+                match unit.first().map_or(Some(1.0), |unit| geo_unit(unit)) {
+                    Some(unit) => Command::GeoDist {
+                        key: key.to_string(),
+                        from: from.to_string(),
+                        to: to.to_string(),
+                        unit,
+                    },
+                    None => Command::Err(BAD_GEO_UNIT.to_string()),
+                }
+            }
+            ("geodist", _) => Command::Err(SYNTAX_ERROR.to_string()),
+
+            // geosearch key <frommember member | fromlonlat longitude latitude>
+            //   <byradius radius unit | bybox width height unit> [asc|desc]
+            //   [count count [any]] [withcoord] [withdist] [withhash]
+            ("geosearch", [key, options @ ..]) => {
+                match geosearch(GeoCommand::Search, key, None, None, options) {
+                    Ok(command) | Err(command) => command,
+                }
+            }
+
+            // geosearchstore destination source <frommember member |
+            //   fromlonlat longitude latitude> <byradius radius unit |
+            //   bybox width height unit> [asc|desc] [count count [any]] [storedist]
+            ("geosearchstore", [destination, source, options @ ..]) => {
+                let store = GeoCommand::SearchStore(destination.to_string());
+                match geosearch(store, source, None, None, options) {
+                    Ok(command) | Err(command) => command,
+                }
+            }
+
+            // georadius key longitude latitude radius m|km|ft|mi [withcoord]
+            //   [withdist] [withhash] [count count [any]] [asc|desc]
+            //   [store key|storedist key]
+            // georadiusbymember key member radius m|km|ft|mi ...
+            (
+                "georadius" | "georadius_ro" | "georadiusbymember" | "georadiusbymember_ro",
+                [key, rest @ ..],
+            ) => {
+                let read_only = name.ends_with("_ro");
+                let (from, rest) = match (name.starts_with("georadiusbymember"), rest) {
+                    (true, [member, rest @ ..]) => (Ok(GeoFrom::Member(member.to_string())), rest),
+                    (false, [lon, lat, rest @ ..]) => (
+                        lon_lat(lon, lat).map(|(lon, lat)| GeoFrom::LonLat(lon, lat)),
+                        rest,
+                    ),
+                    _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                let [radius, unit, options @ ..] = rest else {
+                    return Command::Err(SYNTAX_ERROR.to_string());
+                };
+                let radius = from.and_then(|from| {
+                    let unit = geo_unit(unit).ok_or_else(|| Command::Err(BAD_GEO_UNIT.to_string()))?;
+                    let radius = geo_distance(radius, unit, "ERR radius cannot be negative")?;
+                    Ok((from, (GeoShape::Radius(radius), unit)))
+                });
+                let command = match read_only {
+                    true => GeoCommand::RadiusReadOnly,
+                    false => GeoCommand::Radius,
+                };
+                match radius.and_then(|(from, shape)| {
+                    geosearch(command, key, Some(from), Some(shape), options)
+                }) {
+                    Ok(command) | Err(command) => command,
+                }
+            }
+
+            // xadd key [nomkstream] <* | ms-* | ms-seq> field value [field value ...]
+            ("xadd", [key, rest @ ..]) => {
+                let (create, rest) = match rest.split_first() {
+                    Some((option, rest)) if keyword(option) == "nomkstream" => (false, rest),
+                    _ => (true, rest),
+                };
+                let Some((id, fields)) = rest.split_first() else {
+                    return Command::Err(
+                        "ERR wrong number of arguments for 'xadd' command".to_string(),
+                    );
+                };
+                let chunks = fields.chunks_exact(2);
+                if fields.is_empty() || !chunks.remainder().is_empty() {
+                    return Command::Err(
+                        "ERR wrong number of arguments for 'xadd' command".to_string(),
+                    );
+                }
+                match id.parse() {
+                    Ok(id) => Command::XAdd {
+                        key: key.to_string(),
+                        id,
+                        fields: chunks
+                            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                            .collect(),
+                        create,
+                    },
+                    Err(_) => Command::Err(INVALID_STREAM_ID.to_string()),
+                }
+            }
+
+            // xread [count count] [block milliseconds] streams key [key ...] id [id ...]
+            ("xread", args) => {
+                let (mut count, mut block) = (usize::MAX, None);
+                let mut rest = args;
+                let streams = loop {
+                    let [option, value, more @ ..] = rest else {
+                        return Command::Err(SYNTAX_ERROR.to_string());
+                    };
+                    match keyword(option).as_str() {
+                        "streams" => break &rest[1..],
+                        // zero or less means no limit, like Redis
+                        "count" => match value.parse::<i64>() {
+                            Ok(value) if value > 0 => count = value as usize,
+                            Ok(_) => count = usize::MAX,
+                            Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+                        },
+                        "block" => match value.parse::<i64>() {
+                            Ok(value) if value < 0 => {
+                                return Command::Err("ERR timeout is negative".to_string())
+                            }
+                            Ok(value) => block = Some(Duration::from_millis(value as u64)),
+                            Err(_) => {
+                                return Command::Err(
+                                    "ERR timeout is not an integer or out of range".to_string(),
+                                )
+                            }
+                        },
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    }
+                    rest = more;
+                };
+                if streams.len() % 2 != 0 {
+                    return Command::Err(
+                        "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+                            .to_string(),
+                    );
+                }
+                let (keys, ids) = streams.split_at(streams.len() / 2);
+                let ids: Option<Vec<Option<StreamId>>> = ids
+                    .iter()
+                    .map(|id| match *id {
+                        "$" => Some(None),
+                        id => id.parse().ok().map(Some),
+                    })
+                    .collect();
+                match ids {
+                    Some(ids) => Command::XRead {
+                        keys: keys.iter().map(|key| key.to_string()).collect(),
+                        ids,
+                        count,
+                        block,
+                    },
+                    None => Command::Err(INVALID_STREAM_ID.to_string()),
+                }
+            }
+
+            // xsetid key last-id [entriesadded entries-added] [maxdeletedid max-deleted-id]
+            ("xsetid", [key, id, options @ ..]) => {
+                let Ok(id) = id.parse::<StreamId>() else {
+                    return Command::Err(INVALID_STREAM_ID.to_string());
+                };
+                let (mut entries_added, mut max_deleted_id) = (None, None);
+                for option in options.chunks(2) {
+                    let [option, value] = option else {
+                        return Command::Err(SYNTAX_ERROR.to_string());
+                    };
+                    match keyword(option).as_str() {
+                        "entriesadded" => match value.parse::<i64>() {
+                            Ok(value) if value >= 0 => entries_added = Some(value as u64),
+                            Ok(_) => {
+                                return Command::Err("ERR entries_added must be positive".to_string())
+                            }
+                            Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+                        },
+                        "maxdeletedid" => match value.parse::<StreamId>() {
+                            Ok(value) if value > id => {
+                                return Command::Err("ERR The ID specified in XSETID is smaller than the provided max_deleted_entry_id".to_string())
+                            }
+                            Ok(value) => max_deleted_id = Some(value),
+                            Err(_) => return Command::Err(INVALID_STREAM_ID.to_string()),
+                        },
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    }
+                }
+                Command::XSetId {
+                    key: key.to_string(),
+                    id,
+                    entries_added,
+                    max_deleted_id,
+                }
+            }
+
+            // xinfo stream key
+            ("xinfo", [subcommand, key]) if keyword(subcommand) == "stream" => {
+                Command::XInfoStream {
+                    key: key.to_string(),
+                }
+            }
+            ("xinfo", [subcommand, ..]) => Command::Err(format!(
+                "ERR unknown subcommand '{subcommand}'. Try XINFO HELP."
+            )),
+
+            // info
+            ("info", _rest) => Command::Info,
+
+            ("replconf", [option, val]) => match keyword(option).as_str() {
+                "listening-port" => Command::Replconf(Replconf::ListeningPort(val.to_string())),
+                "capa" => Command::Replconf(Replconf::Capa(val.to_string())),
+                "getack" => Command::Replconf(Replconf::GetAck(val.to_string())),
+                "ack" => Command::Replconf(Replconf::Ack(val.to_string())),
+                _ => Command::Err(SYNTAX_ERROR.to_string()),
+            },
+
+            // psync replid offset, with `? -1` asking for a full resync
+            ("psync", [replid, offset]) => match offset.parse() {
+                Ok(offset) => Command::Psync {
+                    replid: replid.to_string(),
+                    offset,
+                },
+                Err(_) => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+            ("sync", []) => Command::Sync,
+
+            ("wait", [replicas, timeout]) => match (replicas.parse(), timeout.parse()) {
+                (Ok(replicas), Ok(timeout)) => Command::Wait(replicas, timeout),
+                _ => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            // export filename [json|csv]
+            ("export", [filename, rest @ ..]) => {
+                let format = match rest.first().map(|arg| keyword(arg)).as_deref() {
+                    None | Some("json") => Format::Json,
+                    Some("csv") if rest.len() == 1 => Format::Csv,
+                    _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                Command::Export {
+                    filename: filename.to_string(),
+                    format,
+                }
+            }
+
+            // import filename|redis://... [skip|replace]
+            ("import", [source, rest @ ..]) => {
+                let conflict = match rest.first().map(|arg| keyword(arg)).as_deref() {
+                    None | Some("skip") => Conflict::Skip,
+                    Some("replace") if rest.len() == 1 => Conflict::Replace,
+                    _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                };
+                let source = if source.starts_with("redis://") {
+                    match Remote::parse(source) {
+                        Some(remote) => Source::Redis(remote),
+                        None => return Command::Err("ERR invalid redis URI".to_string()),
+                    }
+                } else {
+                    Source::File(source.to_string())
+                };
+                Command::Import { source, conflict }
+            }
+
+            // keystats [samples count]
+            ("keystats", []) => Command::KeyStats {
+                samples: usize::MAX,
+            },
+            ("keystats", [option, count]) if keyword(option) == "samples" => match count.parse() {
+                Ok(samples) => Command::KeyStats { samples },
+                Err(_) => Command::Err(NOT_AN_INTEGER.to_string()),
+            },
+
+            ("save", []) => Command::Save,
+            // bgsave [schedule], which only matters while a rewrite runs
+            ("bgsave", []) => Command::BgSave,
+            ("bgsave", [option]) if keyword(option) == "schedule" => Command::BgSave,
+            ("lastsave", []) => Command::LastSave,
+            ("bgrewriteaof", []) => Command::BgRewriteAof,
+
+            // config get pattern | config set name value
+            ("config", [subcommand, pattern]) if keyword(subcommand) == "get" => {
+                Command::ConfigGet {
+                    pattern: pattern.to_string(),
+                }
+            }
+            ("config", [subcommand, name, value]) if keyword(subcommand) == "set" => {
+                Command::ConfigSet {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                }
+            }
+            // auth [username] password
+            ("auth", [password]) => Command::Auth {
+                username: None,
+                password: password.to_string(),
+            },
+            ("auth", [username, password]) => Command::Auth {
+                username: Some(username.to_string()),
+                password: password.to_string(),
+            },
+
+            // replicaof host port | replicaof no one
+            ("replicaof" | "slaveof", [host, port])
+                if keyword(host) == "no" && keyword(port) == "one" =>
+            {
+                Command::ReplicaOf(None)
+            }
+            ("replicaof" | "slaveof", [host, port]) => match port.parse::<u16>() {
+                Ok(_) => Command::ReplicaOf(Some((host.to_string(), port.to_string()))),
+                Err(_) => Command::Err("ERR Invalid master port".to_string()),
+            },
+
+            // shutdown [nosave|save] [now] [force]
+            ("shutdown", options) => {
+                let (mut save, mut now, mut force) = (None, false, false);
+                for option in options {
+                    match keyword(option).as_str() {
+                        "nosave" if save.is_none() => save = Some(false),
+                        "save" if save.is_none() => save = Some(true),
+                        "now" => now = true,
+                        "force" => force = true,
+                        _ => return Command::Err(SYNTAX_ERROR.to_string()),
+                    }
+                }
+                Command::Shutdown { save, now, force }
+            }
+
+            ("config", [subcommand, ..]) => Command::Err(format!(
+                "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try CONFIG HELP."
+            )),
+
+            (name, _) if COMMANDS.contains(&name) => Command::Err(format!(
+                "ERR wrong number of arguments for '{name}' command"
+            )),
+            _ => Command::Err(format!("ERR unknown command '{name}'")),
+        }
+    }
+
+    /// Whether the command may modify the dataset, and so must reach the
+    /// replicas, some in the form of the change it made, and is refused by
+    /// them when a client sends it.
+    pub fn is_write(&self) -> bool {
+        if let Command::BitField { ops, .. } = self {
+            return ops.iter().any(|op| !matches!(op, BitFieldOp::Get(_)));
+        }
+        matches!(
+            self,
+            Command::Set { .. }
+                | Command::SetNx { .. }
+                | Command::Del { .. }
+                | Command::Expire { .. }
+                | Command::Copy { .. }
+                | Command::Restore { .. }
+                | Command::Sort { store: Some(_), .. }
+                | Command::Incr { .. }
+                | Command::IncrByFloat { .. }
+                | Command::Append { .. }
+                | Command::MSet { .. }
+                | Command::Push { .. }
+                | Command::Pop { .. }
+                | Command::MPop { .. }
+                | Command::BlockingPop { .. }
+                | Command::LInsert { .. }
+                | Command::LSet { .. }
+                | Command::LRem { .. }
+                | Command::LTrim { .. }
+                | Command::Move { .. }
+                | Command::BlockingMove { .. }
+                | Command::HSet { .. }
+                | Command::HSetNx { .. }
+                | Command::HIncrBy { .. }
+                | Command::HIncrByFloat { .. }
+                | Command::SAdd { .. }
+                | Command::SRem { .. }
+                | Command::SPop { .. }
+                | Command::SMove { .. }
+                | Command::SetOpStore { .. }
+                | Command::ZAdd { .. }
+                | Command::ZIncrBy { .. }
+                | Command::ZRangeStore { .. }
+                | Command::ZRemRange { .. }
+                | Command::ZCombineStore { .. }
+                | Command::SetBit { .. }
+                | Command::XAdd { .. }
+                | Command::XSetId { .. }
+                | Command::HDel { .. }
+                | Command::Import { .. }
+                | Command::GeoSearchStore { .. }
+        )
+    }
+}
+
+fn end(arg: &str) -> Option<End> {
+    match arg.to_lowercase().as_str() {
+        "left" => Some(End::Left),
+        "right" => Some(End::Right),
+        _ => None,
+    }
+}
+
+/// Blocking timeouts are in seconds and may be fractional, zero blocks forever.
+fn block_timeout(arg: &str) -> Result<Option<Duration>, Command> {
+    match arg.parse::<f64>() {
+        Ok(timeout) if timeout < 0.0 => Err(Command::Err("ERR timeout is negative".to_string())),
+        Ok(timeout) if timeout.is_finite() => Duration::try_from_secs_f64(timeout)
+            .map(|duration| (timeout > 0.0).then_some(duration))
+            .map_err(|_| Command::Err("ERR timeout is out of range".to_string())),
+        _ => Err(Command::Err(
+            "ERR timeout is not a float or out of range".to_string(),
+        )),
+    }
+}
+
+/// Parses `numkeys key [key ...] left|right [count count]`.
+fn mpop(args: &[&str]) -> Result<(Vec<String>, End, usize), Command> {
+    let numkeys = match args[0].parse::<i64>() {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        Ok(_) => {
+            return Err(Command::Err(
+                "ERR numkeys should be greater than 0".to_string(),
+            ))
+        }
+        Err(_) => return Err(Command::Err(NOT_AN_INTEGER.to_string())),
+    };
+    let syntax = || Command::Err(SYNTAX_ERROR.to_string());
+    let rest = args.get(1 + numkeys..).ok_or_else(syntax)?;
+    let keys = args[1..1 + numkeys]
+        .iter()
+        .map(|key| key.to_string())
+        .collect();
+    let count = match rest {
+        [_] => 1,
+        [_, option, count] if option.eq_ignore_ascii_case("count") => match count.parse::<i64>() {
+            Ok(count) if count > 0 => count as usize,
+            _ => {
+                return Err(Command::Err(
+                    "ERR count should be greater than 0".to_string(),
+                ))
+            }
+        },
+        _ => return Err(syntax()),
+    };
+    let end = rest.first().and_then(|arg| end(arg)).ok_or_else(syntax)?;
+    Ok((keys, end, count))
+}
+
+/// Parses the `cursor [match pattern] [count count]` tail of the SCAN family.
+fn scan_options(cursor: &str, options: &[&str]) -> Result<(u64, Option<String>, usize), Command> {
+    let cursor = cursor
+        .parse()
+        .map_err(|_| Command::Err("ERR invalid cursor".to_string()))?;
+    let mut pattern = None;
+    let mut count = 10;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case("match") => {
+                pattern = Some(value.to_string())
+            }
+            [name, value] if name.eq_ignore_ascii_case("count") => {
+                count = match value.parse::<i64>() {
+                    Ok(count) if count > 0 => count as usize,
+                    Ok(_) => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+                    Err(_) => return Err(Command::Err(NOT_AN_INTEGER.to_string())),
+                }
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        }
+    }
+    Ok((cursor, pattern, count))
+}
+
+/// The operation named by a set or sorted set command, e.g. `sinter` or `zunion`.
+fn set_op(name: &str) -> SetOp {
+    match &name[1..] {
+        "inter" => SetOp::Inter,
+        "union" => SetOp::Union,
+        _ => SetOp::Diff,
+    }
+}
+
+/// The count of SRANDMEMBER and the like, negative for repeats, of which
+/// there may be only so many.
+fn random_count(arg: &str) -> Result<i64, &'static str> {
+    let count = arg.parse::<i64>().map_err(|_| NOT_AN_INTEGER)?;
+    if count < 0 && count.unsigned_abs() > MAX_RANDOM_REPEATS {
+        return Err(OUT_OF_RANGE);
+    }
+    Ok(count)
+}
+
+/// Scores are floats including `inf`, `+inf` and `-inf`, but never NaN.
+fn score(arg: &str) -> Option<f64> {
+    arg.parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// `(1.5` is exclusive, `1.5` inclusive, and `-inf`/`+inf` are open ends.
+fn score_bound(arg: &str) -> Option<Bound<f64>> {
+    match arg.strip_prefix('(') {
+        Some(score) => self::score(score).map(Bound::Excluded),
+        None => self::score(arg).map(Bound::Included),
+    }
+}
+
+/// `[a` is inclusive, `(a` exclusive, and `-`/`+` are open ends.
+fn lex_bound(arg: &str) -> Option<Bound<String>> {
+    match arg.split_at(arg.len().min(1)) {
+        ("-" | "+", "") => Some(Bound::Unbounded),
+        ("[", member) => Some(Bound::Included(member.to_string())),
+        ("(", member) => Some(Bound::Excluded(member.to_string())),
+        _ => None,
+    }
+}
+
+/// Parses `[withscores] [limit offset count]` into the WITHSCORES flag and
+/// the offset and count to page with. A negative count means all of them.
+fn range_options(options: &[&str], allow_scores: bool) -> Result<(bool, usize, usize), Command> {
+    let mut with_scores = false;
+    let (mut offset, mut count) = (0, usize::MAX);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "withscores" if allow_scores => with_scores = true,
+            "limit" => {
+                let (Some(start), Some(limit)) = (options.next(), options.next()) else {
+                    return Err(Command::Err(SYNTAX_ERROR.to_string()));
+                };
+                let (Ok(start), Ok(limit)) = (start.parse::<i64>(), limit.parse::<i64>()) else {
+                    return Err(Command::Err(NOT_AN_INTEGER.to_string()));
+                };
+                // a negative offset selects nothing
+                offset = usize::try_from(start).unwrap_or(usize::MAX);
+                count = usize::try_from(limit).unwrap_or(usize::MAX);
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        }
+    }
+    Ok((with_scores, offset, count))
+}
+
+/// Parses `min max [byscore|bylex] [rev] [limit offset count]`, where `min`
+/// and `max` swap places with `rev` unless they are ranks.
+fn zrangestore_options(
+    first: &str,
+    second: &str,
+    options: &[&str],
+) -> Result<(ZRange, bool, usize, usize), Command> {
+    let (mut by, mut rev, mut limit) = ("rank", false, None);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "byscore" => by = "score",
+            "bylex" => by = "lex",
+            "rev" => rev = true,
+            "limit" => {
+                let (Some(offset), Some(count)) = (options.next(), options.next()) else {
+                    return Err(Command::Err(SYNTAX_ERROR.to_string()));
+                };
+                let (Ok(offset), Ok(count)) = (offset.parse::<i64>(), count.parse::<i64>()) else {
+                    return Err(Command::Err(NOT_AN_INTEGER.to_string()));
+                };
+                // a negative offset selects nothing
+                let offset = usize::try_from(offset).unwrap_or(usize::MAX);
+                limit = Some((offset, usize::try_from(count).unwrap_or(usize::MAX)));
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        }
+    }
+
+    let (min, max) = if rev {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    let range = match by {
+        "score" => {
+            let (Some(min), Some(max)) = (score_bound(min), score_bound(max)) else {
+                return Err(Command::Err("ERR min or max is not a float".to_string()));
+            };
+            ZRange::Score { min, max }
+        }
+        "lex" => {
+            let (Some(min), Some(max)) = (lex_bound(min), lex_bound(max)) else {
+                return Err(Command::Err(
+                    "ERR min or max not valid string range item".to_string(),
+                ));
+            };
+            ZRange::Lex { min, max }
+        }
+        _ => {
+            if limit.is_some() {
+                return Err(Command::Err(
+                    "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                        .to_string(),
+                ));
+            }
+            let (Ok(start), Ok(stop)) = (first.parse(), second.parse()) else {
+                return Err(Command::Err(NOT_AN_INTEGER.to_string()));
+            };
+            ZRange::Rank { start, stop }
+        }
+    };
+    let (offset, count) = limit.unwrap_or((0, usize::MAX));
+    Ok((range, rev, offset, count))
+}
+
+/// Parses `key [key ...] [weights weight ...] [aggregate sum|min|max] [withscores]`
+/// after `numkeys` for the sorted set combination commands.
+fn zcombine_options(
+    name: &str,
+    numkeys: &str,
+    args: &[&str],
+    allow_weights: bool,
+    allow_scores: bool,
+) -> Result<(Vec<String>, Vec<f64>, Aggregate, bool), Command> {
+    let syntax = || Command::Err(SYNTAX_ERROR.to_string());
+    let numkeys = match numkeys.parse::<i64>() {
+        Ok(numkeys) if numkeys > 0 => numkeys as usize,
+        Ok(_) => {
+            return Err(Command::Err(format!(
+                "ERR at least 1 input key is needed for '{name}' command"
+            )))
+        }
+        Err(_) => return Err(Command::Err(NOT_AN_INTEGER.to_string())),
+    };
+    let keys: Vec<String> = args
+        .get(..numkeys)
+        .ok_or_else(syntax)?
+        .iter()
+        .map(|key| key.to_string())
+        .collect();
+
+    let mut weights = vec![];
+    let mut aggregate = Aggregate::Sum;
+    let mut with_scores = false;
+    let mut options = &args[numkeys..];
+    while let Some((option, rest)) = options.split_first() {
+        options = rest;
+        match option.to_lowercase().as_str() {
+            "weights" if allow_weights => {
+                let given = options.get(..numkeys).ok_or_else(syntax)?;
+                weights = given
+                    .iter()
+                    .map(|weight| score(weight))
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| Command::Err("ERR weight value is not a float".to_string()))?;
+                options = &options[numkeys..];
+            }
+            "aggregate" if allow_weights => {
+                let (kind, rest) = options.split_first().ok_or_else(syntax)?;
+                aggregate = match kind.to_lowercase().as_str() {
+                    "sum" => Aggregate::Sum,
+                    "min" => Aggregate::Min,
+                    "max" => Aggregate::Max,
+                    _ => return Err(syntax()),
+                };
+                options = rest;
+            }
+            "withscores" if allow_scores => with_scores = true,
+            _ => return Err(syntax()),
+        }
+    }
+    Ok((keys, weights, aggregate, with_scores))
+}
+
+/// Bit offsets stay within a 512MB string, like Redis.
+fn bit_offset(arg: &str) -> Option<u64> {
+    arg.parse::<u64>().ok().filter(|offset| *offset < 1 << 32)
+}
+
+/// SET's condition, expiration and GET, each given at most once.
+fn set_options(options: &[&str]) -> Result<(Option<SetCondition>, Option<Expiry>, bool), Command> {
+    let mut condition = None;
+    let mut expiry = None;
+    let mut get = false;
+    let mut options = options;
+    while let Some((option, rest)) = options.split_first() {
+        options = match (option.to_lowercase().as_str(), rest) {
+            ("nx", rest) if condition.is_none() => {
+                condition = Some(SetCondition::IfAbsent);
+                rest
+            }
+            ("xx", rest) if condition.is_none() => {
+                condition = Some(SetCondition::IfPresent);
+                rest
+            }
+            ("get", rest) if !get => {
+                get = true;
+                rest
+            }
+            ("keepttl", rest) if expiry.is_none() => {
+                expiry = Some(Expiry::Keep);
+                rest
+            }
+            (unit @ ("ex" | "px" | "exat" | "pxat"), [time, rest @ ..]) if expiry.is_none() => {
+                expiry = Some(expire_time(time, unit, "set")?);
+                rest
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        };
+    }
+    Ok((condition, expiry, get))
+}
+
+/// A positive expiration in seconds or milliseconds, relative for `ex` and
+/// `px`, or a unix time for `exat` and `pxat`.
+fn expire_time(time: &str, unit: &str, command: &str) -> Result<Expiry, Command> {
+    let time = time
+        .parse::<i64>()
+        .map_err(|_| Command::Err(NOT_AN_INTEGER.to_string()))?;
+    let millis = match unit {
+        "ex" | "exat" => time.checked_mul(1000),
+        _ => Some(time),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as i64);
+    // the expiration must be representable as a unix time in milliseconds
+    let millis = millis
+        .filter(|millis| *millis > 0 && millis.checked_add(now).is_some())
+        .ok_or_else(|| Command::Err(format!("ERR invalid expire time in '{command}' command")))?;
+    let duration = Duration::from_millis(millis as u64);
+    Ok(match unit {
+        "ex" | "px" => Expiry::In(duration),
+        _ => Expiry::At(UNIX_EPOCH + duration),
+    })
+}
+
+/// The time an EXPIRE-style command sets, and its NX, XX, GT or LT flags.
+/// Times in the past are kept as the epoch, since they all delete the key.
+fn expire_options(
+    name: &str,
+    time: &str,
+    options: &[&str],
+) -> Result<(SystemTime, ExpireFlags), Command> {
+    let time = time
+        .parse::<i64>()
+        .map_err(|_| Command::Err(NOT_AN_INTEGER.to_string()))?;
+    let millis = match name {
+        "expire" | "expireat" => time.checked_mul(1000),
+        _ => Some(time),
+    };
+    let millis = match name {
+        "expire" | "pexpire" => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis() as i64);
+            millis.and_then(|millis| millis.checked_add(now))
+        }
+        _ => millis,
+    }
+    .ok_or_else(|| Command::Err(format!("ERR invalid expire time in '{name}' command")))?;
+    let at = UNIX_EPOCH + Duration::from_millis(millis.max(0) as u64);
+
+    let mut flags = ExpireFlags::default();
+    for option in options {
+        match option.to_lowercase().as_str() {
+            "nx" => flags.nx = true,
+            "xx" => flags.xx = true,
+            "gt" => flags.gt = true,
+            "lt" => flags.lt = true,
+            _ => return Err(Command::Err(format!("ERR Unsupported option {option}"))),
+        }
+    }
+    if flags.nx && (flags.xx || flags.gt || flags.lt) {
+        return Err(Command::Err(
+            "ERR NX and XX, GT or LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    if flags.gt && flags.lt {
+        return Err(Command::Err(
+            "ERR GT and LT options at the same time are not compatible".to_string(),
+        ));
+    }
+    Ok((at, flags))
+}
+
+/// restore key ttl payload [replace] [absttl]
+///
+/// Parsed from the raw arguments, since the payload DUMP makes is binary.
+fn restore(args: &[Vec<u8>]) -> Command {
+    let [key, ttl, payload, options @ ..] = args else {
+        return Command::Err("ERR wrong number of arguments for 'restore' command".to_string());
+    };
+    let (Ok(key), Ok(ttl)) = (std::str::from_utf8(key), std::str::from_utf8(ttl)) else {
+        return Command::Err(NOT_UTF8.to_string());
+    };
+    let ttl = match ttl.parse::<i64>() {
+        Ok(ttl) if ttl < 0 => {
+            return Command::Err("ERR Invalid TTL value, must be >= 0".to_string());
+        }
+        Ok(ttl) => Duration::from_millis(ttl as u64),
+        Err(_) => return Command::Err(NOT_AN_INTEGER.to_string()),
+    };
+
+    let (mut replace, mut absolute) = (false, false);
+    for option in options {
+        match option.to_ascii_lowercase().as_slice() {
+            b"replace" => replace = true,
+            b"absttl" => absolute = true,
+            _ => return Command::Err(SYNTAX_ERROR.to_string()),
+        }
+    }
+    // a ttl of 0 means no expiration
+    let at = match ttl {
+        Duration::ZERO => None,
+        ttl if absolute => Some(UNIX_EPOCH + ttl),
+        ttl => Some(SystemTime::now() + ttl),
+    };
+    Command::Restore {
+        key: key.to_string(),
+        payload: payload.clone(),
+        at,
+        replace,
+    }
+}
+
+/// SORT's options and its STORE destination.
+fn sort_options(options: &[&str]) -> Result<(SortOptions, Option<String>), Command> {
+    let mut sort = SortOptions::default();
+    let mut store = None;
+    let mut options = options;
+    while let Some((option, rest)) = options.split_first() {
+        options = match (option.to_lowercase().as_str(), rest) {
+            ("asc", rest) => {
+                sort.desc = false;
+                rest
+            }
+            ("desc", rest) => {
+                sort.desc = true;
+                rest
+            }
+            ("alpha", rest) => {
+                sort.alpha = true;
+                rest
+            }
+            ("by", [pattern, rest @ ..]) => {
+                sort.by = Some(pattern.to_string());
+                rest
+            }
+            ("get", [pattern, rest @ ..]) => {
+                sort.get.push(pattern.to_string());
+                rest
+            }
+            ("store", [destination, rest @ ..]) => {
+                store = Some(destination.to_string());
+                rest
+            }
+            ("limit", [offset, count, rest @ ..]) => match (offset.parse(), count.parse()) {
+                (Ok(offset), Ok(count)) => {
+                    sort.limit = Some((offset, count));
+                    rest
+                }
+                _ => return Err(Command::Err(NOT_AN_INTEGER.to_string())),
+            },
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        };
+    }
+    Ok((sort, store))
+}
+
+/// The length of a GEO distance unit in meters.
+fn geo_unit(arg: &str) -> Option<f64> {
+    match arg.to_lowercase().as_str() {
+        "m" => Some(1.0),
+        "km" => Some(1000.0),
+        "ft" => Some(0.3048),
+        "mi" => Some(1609.34),
+        _ => None,
+    }
+}
+
+fn lon_lat(lon: &str, lat: &str) -> Result<(f64, f64), Command> {
+    let (Some(lon), Some(lat)) = (score(lon), score(lat)) else {
+        return Err(Command::Err(NOT_A_FLOAT.to_string()));
+    };
+    if !in_bounds(lon, lat) {
+        return Err(Command::Err(format!(
+            "ERR invalid longitude,latitude pair {lon:.6},{lat:.6}"
+        )));
+    }
+    Ok((lon, lat))
+}
+
+/// A non-negative GEOSEARCH distance, converted to meters.
+fn geo_distance(arg: &str, unit: f64, error: &str) -> Result<f64, Command> {
+    match score(arg) {
+        None => Err(Command::Err(NOT_A_FLOAT.to_string())),
+        Some(distance) if distance < 0.0 => Err(Command::Err(error.to_string())),
+        Some(distance) => Ok(distance * unit),
+    }
+}
+
+/// The commands sharing GEOSEARCH's options
+enum GeoCommand {
+    Search,
+    /// GEOSEARCHSTORE into this destination
+    SearchStore(String),
+    /// GEORADIUS and GEORADIUSBYMEMBER, which may STORE
+    Radius,
+    RadiusReadOnly,
+}
+
+/// Parses the options of a geo search. GEORADIUS and GEORADIUSBYMEMBER give
+/// their center and radius up front, with its unit.
+fn geosearch(
+    command: GeoCommand,
+    key: &str,
+    mut from: Option<GeoFrom>,
+    mut shape: Option<(GeoShape, f64)>,
+    options: &[&str],
+) -> Result<Command, Command> {
+    let search = matches!(command, GeoCommand::Search | GeoCommand::SearchStore(_));
+    let mut order = None;
+    let mut count = None;
+    let mut any = false;
+    let (mut with_coord, mut with_dist, mut with_hash) = (false, false, false);
+    let mut store = match &command {
+        GeoCommand::SearchStore(destination) => Some(destination.clone()),
+        _ => None,
+    };
+    let mut store_dist = false;
+    let mut options = options;
+    let unit_of = |arg: &str| geo_unit(arg).ok_or_else(|| Command::Err(BAD_GEO_UNIT.to_string()));
+    while let Some((option, rest)) = options.split_first() {
+        options = match (option.to_lowercase().as_str(), rest) {
+            ("frommember", [member, rest @ ..]) if search && from.is_none() => {
+                from = Some(GeoFrom::Member(member.to_string()));
+                rest
+            }
+            ("fromlonlat", [lon, lat, rest @ ..]) if search && from.is_none() => {
+                let (lon, lat) = lon_lat(lon, lat)?;
+                from = Some(GeoFrom::LonLat(lon, lat));
+                rest
+            }
+            ("frommember" | "fromlonlat", _) if search && from.is_some() => {
+                return Err(Command::Err(
+                    "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                        .to_string(),
+                ))
+            }
+            ("byradius", [radius, by, rest @ ..]) if search && shape.is_none() => {
+                let unit = unit_of(by)?;
+                let radius = geo_distance(radius, unit, "ERR radius cannot be negative")?;
+                shape = Some((GeoShape::Radius(radius), unit));
+                rest
+            }
+            ("bybox", [width, height, by, rest @ ..]) if search && shape.is_none() => {
+                let unit = unit_of(by)?;
+                let error = "ERR height or width cannot be negative";
+                let width = geo_distance(width, unit, error)?;
+                let height = geo_distance(height, unit, error)?;
+                shape = Some((GeoShape::Box { width, height }, unit));
+                rest
+            }
+            ("byradius" | "bybox", _) if search && shape.is_some() => {
+                return Err(Command::Err(
+                    "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+                        .to_string(),
+                ))
+            }
+            ("asc", rest) => {
+                order = Some(GeoOrder::Asc);
+                rest
+            }
+            ("desc", rest) => {
+                order = Some(GeoOrder::Desc);
+                rest
+            }
+            ("count", [n, rest @ ..]) => {
+                match n.parse::<i64>() {
+                    Ok(n) if n > 0 => count = Some(n as usize),
+                    Ok(_) => return Err(Command::Err("ERR COUNT must be > 0".to_string())),
+                    Err(_) => return Err(Command::Err(NOT_AN_INTEGER.to_string())),
+                }
+                match rest.split_first() {
+                    Some((next, rest)) if next.eq_ignore_ascii_case("any") => {
+                        any = true;
+                        rest
+                    }
+                    _ => rest,
+                }
+            }
+            ("any", _) => {
+                return Err(Command::Err(
+                    "ERR the ANY argument requires COUNT argument".to_string(),
+                ))
+            }
+            ("withcoord", rest) => {
+                with_coord = true;
+                rest
+            }
+            ("withdist", rest) => {
+                with_dist = true;
+                rest
+            }
+            ("withhash", rest) => {
+                with_hash = true;
+                rest
+            }
+            ("storedist", rest) if matches!(command, GeoCommand::SearchStore(_)) => {
+                store_dist = true;
+                rest
+            }
+            ("store" | "storedist", [destination, rest @ ..])
+                if matches!(command, GeoCommand::Radius) =>
+            {
+                store = Some(destination.to_string());
+                store_dist = option.eq_ignore_ascii_case("storedist");
+                rest
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        };
+    }
+    let Some(from) = from else {
+        return Err(Command::Err(
+            "ERR exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                .to_string(),
+        ));
+    };
+    let Some((shape, unit)) = shape else {
+        return Err(Command::Err(
+            "ERR exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH".to_string(),
+        ));
+    };
+    let query = GeoQuery {
+        from,
+        shape,
+        order,
+        count,
+        any,
+    };
+    let Some(destination) = store else {
+        return Ok(Command::GeoSearch {
+            key: key.to_string(),
+            query,
+            unit,
+            with_coord,
+            with_dist,
+            with_hash,
+        });
+    };
+    if with_coord || with_dist || with_hash {
+        let command = match command {
+            GeoCommand::SearchStore(_) => "GEOSEARCHSTORE",
+            _ => "STORE option in GEORADIUS",
+        };
+        return Err(Command::Err(format!(
+            "ERR {command} is not compatible with WITHDIST, WITHHASH and WITHCOORD options"
+        )));
+    }
+    Ok(Command::GeoSearchStore {
+        destination,
+        source: key.to_string(),
+        query: Box::new(query),
+        unit,
+        store_dist,
+    })
+}
+
+/// A BITFIELD type and offset: `i1` to `i64` or `u1` to `u63`, and a bit
+/// offset or `#n` for the n-th field of that width.
+fn bit_field(ty: &str, offset: &str) -> Result<BitField, Command> {
+    let signed = match ty.as_bytes().first() {
+        Some(b'i' | b'I') => true,
+        Some(b'u' | b'U') => false,
+        _ => return Err(Command::Err(BAD_BITFIELD_TYPE.to_string())),
+    };
+    let bits = match ty[1..].parse::<u32>() {
+        Ok(bits) if bits >= 1 && (bits <= 63 || signed && bits == 64) => bits,
+        _ => return Err(Command::Err(BAD_BITFIELD_TYPE.to_string())),
+    };
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index
+            .parse::<u64>()
+            .ok()
+            .and_then(|index| index.checked_mul(bits as u64)),
+        None => offset.parse::<u64>().ok(),
+    }
+    .filter(|offset| {
+        offset
+            .checked_add(bits as u64)
+            .is_some_and(|end| end <= 1 << 32)
+    })
+    .ok_or_else(|| Command::Err(BAD_BIT_OFFSET.to_string()))?;
+    Ok(BitField {
+        signed,
+        bits,
+        offset,
+    })
+}
+
+/// The subcommands of a BITFIELD, with the OVERFLOW in effect when each
+/// write was given.
+fn bitfield_ops(args: &[&str]) -> Result<Vec<BitFieldOp>, Command> {
+    let mut ops = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut args = args;
+    while let Some((op, rest)) = args.split_first() {
+        let (op, rest) = match (op.to_lowercase().as_str(), rest) {
+            ("get", [ty, offset, rest @ ..]) => (BitFieldOp::Get(bit_field(ty, offset)?), rest),
+            ("set" | "incrby", [ty, offset, value, rest @ ..]) => {
+                let field = bit_field(ty, offset)?;
+                let value = value
+                    .parse()
+                    .map_err(|_| Command::Err(NOT_AN_INTEGER.to_string()))?;
+                if op.eq_ignore_ascii_case("set") {
+                    (BitFieldOp::Set(field, value, overflow), rest)
+                } else {
+                    (BitFieldOp::IncrBy(field, value, overflow), rest)
+                }
+            }
+            ("overflow", [mode, rest @ ..]) => {
+                overflow = match mode.to_lowercase().as_str() {
+                    "wrap" => Overflow::Wrap,
+                    "sat" => Overflow::Sat,
+                    "fail" => Overflow::Fail,
+                    _ => {
+                        return Err(Command::Err(
+                            "ERR Invalid OVERFLOW type specified".to_string(),
+                        ))
+                    }
+                };
+                args = rest;
+                continue;
+            }
+            _ => return Err(Command::Err(SYNTAX_ERROR.to_string())),
+        };
+        ops.push(op);
+        args = rest;
     }
+    Ok(ops)
 }
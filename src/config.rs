@@ -0,0 +1,663 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+use anyhow::{anyhow, Result};
+use clap::{Arg, ArgMatches};
+
+use crate::glob;
+
+type Validator = fn(&str) -> Result<(), String>;
+
+struct Directive {
+    name: &'static str,
+    /// Whether a config reload may change it without a restart
+    runtime: bool,
+    default: &'static str,
+    help: &'static str,
+    validate: Validator,
+}
+
+const DIRECTIVES: &[Directive] = &[
+    Directive {
+        name: "port",
+        runtime: false,
+        default: "6379",
+        help: "TCP port to listen on",
+        validate: port,
+    },
+    Directive {
+        name: "memcached-port",
+        runtime: false,
+        default: "0",
+        help: "TCP port for the memcached text protocol, 0 disables",
+        validate: port,
+    },
+    Directive {
+        name: "bind",
+        runtime: false,
+        default: "127.0.0.1",
+        help: "Interface address to listen on",
+        validate: address,
+    },
+    Directive {
+        name: "replicaof",
+        runtime: false,
+        default: "",
+        help: "Master host and port for replication",
+        validate: replicaof,
+    },
+    Directive {
+        name: "repl-backlog-size",
+        runtime: false,
+        default: "1mb",
+        help: "Bytes of the replication stream kept for replicas to resume from",
+        validate: memory,
+    },
+    Directive {
+        name: "replica-read-only",
+        runtime: true,
+        default: "yes",
+        help: "Whether a replica refuses writes from its clients",
+        validate: yes_no,
+    },
+    Directive {
+        name: "shutdown-timeout",
+        runtime: true,
+        default: "10",
+        help: "Seconds a master shutting down waits for its replicas to catch up, 0 disables",
+        validate: unsigned,
+    },
+    Directive {
+        name: "requirepass",
+        runtime: true,
+        default: "",
+        help: "Password clients must AUTH with",
+        validate: any,
+    },
+    Directive {
+        name: "masterauth",
+        runtime: true,
+        default: "",
+        help: "Password a replica AUTHs with to its master",
+        validate: any,
+    },
+    Directive {
+        name: "dir",
+        runtime: true,
+        default: ".",
+        help: "Working directory for RDB and AOF files",
+        validate: any,
+    },
+    Directive {
+        name: "dbfilename",
+        runtime: true,
+        default: "dump.rdb",
+        help: "RDB file name",
+        validate: file_name,
+    },
+    Directive {
+        name: "save",
+        runtime: true,
+        default: "3600 1 300 100 60 10000",
+        help: "Snapshot points as '<seconds> <changes>' pairs, \"\" disables",
+        validate: save_points,
+    },
+    Directive {
+        name: "appendonly",
+        runtime: false,
+        default: "no",
+        help: "Enable append-only file persistence (yes/no)",
+        validate: yes_no,
+    },
+    Directive {
+        name: "appendfilename",
+        runtime: false,
+        default: "appendonly.aof",
+        help: "AOF file name",
+        validate: file_name,
+    },
+    Directive {
+        name: "appendfsync",
+        runtime: true,
+        default: "everysec",
+        help: "AOF fsync policy (always/everysec/no)",
+        validate: fsync_policy,
+    },
+    Directive {
+        name: "aof-load-truncated",
+        runtime: true,
+        default: "yes",
+        help: "Load a truncated AOF instead of refusing to start (yes/no)",
+        validate: yes_no,
+    },
+    Directive {
+        name: "aof-use-rdb-preamble",
+        runtime: true,
+        default: "yes",
+        help: "Start rewritten AOFs with an RDB snapshot (yes/no)",
+        validate: yes_no,
+    },
+    Directive {
+        name: "maxmemory",
+        runtime: true,
+        default: "0",
+        help: "Memory limit in bytes, only 0 (no limit) as eviction isn't supported",
+        validate: no_memory_limit,
+    },
+    Directive {
+        name: "maxmemory-policy",
+        runtime: true,
+        default: "noeviction",
+        help: "Eviction policy when maxmemory is reached",
+        validate: eviction_policy,
+    },
+    Directive {
+        name: "maxclients",
+        runtime: true,
+        default: "10000",
+        help: "Maximum number of connected clients",
+        validate: positive,
+    },
+    Directive {
+        name: "timeout",
+        runtime: true,
+        default: "0",
+        help: "Close idle clients after this many seconds, 0 disables",
+        validate: unsigned,
+    },
+    Directive {
+        name: "databases",
+        runtime: false,
+        default: "1",
+        help: "Number of logical databases, only 1 as SELECT isn't supported",
+        validate: single_database,
+    },
+    Directive {
+        name: "hz",
+        runtime: true,
+        default: "10",
+        help: "Frequency of background tasks (1-500)",
+        validate: hz,
+    },
+    Directive {
+        name: "active-expire-effort",
+        runtime: true,
+        default: "1",
+        help: "How hard active expiry works to reclaim expired keys (1-10)",
+        validate: active_expire_effort,
+    },
+    Directive {
+        name: "loglevel",
+        runtime: true,
+        default: "notice",
+        help: "Log verbosity, only notice as there are no levels to the log",
+        validate: log_level,
+    },
+];
+
+/// Server configuration merged from defaults, the config file and CLI flags,
+/// in increasing order of precedence. Values are kept in their validated
+/// textual form, as `CONFIG GET` would report them.
+#[derive(Debug)]
+pub struct Config {
+    values: RwLock<BTreeMap<&'static str, String>>,
+    file: Option<String>,
+    /// Directives given on the command line, which a reload leaves alone
+    overrides: Vec<&'static str>,
+}
+
+impl Config {
+    /// One clap flag per directive, plus the optional config file argument.
+    pub fn args() -> Vec<Arg> {
+        let mut args = vec![Arg::new("config-file")
+            .value_name("CONFIG_FILE")
+            .help("Path to a redis.conf style configuration file")
+            .index(1)
+            .required(false)];
+
+        for directive in DIRECTIVES {
+            let validate = directive.validate;
+            let arg = Arg::new(directive.name)
+                .long(directive.name)
+                .help(directive.help)
+                .required(false);
+            let arg = match directive.name {
+                // both `--replicaof host port` and `--replicaof "host port"`,
+                // validated once the values are joined
+                "replicaof" => arg
+                    .num_args(1..=2)
+                    .value_names(["MASTER_HOST", "MASTER_PORT"]),
+                name => {
+                    let arg = arg.value_parser(move |value: &str| {
+                        validate(value).map(|_| value.to_string())
+                    });
+                    if name == "port" {
+                        arg.short('p')
+                    } else {
+                        arg
+                    }
+                }
+            };
+            args.push(arg);
+        }
+        args
+    }
+
+    pub fn load(matches: &ArgMatches) -> Result<Config> {
+        let file = matches.get_one::<String>("config-file").cloned();
+        let mut values = match &file {
+            Some(path) => read_file(path)?,
+            None => defaults(),
+        };
+
+        let mut overrides = vec![];
+        for directive in DIRECTIVES {
+            if let Some(args) = matches.get_many::<String>(directive.name) {
+                let value: Vec<&str> = args.map(|s| s.as_str()).collect();
+                let value = value.join(" ");
+                (directive.validate)(&value)
+                    .map_err(|err| anyhow!("--{} '{value}': {err}", directive.name))?;
+                values.insert(directive.name, value);
+                overrides.push(directive.name);
+            }
+        }
+        conflicts(&values).map_err(|err| anyhow!(err))?;
+
+        Ok(Config {
+            values: RwLock::new(values),
+            file,
+            overrides,
+        })
+    }
+
+    /// Re-reads the config file and applies the directives that can change at
+    /// runtime, logging what changed and what needs a restart.
+    pub fn reload(&self) -> Result<()> {
+        let Some(path) = &self.file else {
+            return Err(anyhow!("no config file to reload"));
+        };
+        let fresh = read_file(path)?;
+
+        let mut current = self.values.write().unwrap();
+        let mut values = current.clone();
+        for directive in DIRECTIVES {
+            let (current, new) = (&values[directive.name], &fresh[directive.name]);
+            if current == new {
+                continue;
+            }
+            if self.overrides.contains(&directive.name) {
+                println!(
+                    "[INFO] Config: '{}' is set on the command line, ignoring file value '{new}'",
+                    directive.name
+                );
+            } else if !directive.runtime {
+                println!(
+                    "[WARN] Config: '{}' changed to '{new}' but requires a restart",
+                    directive.name
+                );
+            } else {
+                println!(
+                    "[INFO] Config: '{}' changed from '{current}' to '{new}'",
+                    directive.name
+                );
+                values.insert(directive.name, new.clone());
+            }
+        }
+        conflicts(&values).map_err(|err| anyhow!(err))?;
+        *current = values;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> String {
+        let values = self.values.read().unwrap();
+        values.get(name).cloned().unwrap_or_default()
+    }
+
+    /// CONFIG GET: every directive whose name matches the glob `pattern`.
+    pub fn matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let values = self.values.read().unwrap();
+        let pattern = pattern.to_lowercase();
+        values
+            .iter()
+            .filter(|(name, _)| glob::matches(&pattern, name))
+            .map(|(name, value)| (*name, value.clone()))
+            .collect()
+    }
+
+    /// CONFIG SET: changes a directive that can change at runtime, with the
+    /// error message to reply with when it can't.
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        let name = name.to_lowercase();
+        let Some(directive) = DIRECTIVES.iter().find(|d| d.name == name) else {
+            return Err(format!(
+                "ERR Unknown option or number of arguments for CONFIG SET - '{name}'"
+            ));
+        };
+        if !directive.runtime {
+            return Err(format!(
+                "ERR CONFIG SET failed (possibly related to argument '{name}') - can't set immutable config"
+            ));
+        }
+        (directive.validate)(value).map_err(|err| {
+            format!("ERR CONFIG SET failed (possibly related to argument '{name}') - {err}")
+        })?;
+        let value = if directive.name == "save" {
+            value.split_whitespace().collect::<Vec<_>>().join(" ")
+        } else {
+            value.to_string()
+        };
+        let mut values = self.values.write().unwrap();
+        let mut next = values.clone();
+        next.insert(directive.name, value);
+        conflicts(&next).map_err(|err| {
+            format!("ERR CONFIG SET failed (possibly related to argument '{name}') - {err}")
+        })?;
+        if directive.name == "requirepass" {
+            println!("[INFO] Config: 'requirepass' changed");
+        } else {
+            println!("[INFO] Config: '{name}' set to '{}'", next[directive.name]);
+        }
+        *values = next;
+        Ok(())
+    }
+
+    pub fn port(&self) -> String {
+        self.get("port")
+    }
+
+    /// A directive holding a memory amount, in bytes.
+    pub fn memory(&self, name: &str) -> u64 {
+        parse_memory(&self.get(name)).unwrap_or_default()
+    }
+
+    /// The save points as (seconds, changes) pairs, none if saving is off.
+    pub fn save_points(&self) -> Vec<(u64, u64)> {
+        let numbers: Vec<u64> = self
+            .get("save")
+            .split_whitespace()
+            .filter_map(|n| n.parse().ok())
+            .collect();
+        numbers
+            .chunks_exact(2)
+            .map(|pair| (pair[0], pair[1]))
+            .collect()
+    }
+
+    pub fn replicaof(&self) -> Option<(String, String)> {
+        let value = self.get("replicaof");
+        let (host, port) = value.split_once(' ')?;
+        Some((host.to_string(), port.to_string()))
+    }
+}
+
+fn defaults() -> BTreeMap<&'static str, String> {
+    DIRECTIVES
+        .iter()
+        .map(|d| (d.name, d.default.to_string()))
+        .collect()
+}
+
+/// Defaults overlaid with the directives from a config file.
+fn read_file(path: &str) -> Result<BTreeMap<&'static str, String>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!("Can't open config file '{path}': {err}"))?;
+    let mut values = defaults();
+    for (name, value) in parse_file(&content)? {
+        values.insert(name, value);
+    }
+    Ok(values)
+}
+
+/// Parses directives from a config file, reporting every invalid line at once.
+fn parse_file(content: &str) -> Result<Vec<(&'static str, String)>> {
+    let mut parsed: Vec<(&'static str, String)> = vec![];
+    let mut errors = vec![];
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words = split_words(line);
+        let Some((name, args)) = words.split_first() else {
+            continue;
+        };
+        let name = name.to_lowercase();
+        let name = if name == "slaveof" {
+            "replicaof".to_string()
+        } else {
+            name
+        };
+
+        let Some(directive) = DIRECTIVES.iter().find(|d| d.name == name) else {
+            errors.push(format!(
+                "line {}: '{line}'\n  Bad directive or wrong number of arguments",
+                number + 1
+            ));
+            continue;
+        };
+
+        let mut value = args.join(" ");
+        // repeated save lines add up, `save ""` resets
+        if directive.name == "save" && !value.is_empty() {
+            if let Some((_, previous)) = parsed.iter().rev().find(|(n, _)| *n == "save") {
+                if !previous.is_empty() {
+                    value = format!("{previous} {value}");
+                }
+            }
+        }
+
+        match (directive.validate)(&value) {
+            Ok(()) => parsed.push((directive.name, value)),
+            Err(err) => errors.push(format!("line {}: '{line}'\n  {err}", number + 1)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(anyhow!(
+            "Reading the configuration file failed:\n{}",
+            errors.join("\n")
+        ));
+    }
+    Ok(parsed)
+}
+
+/// Splits a config line on whitespace, honoring double quotes.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut in_word = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+fn any(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+fn unsigned(value: &str) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("'{value}' is not a non-negative integer"))
+}
+
+fn positive(value: &str) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("'{value}' is not a positive integer")),
+    }
+}
+
+fn port(value: &str) -> Result<(), String> {
+    value
+        .parse::<u16>()
+        .map(|_| ())
+        .map_err(|_| format!("Invalid port '{value}', expected 0-65535"))
+}
+
+fn address(value: &str) -> Result<(), String> {
+    if value.split_whitespace().count() > 1 {
+        return Err("Binding multiple addresses is not supported".to_string());
+    }
+    if value == "localhost" || value.parse::<IpAddr>().is_ok() {
+        Ok(())
+    } else {
+        Err(format!("Invalid bind address '{value}'"))
+    }
+}
+
+fn replicaof(value: &str) -> Result<(), String> {
+    if value.is_empty() {
+        return Ok(());
+    }
+    match value.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [_host, master_port] => port(master_port),
+        _ => Err("Expected '<host> <port>'".to_string()),
+    }
+}
+
+fn file_name(value: &str) -> Result<(), String> {
+    if value.is_empty() || value.contains('/') {
+        Err(format!("'{value}' must be a plain file name, not a path"))
+    } else {
+        Ok(())
+    }
+}
+
+fn save_points(value: &str) -> Result<(), String> {
+    let numbers: Vec<&str> = value.split_whitespace().collect();
+    if !numbers.chunks_exact(2).remainder().is_empty() {
+        return Err("Expected '<seconds> <changes>' pairs".to_string());
+    }
+    numbers.iter().try_for_each(|n| unsigned(n))
+}
+
+fn yes_no(value: &str) -> Result<(), String> {
+    one_of(value, &["yes", "no"])
+}
+
+fn fsync_policy(value: &str) -> Result<(), String> {
+    one_of(value, &["always", "everysec", "no"])
+}
+
+fn eviction_policy(value: &str) -> Result<(), String> {
+    one_of(
+        value,
+        &[
+            "noeviction",
+            "allkeys-lru",
+            "allkeys-lfu",
+            "allkeys-random",
+            "volatile-lru",
+            "volatile-lfu",
+            "volatile-random",
+            "volatile-ttl",
+        ],
+    )
+}
+
+fn log_level(value: &str) -> Result<(), String> {
+    one_of(value, &["debug", "verbose", "notice", "warning", "nothing"])?;
+    if value != "notice" {
+        return Err(format!("'{value}' is not supported, only 'notice'"));
+    }
+    Ok(())
+}
+
+fn no_memory_limit(value: &str) -> Result<(), String> {
+    memory(value)?;
+    if parse_memory(value) != Some(0) {
+        return Err("a memory limit is not supported, only 0 (no limit)".to_string());
+    }
+    Ok(())
+}
+
+fn single_database(value: &str) -> Result<(), String> {
+    if value != "1" {
+        return Err(format!("'{value}' is not supported, only 1 database"));
+    }
+    Ok(())
+}
+
+/// Directives that can't be set together, checked on every change.
+fn conflicts(values: &BTreeMap<&'static str, String>) -> Result<(), String> {
+    if !values["requirepass"].is_empty() && values["memcached-port"] != "0" {
+        return Err(
+            "requirepass can't be used with memcached-port, which has no authentication"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+fn hz(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(n) if (1..=500).contains(&n) => Ok(()),
+        _ => Err(format!("'{value}' must be between 1 and 500")),
+    }
+}
+
+fn active_expire_effort(value: &str) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(n) if (1..=10).contains(&n) => Ok(()),
+        _ => Err(format!("'{value}' must be between 1 and 10")),
+    }
+}
+
+fn memory(value: &str) -> Result<(), String> {
+    parse_memory(value)
+        .map(|_| ())
+        .ok_or_else(|| format!("Invalid memory amount '{value}'"))
+}
+
+/// Parses memory amounts like `100mb` or `1g` into bytes.
+fn parse_memory(value: &str) -> Option<u64> {
+    let lower = value.to_lowercase();
+    let digits = lower.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match &lower[digits.len()..] {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn one_of(value: &str, allowed: &[&str]) -> Result<(), String> {
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid value '{value}', expected one of: {}",
+            allowed.join(", ")
+        ))
+    }
+}
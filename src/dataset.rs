@@ -0,0 +1,659 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Result};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::db::{SortedSet, Stream, StreamId, Value, DB};
+use crate::parse::{array_bytes, MAX_BULK_LEN, PREALLOC_LIMIT};
+use crate::rdb;
+
+#[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line
+    Json,
+    Csv,
+}
+
+/// What to do when an imported key already exists
+#[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
+pub enum Conflict {
+    Skip,
+    Replace,
+}
+
+/// Where IMPORT reads keys from
+#[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
+pub enum Source {
+    /// A JSON Lines file in the configured dir
+    File(String),
+    Redis(Remote),
+}
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+const PROGRESS_EVERY: usize = 10_000;
+
+/// Writes every live key with its type, value and remaining TTL in
+/// milliseconds (-1 when persistent), returning the number of keys written.
+pub fn export(db: &DB, path: &Path, format: &Format) -> Result<usize> {
+    let mut out = BufWriter::new(File::create(path)?);
+    if *format == Format::Csv {
+        writeln!(out, "key,type,value,ttl")?;
+    }
+
+    let entries = db.entries();
+    for (key, value, ttl) in &entries {
+        let ttl = ttl.map_or(-1, |ttl| ttl.as_millis() as i64);
+        let kind = value.type_name();
+        let json = json_value(value);
+        match format {
+            Format::Json => writeln!(
+                out,
+                r#"{{"key":{},"type":"{kind}","value":{json},"ttl":{ttl}}}"#,
+                json_string(key)
+            )?,
+            Format::Csv => {
+                // strings go in as is, collections as their JSON array
+                let field = match value {
+                    Value::String(value) => csv_field(&String::from_utf8_lossy(value)),
+                    _ => csv_field(&json),
+                };
+                writeln!(out, "{},{kind},{field},{ttl}", csv_field(key))?
+            }
+        }
+    }
+    out.flush()?;
+    Ok(entries.len())
+}
+
+/// Strings as JSON strings, lists and sets as JSON arrays of strings,
+/// hashes as JSON objects, sorted sets as objects of member to score and
+/// streams as objects of id to fields.
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => json_string(&String::from_utf8_lossy(value)),
+        Value::List(list) => json_array(list.iter()),
+        Value::Set(set) => json_array(set.iter()),
+        Value::Hash(hash) => json_object(
+            hash.iter()
+                .map(|(field, value)| (field.as_str(), value.clone())),
+        ),
+        // scores as strings, since JSON numbers can't hold inf
+        Value::ZSet(zset) => json_object(
+            zset.iter()
+                .map(|(member, score)| (member, score.to_string())),
+        ),
+        Value::Stream(stream) => {
+            let entries: Vec<String> = stream
+                .iter()
+                .map(|(id, fields)| {
+                    let fields = fields
+                        .iter()
+                        .map(|(field, value)| (field.as_str(), value.clone()));
+                    format!("{}:{}", json_string(&id.to_string()), json_object(fields))
+                })
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+    }
+}
+
+fn json_array<'a>(items: impl Iterator<Item = &'a String>) -> String {
+    let items: Vec<String> = items.map(|item| json_string(item)).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_object<'a>(fields: impl Iterator<Item = (&'a str, String)>) -> String {
+    let fields: Vec<String> = fields
+        .map(|(field, value)| format!("{}:{}", json_string(field), json_string(&value)))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Loads keys from a JSON Lines file in the format written by [`export`],
+/// calling `on_store` for every key written so it can be propagated.
+/// Records of a type this server doesn't know are skipped.
+pub fn import(
+    db: &DB,
+    path: &Path,
+    conflict: &Conflict,
+    mut on_store: impl FnMut(&str, &Value, Option<SystemTime>),
+) -> Result<ImportReport> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut report = ImportReport::default();
+    let replace = *conflict == Conflict::Replace;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let at_line = |err| anyhow!("line {}: {err}", number + 1);
+        let record = parse_record(&line).map_err(at_line)?;
+        let Some(value) = record_value(&record.kind, record.value).map_err(at_line)? else {
+            report.skipped += 1;
+            continue;
+        };
+
+        let ttl = match record.ttl {
+            -1 => None,
+            ttl if ttl > 0 => Some(Duration::from_millis(ttl as u64)),
+            // already expired by the time it was exported
+            _ => {
+                report.skipped += 1;
+                continue;
+            }
+        };
+        // pinned to a time here, so replicas and the AOF expire it when we do
+        let expires_at = ttl.map(|ttl| SystemTime::now() + ttl);
+        if db.put(&record.key, value.clone(), expires_at, replace) {
+            on_store(&record.key, &value, expires_at);
+            report.imported += 1;
+        } else {
+            report.skipped += 1;
+        }
+
+        if (report.imported + report.skipped) % PROGRESS_EVERY == 0 {
+            println!(
+                "Import: {} keys loaded, {} skipped",
+                report.imported, report.skipped
+            );
+        }
+    }
+    Ok(report)
+}
+
+/// Copies every key of the instance at `remote` with SCAN, DUMP and PTTL,
+/// calling `on_store` for every key written. Keys whose payload this server
+/// can't read, such as module types, are skipped.
+pub async fn copy(
+    db: &DB,
+    remote: &Remote,
+    conflict: &Conflict,
+    mut on_store: impl FnMut(&str, &Value, Option<SystemTime>),
+) -> Result<ImportReport> {
+    let mut connection = Connection::open(remote).await?;
+    let mut report = ImportReport::default();
+    let replace = *conflict == Conflict::Replace;
+    let mut cursor = "0".to_string();
+
+    loop {
+        connection
+            .send(&[b"scan", cursor.as_bytes(), b"count", b"1000"])
+            .await?;
+        connection.expect_array(2).await?;
+        cursor = String::from_utf8(connection.bulk().await?.unwrap_or_default())?;
+        let count = connection.array().await?;
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            keys.push(connection.bulk().await?.unwrap_or_default());
+        }
+
+        // one round trip for the whole batch
+        let mut batch = vec![];
+        for key in &keys {
+            batch.extend(array_bytes(&[b"dump".as_slice(), key]));
+            batch.extend(array_bytes(&[b"pttl".as_slice(), key]));
+        }
+        connection.write(&batch).await?;
+        for key in keys {
+            let payload = connection.bulk().await?;
+            let ttl = connection.integer().await?;
+            let value = payload
+                .as_deref()
+                .and_then(rdb::undump)
+                .map(rdb::read_value);
+            let (Ok(key), Some(Ok(value))) = (String::from_utf8(key), value) else {
+                report.skipped += 1;
+                continue;
+            };
+            let expires_at = match ttl {
+                -1 => None,
+                ttl if ttl > 0 => Some(SystemTime::now() + Duration::from_millis(ttl as u64)),
+                // gone since the SCAN
+                _ => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            if db.put(&key, value.clone(), expires_at, replace) {
+                on_store(&key, &value, expires_at);
+                report.imported += 1;
+            } else {
+                report.skipped += 1;
+            }
+
+            if (report.imported + report.skipped) % PROGRESS_EVERY == 0 {
+                println!(
+                    "Import: {} keys loaded, {} skipped",
+                    report.imported, report.skipped
+                );
+            }
+        }
+
+        if cursor == "0" {
+            return Ok(report);
+        }
+    }
+}
+
+/// Another instance to import from, as given by a
+/// `redis://[[user]:password@]host[:port][/db]` URI
+#[derive(Debug, Ord, PartialOrd, PartialEq, Eq)]
+pub struct Remote {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub db: Option<u32>,
+}
+
+impl Remote {
+    pub fn parse(uri: &str) -> Option<Self> {
+        let rest = uri.strip_prefix("redis://")?;
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (address, db) = match rest.split_once('/') {
+            Some((address, "")) => (address, None),
+            Some((address, db)) => (address, Some(db.parse().ok()?)),
+            None => (rest, None),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (address, 6379),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let (username, password) = match credentials.map(|c| c.split_once(':')) {
+            None => (None, None),
+            Some(None) => return None,
+            Some(Some(("", password))) => (None, Some(password.to_string())),
+            Some(Some((username, password))) => {
+                (Some(username.to_string()), Some(password.to_string()))
+            }
+        };
+        Some(Remote {
+            host: host.to_string(),
+            port,
+            username,
+            password,
+            db,
+        })
+    }
+}
+
+impl fmt::Display for Remote {
+    // leaves the password out, as this ends up in logs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "redis://{}:{}", self.host, self.port)?;
+        if let Some(db) = self.db {
+            write!(f, "/{db}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A client connection reading just the replies an import needs
+struct Connection {
+    stream: io::BufReader<TcpStream>,
+}
+
+impl Connection {
+    async fn open(remote: &Remote) -> Result<Self> {
+        let stream = TcpStream::connect((remote.host.as_str(), remote.port)).await?;
+        let mut connection = Connection {
+            stream: io::BufReader::new(stream),
+        };
+        if let Some(password) = &remote.password {
+            match &remote.username {
+                Some(username) => {
+                    connection
+                        .send(&[b"auth", username.as_bytes(), password.as_bytes()])
+                        .await?
+                }
+                None => connection.send(&[b"auth", password.as_bytes()]).await?,
+            }
+            connection.line().await?;
+        }
+        if let Some(db) = remote.db {
+            connection
+                .send(&[b"select", db.to_string().as_bytes()])
+                .await?;
+            connection.line().await?;
+        }
+        Ok(connection)
+    }
+
+    async fn send(&mut self, args: &[&[u8]]) -> Result<()> {
+        self.write(&array_bytes(args)).await
+    }
+
+    async fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.stream.get_mut().write_all(bytes).await?;
+        Ok(())
+    }
+
+    /// The next reply line without its CRLF, failing on error replies
+    async fn line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("connection closed"));
+        }
+        let line = line
+            .strip_suffix("\r\n")
+            .ok_or_else(|| anyhow!("expected CRLF terminated line"))?;
+        if let Some(err) = line.strip_prefix('-') {
+            return Err(anyhow!("remote replied: {err}"));
+        }
+        Ok(line.to_string())
+    }
+
+    async fn integer(&mut self) -> Result<i64> {
+        let line = self.line().await?;
+        line.strip_prefix(':')
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| anyhow!("expected an integer but got {line}"))
+    }
+
+    async fn array(&mut self) -> Result<usize> {
+        let line = self.line().await?;
+        line.strip_prefix('*')
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| anyhow!("expected an array but got {line}"))
+    }
+
+    async fn expect_array(&mut self, len: usize) -> Result<()> {
+        match self.array().await? {
+            n if n == len => Ok(()),
+            n => Err(anyhow!("expected {len} elements but got {n}")),
+        }
+    }
+
+    async fn bulk(&mut self) -> Result<Option<Vec<u8>>> {
+        let line = self.line().await?;
+        if line == "$-1" {
+            return Ok(None);
+        }
+        let size = line
+            .strip_prefix('$')
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|size| *size <= MAX_BULK_LEN)
+            .ok_or_else(|| anyhow!("expected a bulk string but got {line}"))?;
+        let mut value = Vec::with_capacity((size + 2).min(PREALLOC_LIMIT));
+        (&mut self.stream)
+            .take(size as u64 + 2)
+            .read_to_end(&mut value)
+            .await?;
+        if !value.ends_with(b"\r\n") || value.len() != size + 2 {
+            return Err(anyhow!("truncated bulk string"));
+        }
+        value.truncate(size);
+        Ok(Some(value))
+    }
+}
+
+struct Record {
+    key: String,
+    kind: String,
+    value: Json,
+    ttl: i64,
+}
+
+/// The JSON [`export`] writes values as: strings, and arrays and objects
+/// holding more of them.
+enum Json {
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Deepest a value nests, for the fields of a stream entry
+const MAX_DEPTH: usize = 3;
+
+/// Parses one JSON object of a key, its type, value and TTL.
+fn parse_record(line: &str) -> Result<Record> {
+    let mut chars = line.trim().chars().peekable();
+    let mut key = None;
+    let mut kind = None;
+    let mut value = None;
+    let mut ttl = -1;
+
+    expect(&mut chars, '{')?;
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.peek() == Some(&'}') {
+            break;
+        }
+        let field = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        expect(&mut chars, ':')?;
+        skip_whitespace(&mut chars);
+
+        match field.as_str() {
+            "key" => key = Some(parse_string(&mut chars)?),
+            "value" => value = Some(parse_json(&mut chars, 1)?),
+            "type" => kind = Some(parse_string(&mut chars)?),
+            "ttl" => {
+                let mut number = String::new();
+                while let Some(c) = chars.next_if(|c| *c == '-' || c.is_ascii_digit()) {
+                    number.push(c);
+                }
+                ttl = number
+                    .parse()
+                    .map_err(|_| anyhow!("invalid ttl '{number}'"))?;
+            }
+            other => return Err(anyhow!("unknown field '{other}'")),
+        }
+
+        skip_whitespace(&mut chars);
+        if chars.next_if_eq(&',').is_none() {
+            break;
+        }
+    }
+    expect(&mut chars, '}')?;
+
+    Ok(Record {
+        key: key.ok_or_else(|| anyhow!("missing key"))?,
+        // files from before other types were exported hold only strings
+        kind: kind.unwrap_or_else(|| "string".to_string()),
+        value: value.ok_or_else(|| anyhow!("missing value"))?,
+        ttl,
+    })
+}
+
+fn parse_json(chars: &mut Chars, depth: usize) -> Result<Json> {
+    if depth > MAX_DEPTH {
+        return Err(anyhow!("value nested too deep"));
+    }
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('[') => {
+            chars.next();
+            let mut items = vec![];
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_json(chars, depth + 1)?);
+                skip_whitespace(chars);
+                if chars.next_if_eq(&',').is_none() {
+                    break;
+                }
+            }
+            expect(chars, ']')?;
+            Ok(Json::Array(items))
+        }
+        Some('{') => {
+            chars.next();
+            let mut fields = vec![];
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                let name = parse_string(chars)?;
+                skip_whitespace(chars);
+                expect(chars, ':')?;
+                fields.push((name, parse_json(chars, depth + 1)?));
+                skip_whitespace(chars);
+                if chars.next_if_eq(&',').is_none() {
+                    break;
+                }
+            }
+            expect(chars, '}')?;
+            Ok(Json::Object(fields))
+        }
+        _ => Ok(Json::String(parse_string(chars)?)),
+    }
+}
+
+/// The value of a record of type `kind`, in the shape [`json_value`] gives
+/// it. `None` for types it doesn't write.
+fn record_value(kind: &str, json: Json) -> Result<Option<Value>> {
+    let value = match (kind, json) {
+        ("string", Json::String(value)) => Value::String(value.into_bytes()),
+        ("list", Json::Array(items)) => Value::List(strings(items)?.collect()),
+        ("set", Json::Array(items)) => Value::Set(strings(items)?.collect()),
+        ("hash", Json::Object(fields)) => Value::Hash(fields_of(fields)?.into_iter().collect()),
+        ("zset", Json::Object(members)) => {
+            let mut zset = SortedSet::default();
+            for (member, score) in fields_of(members)? {
+                let score = score
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|score| !score.is_nan())
+                    .ok_or_else(|| anyhow!("invalid score '{score}'"))?;
+                zset.insert(&member, score);
+            }
+            Value::ZSet(zset)
+        }
+        ("stream", Json::Object(entries)) => {
+            let mut parsed = BTreeMap::new();
+            for (id, fields) in entries {
+                let id = id
+                    .parse::<StreamId>()
+                    .map_err(|_| anyhow!("invalid stream id '{id}'"))?;
+                let Json::Object(fields) = fields else {
+                    return Err(anyhow!("stream entry {id} is not an object"));
+                };
+                parsed.insert(id, fields_of(fields)?);
+            }
+            let last_id = parsed.keys().next_back().copied().unwrap_or_default();
+            let added = parsed.len() as u64;
+            Value::Stream(Stream::from_parts(
+                parsed,
+                last_id,
+                added,
+                StreamId::default(),
+            ))
+        }
+        ("string" | "list" | "set" | "hash" | "zset" | "stream", _) => {
+            return Err(anyhow!("value doesn't fit type '{kind}'"))
+        }
+        _ => return Ok(None),
+    };
+    if value.len() == 0 && !matches!(value, Value::String(_) | Value::Stream(_)) {
+        return Err(anyhow!("empty {kind}"));
+    }
+    Ok(Some(value))
+}
+
+fn strings(items: Vec<Json>) -> Result<impl Iterator<Item = String>> {
+    let items = items
+        .into_iter()
+        .map(|item| match item {
+            Json::String(item) => Ok(item),
+            _ => Err(anyhow!("expected a string")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(items.into_iter())
+}
+
+fn fields_of(fields: Vec<(String, Json)>) -> Result<Vec<(String, String)>> {
+    fields
+        .into_iter()
+        .map(|(name, value)| match value {
+            Json::String(value) => Ok((name, value)),
+            _ => Err(anyhow!("expected a string for '{name}'")),
+        })
+        .collect()
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+fn skip_whitespace(chars: &mut Chars) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Chars, expected: char) -> Result<()> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        other => Err(anyhow!("expected '{expected}' but got {other:?}")),
+    }
+}
+
+fn parse_string(chars: &mut Chars) -> Result<String> {
+    expect(chars, '"')?;
+    let mut result = String::new();
+    loop {
+        match chars.next().ok_or_else(|| anyhow!("unterminated string"))? {
+            '"' => return Ok(result),
+            '\\' => match chars.next().ok_or_else(|| anyhow!("unterminated escape"))? {
+                'n' => result.push('\n'),
+                'r' => result.push('\r'),
+                't' => result.push('\t'),
+                'b' => result.push('\u{8}'),
+                'f' => result.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&code, 16)
+                        .map_err(|_| anyhow!("invalid unicode escape '{code}'"))?;
+                    result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => result.push(c),
+            },
+            c => result.push(c),
+        }
+    }
+}
@@ -1,45 +1,995 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use thiserror::Error;
+
+use crate::{glob, rdb};
+
+mod bitmap;
+mod geo;
+mod hash;
+mod list;
+mod set;
+mod sort;
+mod stream;
+mod zset;
+
+pub use bitmap::{BitField, BitFieldOp, Overflow};
+pub use geo::{geohash, in_bounds, GeoFrom, GeoOrder, GeoQuery, GeoShape};
+pub use list::End;
+pub use set::SetOp;
+pub use sort::SortOptions;
+pub use stream::{Stream, StreamEntry, StreamId, StreamInfo, XAddId};
+pub use zset::{Aggregate, SortedSet, ZAddFlags, ZRange};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DbError {
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("ERR no such key")]
+    NoSuchKey,
+    #[error("ERR index out of range")]
+    OutOfRange,
+    #[error("ERR hash value is not an integer")]
+    HashNotInteger,
+    #[error("ERR hash value is not a float")]
+    HashNotFloat,
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+    #[error("ERR increment or decrement would overflow")]
+    Overflow,
+    #[error("ERR increment would produce NaN or Infinity")]
+    NotFinite,
+    #[error("ERR resulting score is not a number (NaN)")]
+    NotANumber,
+    #[error("ERR The ID specified in XADD must be greater than 0-0")]
+    StreamIdZero,
+    #[error("ERR The ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+    #[error("ERR The ID specified in XSETID is smaller than the target stream top item")]
+    StreamIdBelowTop,
+    #[error("ERR The entries_added specified in XSETID is smaller than the target stream length")]
+    EntriesAddedTooSmall,
+    #[error("ERR could not decode requested zset member")]
+    NoSuchMember,
+    #[error("ERR DUMP payload version or checksum are wrong")]
+    BadDumpPayload,
+    #[error("ERR Bad data format")]
+    BadDataFormat,
+    #[error("BUSYKEY Target key name already exists.")]
+    BusyKey,
+    #[error("ERR One or more scores can't be converted into double")]
+    NotSortable,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    /// Raw bytes, since bitmaps can hold anything
+    String(Vec<u8>),
+    List(VecDeque<String>),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+
+    /// Byte length for strings, element count for collections
+    pub fn len(&self) -> usize {
+        match self {
+            Value::String(value) => value.len(),
+            Value::List(list) => list.len(),
+            Value::Hash(hash) => hash.len(),
+            Value::Set(set) => set.len(),
+            Value::ZSet(zset) => zset.len(),
+            Value::Stream(stream) => stream.len(),
+        }
+    }
+}
+
+/// Which keys SET writes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetCondition {
+    /// NX
+    IfAbsent,
+    /// XX
+    IfPresent,
+}
+
+/// The expiration SET gives the key, which otherwise loses any it had.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    /// KEEPTTL
+    Keep,
+    In(Duration),
+    At(SystemTime),
+}
+
+/// When EXPIRE sets the expiration.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ExpireFlags {
+    /// only when the key has none
+    pub nx: bool,
+    /// only when the key has one
+    pub xx: bool,
+    /// only when later than the current one, with none counting as never
+    pub gt: bool,
+    /// only when earlier than the current one, with none counting as never
+    pub lt: bool,
+}
+
+/// New keys start with some LFU credit so they aren't the first evicted.
+const LFU_INIT: u8 = 5;
+/// How much slower each LFU increment gets as the counter grows, so the
+/// 8-bit counter saturates around a million hits, as in Redis.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+#[derive(Debug)]
+pub struct Entry {
+    pub value: Value,
+    /// UNIX time in milliseconds, so it means the same once saved or sent
+    /// to a replica
+    pub expires_at: Option<u64>,
+    /// Last read or write, for IDLETIME and LRU
+    accessed_at: Instant,
+    /// Logarithmic access counter, for FREQ and LFU
+    frequency: u8,
+}
+
+/// What OBJECT reports about a key.
 #[derive(Debug)]
-pub enum Entry {
-    Simple(String),
-    Expire(String, Instant),
+pub struct ObjectInfo {
+    pub encoding: &'static str,
+    pub idle: Duration,
+    pub frequency: u8,
+}
+
+impl Entry {
+    fn new(value: Value, ex: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            value,
+            expires_at: ex.map(|duration| now_ms().saturating_add(duration.as_millis() as u64)),
+            accessed_at: now,
+            frequency: LFU_INIT,
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|ex| now > ex)
+    }
+
+    /// The LFU counter, less one for every minute without an access.
+    fn frequency(&self, now: Instant) -> u8 {
+        let minutes = now.duration_since(self.accessed_at).as_secs() / 60;
+        self.frequency
+            .saturating_sub(minutes.min(u8::MAX as u64) as u8)
+    }
+
+    /// Records an access, bumping the counter with a probability that
+    /// falls as it grows.
+    fn touch(&mut self, now: Instant) {
+        let frequency = self.frequency(now);
+        let base = frequency.saturating_sub(LFU_INIT) as f64;
+        let chance = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+        let roll = random(1 << 20) as f64 / (1 << 20) as f64;
+        self.frequency = if frequency < u8::MAX && roll < chance {
+            frequency + 1
+        } else {
+            frequency
+        };
+        self.accessed_at = now;
+    }
+
+    /// Names the representation the way Redis does. These never change
+    /// with size, so there is no listpack or intset.
+    fn encoding(&self) -> &'static str {
+        match &self.value {
+            // only canonical integers, which print back the same
+            Value::String(value) => match std::str::from_utf8(value).map(str::parse::<i64>) {
+                Ok(Ok(n)) if n.to_string().as_bytes() == value.as_slice() => "int",
+                _ => "raw",
+            },
+            Value::List(_) => "quicklist",
+            Value::Hash(_) | Value::Set(_) => "hashtable",
+            Value::ZSet(_) => "skiplist",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+/// Values with more elements than this are freed in the background by UNLINK,
+/// as in Redis.
+const LAZY_FREE_THRESHOLD: usize = 64;
+
+/// Keys active expiry samples per round, at the lowest effort
+const EXPIRE_SAMPLE: usize = 20;
+/// Active expiry goes another round while more than this percentage of a
+/// sample had expired, at the lowest effort
+const EXPIRE_STALE_PERCENT: usize = 10;
+
+/// Keys that were given an expiration, so active expiry can sample them
+/// at random. Keys removed or made persistent since are only dropped when
+/// sampled.
+#[derive(Debug, Default)]
+struct Volatile {
+    keys: Vec<String>,
+    positions: HashMap<String, usize>,
+}
+
+impl Volatile {
+    fn add(&mut self, key: &str) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_string(), self.keys.len());
+            self.keys.push(key.to_string());
+        }
+    }
+
+    fn swap_remove(&mut self, position: usize) {
+        let key = self.keys.swap_remove(position);
+        self.positions.remove(&key);
+        if let Some(moved) = self.keys.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+    }
+}
+
+/// The entries by key. It derefs to the map, with `insert` and `clear`
+/// shadowed to keep the index of volatile keys.
+#[derive(Debug, Default)]
+struct Keyspace {
+    /// `RandomState` is SipHash-1-3 keyed with a random per-process seed, so
+    /// crafted keys can't be precomputed to collide in the keyspace.
+    entries: HashMap<String, Entry, RandomState>,
+    volatile: Volatile,
+    /// Keys removed on expiring that replicas haven't been told about yet
+    expired: Vec<String>,
+    /// Keys removed on expiring since startup
+    expired_keys: u64,
+    /// Set on replicas, which leave expiring keys to their master and only
+    /// hide the expired ones from clients until it deletes them
+    replica: bool,
+    /// Whether the lock is held for the master's stream, to which those
+    /// keys are still there
+    from_master: bool,
+}
+
+impl Keyspace {
+    fn insert(&mut self, key: String, entry: Entry) -> Option<Entry> {
+        if entry.expires_at.is_some() {
+            self.volatile.add(&key);
+        }
+        self.entries.insert(key, entry)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.volatile = Volatile::default();
+    }
+
+    /// Removes a key found to have expired, counting it and noting it for
+    /// replicas.
+    fn remove_expired(&mut self, key: &str) {
+        if self.entries.remove(key).is_some() {
+            self.expired_keys += 1;
+            self.expired.push(key.to_string());
+        }
+    }
+
+    /// Checks up to `count` random volatile keys, removing the expired ones,
+    /// and returns how many keys it checked and how many it removed.
+    fn expire_sample(&mut self, count: usize, now: u64) -> (usize, usize) {
+        if self.replica {
+            return (0, 0);
+        }
+        let mut checked = 0;
+        let mut expired = 0;
+        while checked < count && !self.volatile.keys.is_empty() {
+            let position = random(self.volatile.keys.len());
+            let key = self.volatile.keys[position].clone();
+            match self.entries.get(&key).and_then(|entry| entry.expires_at) {
+                // no longer volatile, so it doesn't count as checked
+                None => {}
+                Some(at) if now > at => {
+                    self.remove_expired(&key);
+                    expired += 1;
+                    checked += 1;
+                }
+                Some(_) => {
+                    checked += 1;
+                    continue;
+                }
+            }
+            self.volatile.swap_remove(position);
+        }
+        (checked, expired)
+    }
+}
+
+impl Deref for Keyspace {
+    type Target = HashMap<String, Entry, RandomState>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl DerefMut for Keyspace {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+/// The wall clock in UNIX milliseconds, which expirations are checked
+/// against. It never goes back, so keys that expired stay expired when the
+/// system clock is set back.
+fn now_ms() -> u64 {
+    static LATEST: AtomicU64 = AtomicU64::new(0);
+    let now = unix_ms(SystemTime::now());
+    LATEST.fetch_max(now, Ordering::Relaxed).max(now)
+}
+
+/// A wall clock time in UNIX milliseconds, with times before 1970 as 0.
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Looks up a live entry, removing it if it has expired, without counting
+/// it as an access. Replicas keep expired entries for their master to delete.
+fn peek<'a>(keyspace: &'a mut Keyspace, key: &str) -> Option<&'a mut Entry> {
+    if !keyspace.from_master
+        && keyspace
+            .get(key)
+            .is_some_and(|entry| entry.is_expired(now_ms()))
+    {
+        if !keyspace.replica {
+            keyspace.remove_expired(key);
+        }
+        return None;
+    }
+    keyspace.get_mut(key)
+}
+
+/// Looks up a live entry for a command, recording the access.
+fn live<'a>(keyspace: &'a mut Keyspace, key: &str) -> Option<&'a mut Entry> {
+    let entry = peek(keyspace, key)?;
+    entry.touch(Instant::now());
+    Some(entry)
+}
+
+/// The live string at `key`, if there is one.
+fn string_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut Vec<u8>>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+/// The string at `key`, created empty if the key doesn't exist.
+fn string_or_create<'a>(keyspace: &'a mut Keyspace, key: &str) -> Result<&'a mut Vec<u8>, DbError> {
+    if string_mut(keyspace, key)?.is_none() {
+        keyspace.insert(key.to_string(), Entry::new(Value::String(vec![]), None));
+    }
+    match &mut keyspace.get_mut(key).expect("checked above").value {
+        Value::String(value) => Ok(value),
+        _ => unreachable!("checked above"),
+    }
+}
+
+/// Collections never stay in the keyspace empty, except streams, which
+/// keep their last id.
+fn remove_if_empty(keyspace: &mut Keyspace, key: &str) {
+    let empty = keyspace.get(key).is_some_and(|entry| match &entry.value {
+        Value::String(_) | Value::Stream(_) => false,
+        collection => collection.len() == 0,
+    });
+    if empty {
+        keyspace.remove(key);
+    }
+}
+
+/// Resolves a possibly negative LRANGE/ZRANGE-style index pair into a valid
+/// inclusive range, or `None` when it selects nothing.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+/// A number in `0..bound`, from a freshly keyed SipHash since there is no RNG crate.
+fn random(bound: usize) -> usize {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(bound);
+    (hasher.finish() % bound as u64) as usize
+}
+
+/// Random items: `count` distinct ones when positive, `-count` possibly
+/// repeated ones when negative.
+fn sample<T: Copy>(mut items: Vec<T>, count: i64) -> Vec<T> {
+    if items.is_empty() {
+        return items;
+    }
+    if count < 0 {
+        return (0..count.unsigned_abs())
+            .map(|_| items[random(items.len())])
+            .collect();
+    }
+    // partial Fisher-Yates shuffle
+    let count = (count as usize).min(items.len());
+    for i in 0..count {
+        let j = i + random(items.len() - i);
+        items.swap(i, j);
+    }
+    items.truncate(count);
+    items
+}
+
+/// Fields are visited in the order of a fixed FNV-1a hash of their name and
+/// the cursor is the hash to resume from, so a scan survives inserts and
+/// deletes between calls without keeping any state. Zero means done.
+fn scan_position(name: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash.max(1)
 }
 
-pub struct DB(Arc<Mutex<HashMap<String, Entry>>>);
+/// One page of a cursor scan over `items`, returning the next cursor and
+/// at least `count` items when there are enough left. Only the page is
+/// sorted, so a page costs one pass over the items.
+fn scan<'a, T>(
+    items: impl Iterator<Item = (&'a String, T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<(&'a String, T)>) {
+    let mut remaining: Vec<(u64, (&String, T))> = items
+        .map(|item| (scan_position(item.0), item))
+        .filter(|(position, _)| *position >= cursor)
+        .collect();
+    if count == 0 || remaining.len() <= count {
+        return (0, remaining.into_iter().map(|(_, item)| item).collect());
+    }
+    remaining.select_nth_unstable_by_key(count - 1, |(position, _)| *position);
+    // never split items sharing a position across pages
+    let last = remaining[count - 1].0;
+    let (page, rest): (Vec<_>, Vec<_>) = remaining
+        .into_iter()
+        .partition(|(position, _)| *position <= last);
+    let next = rest
+        .iter()
+        .map(|(position, _)| *position)
+        .min()
+        .unwrap_or(0);
+    (next, page.into_iter().map(|(_, item)| item).collect())
+}
+
+pub struct DB {
+    keyspace: Arc<Mutex<Keyspace>>,
+    /// Whether this handle applies the master's stream
+    from_master: bool,
+}
 
 impl DB {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashMap::new())))
+        Self {
+            keyspace: Arc::new(Mutex::new(Keyspace::default())),
+            from_master: false,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Keyspace> {
+        let mut guard = self.keyspace.lock().unwrap();
+        guard.from_master = self.from_master;
+        guard
     }
-    pub fn get(&self, key: &str) -> Option<String> {
-        let guard = self.0.lock().unwrap();
-        match guard.get(key) {
+
+    /// Makes this a replica's dataset, where keys only go away on expiring
+    /// when the master says so.
+    pub fn set_replica(&self, replica: bool) {
+        self.lock().replica = replica;
+    }
+
+    /// A handle for applying the master's stream, which sees the keys a
+    /// replica keeps past their expiry, as the master still had them.
+    pub fn master_view(&self) -> DB {
+        DB {
+            keyspace: self.keyspace.clone(),
+            from_master: true,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        let mut guard = self.lock();
+        match live(&mut guard, key).map(|entry| &entry.value) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    /// SET with its options, returning whether the value was written and,
+    /// with `get`, the old value, which must then be a string.
+    pub fn set_with(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        condition: Option<SetCondition>,
+        expiry: Option<Expiry>,
+        get: bool,
+    ) -> Result<(bool, Option<Vec<u8>>), DbError> {
+        let mut guard = self.lock();
+        let old = live(&mut guard, key);
+        let exists = old.is_some();
+        let kept = old.as_ref().and_then(|entry| entry.expires_at);
+        let old = match old.map(|entry| &entry.value) {
+            Some(Value::String(value)) if get => Some(value.clone()),
+            Some(_) if get => return Err(DbError::WrongType),
+            _ => None,
+        };
+        let allowed = match condition {
+            None => true,
+            Some(SetCondition::IfAbsent) => !exists,
+            Some(SetCondition::IfPresent) => exists,
+        };
+        if !allowed {
+            return Ok((false, old));
+        }
+
+        let expires_at = match expiry {
             None => None,
-            Some(Entry::Simple(value)) => Some(value.clone()),
-            Some(Entry::Expire(value, ex)) => {
-                if &Instant::now() > ex {
-                    None
-                } else {
-                    Some(value.clone())
-                }
+            Some(Expiry::Keep) => kept,
+            Some(Expiry::In(duration)) => {
+                Some(now_ms().saturating_add(duration.as_millis() as u64))
+            }
+            // a time already past leaves the key expired
+            Some(Expiry::At(at)) => Some(unix_ms(at)),
+        };
+        let entry = Entry {
+            expires_at,
+            ..Entry::new(Value::String(value), None)
+        };
+        guard.insert(key.to_string(), entry);
+        Ok((true, old))
+    }
+
+    /// Values of the string keys, `None` for missing keys and other types.
+    pub fn mget(&self, keys: &[String]) -> Vec<Option<Vec<u8>>> {
+        let mut guard = self.lock();
+        keys.iter()
+            .map(
+                |key| match live(&mut guard, key).map(|entry| &entry.value) {
+                    Some(Value::String(value)) => Some(value.clone()),
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
+    /// Sets every pair under one lock, clearing any expirations.
+    pub fn mset(&self, pairs: &[(String, String)]) {
+        let mut guard = self.lock();
+        for (key, value) in pairs {
+            let value = Value::String(value.as_bytes().to_vec());
+            guard.insert(key.clone(), Entry::new(value, None));
+        }
+    }
+
+    /// Sets every pair only when none of the keys exist, returning whether they were set.
+    pub fn mset_if_absent(&self, pairs: &[(String, String)]) -> bool {
+        let mut guard = self.lock();
+        if pairs.iter().any(|(key, _)| live(&mut guard, key).is_some()) {
+            return false;
+        }
+        for (key, value) in pairs {
+            let value = Value::String(value.as_bytes().to_vec());
+            guard.insert(key.clone(), Entry::new(value, None));
+        }
+        true
+    }
+
+    /// Runs `f` on the value of a live string key, keeping its expiration.
+    pub fn update<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Vec<u8>) -> T,
+    ) -> Result<Option<T>, DbError> {
+        let mut guard = self.lock();
+        match live(&mut guard, key).map(|entry| &mut entry.value) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(f(value))),
+            Some(_) => Err(DbError::WrongType),
+        }
+    }
+
+    /// Appends to the string, creating it if missing, and returns its new length.
+    pub fn append(&self, key: &str, value: &[u8]) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let string = string_or_create(&mut guard, key)?;
+        string.extend_from_slice(value);
+        Ok(string.len())
+    }
+
+    pub fn strlen(&self, key: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        Ok(string_mut(&mut guard, key)?.map_or(0, |value| value.len()))
+    }
+
+    /// Adds `delta` to an integer string, treating a missing key as 0.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, DbError> {
+        let mut guard = self.lock();
+        let current = match string_mut(&mut guard, key)? {
+            None => 0,
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<i64>().ok())
+                .ok_or(DbError::NotAnInteger)?,
+        };
+        let next = current.checked_add(delta).ok_or(DbError::Overflow)?;
+        *string_or_create(&mut guard, key)? = next.to_string().into_bytes();
+        Ok(next)
+    }
+
+    /// Adds `delta` to a float string, treating a missing key as 0, and
+    /// returns the stored text: the shortest that reads back as the result,
+    /// without an exponent or trailing zeros.
+    pub fn incr_by_float(&self, key: &str, delta: f64) -> Result<String, DbError> {
+        let mut guard = self.lock();
+        let current = match string_mut(&mut guard, key)? {
+            None => 0.0,
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|value| value.parse::<f64>().ok())
+                .filter(|value| value.is_finite())
+                .ok_or(DbError::NotAFloat)?,
+        };
+        let next = current + delta;
+        if !next.is_finite() {
+            return Err(DbError::NotFinite);
+        }
+        let next = next.to_string();
+        *string_or_create(&mut guard, key)? = next.clone().into_bytes();
+        Ok(next)
+    }
+
+    /// Copies out every live entry with its remaining time to live.
+    pub fn entries(&self) -> Vec<(String, Value, Option<Duration>)> {
+        let mut entries = vec![];
+        self.visit(usize::MAX, |key, value, ttl| {
+            entries.push((key.to_string(), value.clone(), ttl))
+        });
+        entries
+    }
+
+    /// Copies out every live entry with its expiration, for an RDB file.
+    pub fn snapshot(&self) -> Vec<rdb::RdbEntry> {
+        let now = now_ms();
+        self.lock()
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| rdb::RdbEntry {
+                key: key.clone(),
+                value: entry.value.clone(),
+                expires_at: entry.expires_at,
+            })
+            .collect()
+    }
+
+    /// Adds entries read from an RDB file, replacing keys of the same name
+    /// and leaving out those already expired, except on replicas, where the
+    /// master deletes them. Returns how many it added.
+    pub fn load(&self, entries: Vec<rdb::RdbEntry>) -> usize {
+        let now = now_ms();
+        let mut guard = self.lock();
+        let mut loaded = 0;
+        for entry in entries {
+            if !guard.replica && entry.expires_at.is_some_and(|at| at <= now) {
+                continue;
+            }
+            let value = Entry {
+                expires_at: entry.expires_at,
+                ..Entry::new(entry.value, None)
+            };
+            guard.insert(entry.key, value);
+            loaded += 1;
+        }
+        loaded
+    }
+
+    /// Calls `f` with up to `limit` live entries and their remaining time to live,
+    /// without copying values out of the map.
+    pub fn visit(&self, limit: usize, mut f: impl FnMut(&str, &Value, Option<Duration>)) {
+        let now = now_ms();
+        let guard = self.lock();
+        let live = guard
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .take(limit);
+        for (key, entry) in live {
+            let ttl = entry.expires_at.map(|ex| Duration::from_millis(ex - now));
+            f(key, &entry.value, ttl);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Removes a key, returning whether it was live.
+    pub fn remove(&self, key: &str) -> bool {
+        let mut guard = self.lock();
+        peek(&mut guard, key).is_some() && guard.remove(key).is_some()
+    }
+
+    /// Keys that expired since the last call, in order, for replicas to
+    /// delete too.
+    pub fn take_expired(&self) -> Vec<String> {
+        std::mem::take(&mut self.lock().expired)
+    }
+
+    /// How many keys have expired since startup.
+    pub fn expired_keys(&self) -> u64 {
+        self.lock().expired_keys
+    }
+
+    /// Sets the key's expiration when `flags` allow, returning whether
+    /// it did. A time that has already passed removes the key.
+    pub fn expire(&self, key: &str, at: SystemTime, flags: ExpireFlags) -> bool {
+        let mut guard = self.lock();
+        let Some(entry) = live(&mut guard, key) else {
+            return false;
+        };
+        let now = now_ms();
+        let at = unix_ms(at);
+        let allowed = match entry.expires_at {
+            None => !flags.xx && !flags.gt,
+            Some(current) => {
+                !flags.nx && (!flags.gt || at > current) && (!flags.lt || at < current)
+            }
+        };
+        if !allowed {
+            return false;
+        }
+        if at <= now {
+            guard.remove(key);
+        } else {
+            entry.expires_at = Some(at);
+            guard.volatile.add(key);
+        }
+        true
+    }
+
+    /// One cycle of active expiry: rounds of sampling volatile keys and
+    /// removing the expired ones, going on while many had expired and
+    /// `budget` isn't spent. `effort` from 1 to 10 samples more keys and
+    /// settles for fewer expired ones. Returns how many it removed.
+    pub fn expire_cycle(&self, effort: usize, budget: Duration) -> usize {
+        let effort = effort.clamp(1, 10) - 1;
+        let sample = EXPIRE_SAMPLE + EXPIRE_SAMPLE / 4 * effort;
+        let stale_percent = EXPIRE_STALE_PERCENT - effort;
+        let start = Instant::now();
+        let mut removed = 0;
+        loop {
+            // the lock is let go between rounds, so clients get a turn
+            let (checked, expired) = self.lock().expire_sample(sample, now_ms());
+            removed += expired;
+            if checked == 0 || expired * 100 <= checked * stale_percent || start.elapsed() > budget
+            {
+                break removed;
             }
         }
     }
 
-    pub fn set(&self, key: String, value: String, ex: Option<Duration>) {
-        let entry = match ex {
-            None => Entry::Simple(value),
-            Some(duration) => Entry::Expire(value, Instant::now() + duration),
+    /// Every live key matching the glob pattern.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let now = now_ms();
+        self.lock()
+            .iter()
+            .filter(|(key, entry)| !entry.is_expired(now) && glob::matches(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// One page of a keyspace scan, like `hscan`, optionally only keys of
+    /// the type with that name.
+    pub fn scan_keys(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let now = now_ms();
+        let guard = self.lock();
+        let live = guard
+            .iter()
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(key, entry)| (key, &entry.value));
+        let (cursor, page) = scan(live, cursor, count);
+        let page = page
+            .into_iter()
+            .filter(|(key, _)| match pattern {
+                None => true,
+                Some(pattern) => glob::matches(pattern, key),
+            })
+            .filter(|(_, value)| match type_name {
+                None => true,
+                Some(name) => value.type_name() == name,
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        (cursor, page)
+    }
+
+    /// Copies the value and expiration of `source` to `destination`, which
+    /// must not exist unless `replace` is set. Returns whether it copied.
+    pub fn copy(&self, source: &str, destination: &str, replace: bool) -> bool {
+        let mut guard = self.lock();
+        let Some(entry) = live(&mut guard, source) else {
+            return false;
+        };
+        let copy = Entry {
+            expires_at: entry.expires_at,
+            ..Entry::new(entry.value.clone(), None)
+        };
+        if !replace && live(&mut guard, destination).is_some() {
+            return false;
+        }
+        guard.insert(destination.to_string(), copy);
+        true
+    }
+
+    /// The value at `key` serialized for RESTORE, `None` if there is no such key.
+    pub fn dump(&self, key: &str) -> Option<Vec<u8>> {
+        let mut guard = self.lock();
+        live(&mut guard, key).map(|entry| rdb::dump(&entry.value))
+    }
+
+    /// Recreates a key from a DUMP payload. It only replaces an existing key
+    /// with `replace`, and an expiration already past leaves no key at all.
+    pub fn restore(
+        &self,
+        key: &str,
+        payload: &[u8],
+        at: Option<SystemTime>,
+        replace: bool,
+    ) -> Result<(), DbError> {
+        let body = rdb::undump(payload).ok_or(DbError::BadDumpPayload)?;
+        let value = rdb::read_value(body).map_err(|_| DbError::BadDataFormat)?;
+        if value.len() == 0 && !matches!(value, Value::String(_) | Value::Stream(_)) {
+            return Err(DbError::BadDataFormat);
+        }
+        if !self.put(key, value, at, replace) {
+            return Err(DbError::BusyKey);
+        }
+        Ok(())
+    }
+
+    /// Stores a whole value, expiring `at` if given, as RESTORE and IMPORT
+    /// do. False if the key exists and may not be replaced.
+    pub fn put(&self, key: &str, value: Value, at: Option<SystemTime>, replace: bool) -> bool {
+        let mut guard = self.lock();
+        if !replace && peek(&mut guard, key).is_some() {
+            return false;
+        }
+        let at = at.map(unix_ms);
+        if at.is_some_and(|at| at <= now_ms()) {
+            guard.remove(key);
+            return true;
+        }
+        let entry = Entry {
+            expires_at: at,
+            ..Entry::new(value, None)
         };
-        self.0.lock().unwrap().insert(key, entry);
+        guard.insert(key.to_string(), entry);
+        true
+    }
+
+    /// The type of the value at `key`, `None` if there is no such key.
+    pub fn type_of(&self, key: &str) -> Option<&'static str> {
+        let mut guard = self.lock();
+        peek(&mut guard, key).map(|entry| entry.value.type_name())
+    }
+
+    /// OBJECT: how the value at `key` is stored and accessed, without
+    /// counting as an access itself.
+    pub fn object(&self, key: &str) -> Option<ObjectInfo> {
+        let now = Instant::now();
+        let mut guard = self.lock();
+        peek(&mut guard, key).map(|entry| ObjectInfo {
+            encoding: entry.encoding(),
+            idle: now.duration_since(entry.accessed_at),
+            frequency: entry.frequency(now),
+        })
+    }
+
+    /// How many of the keys are live, counting a key given twice twice.
+    pub fn exists(&self, keys: &[String]) -> usize {
+        let mut guard = self.lock();
+        keys.iter()
+            .filter(|key| peek(&mut guard, key).is_some())
+            .count()
+    }
+
+    /// Records an access to each of the keys, returning how many are live.
+    pub fn touch(&self, keys: &[String]) -> usize {
+        let mut guard = self.lock();
+        keys.iter()
+            .filter(|key| live(&mut guard, key).is_some())
+            .count()
+    }
+
+    /// Removes the keys, returning their live entries.
+    fn take(&self, keys: &[String]) -> Vec<Entry> {
+        let mut guard = self.lock();
+        keys.iter()
+            .filter_map(|key| {
+                peek(&mut guard, key)?;
+                guard.remove(key)
+            })
+            .collect()
+    }
+
+    /// DEL: removes the keys and returns how many were live.
+    pub fn remove_all(&self, keys: &[String]) -> usize {
+        self.take(keys).len()
+    }
+
+    /// UNLINK: like `remove_all`, but big values are freed on another thread
+    /// instead of by the caller.
+    pub fn unlink(&self, keys: &[String]) -> usize {
+        let entries = self.take(keys);
+        let removed = entries.len();
+        let elements: usize = entries.iter().map(|entry| entry.value.len()).sum();
+        if elements > LAZY_FREE_THRESHOLD {
+            std::thread::spawn(move || drop(entries));
+        }
+        removed
     }
 }
 
 impl Clone for DB {
     fn clone(&self) -> Self {
-        DB(self.0.clone())
+        DB {
+            keyspace: self.keyspace.clone(),
+            from_master: self.from_master,
+        }
     }
 }
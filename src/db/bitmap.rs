@@ -0,0 +1,211 @@
+use super::{string_mut, string_or_create, DbError, DB};
+
+/// The byte holding bit `offset`, and the mask of that bit. Bit 0 is the
+/// most significant bit of the first byte, like Redis.
+fn position(offset: u64) -> (usize, u8) {
+    ((offset / 8) as usize, 0x80 >> (offset % 8))
+}
+
+/// Resolves a BITCOUNT range over `len` units like Redis: negative indexes
+/// count from the end and are clamped rather than emptying the range.
+fn clamp(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let resolve = |index: i64| {
+        if index < 0 {
+            (len + index).max(0)
+        } else {
+            index
+        }
+    };
+    let (start, end) = (resolve(start), resolve(end).min(len - 1));
+    if start > end {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
+
+/// An integer of `bits` bits at a bit offset, as BITFIELD addresses it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitField {
+    pub signed: bool,
+    pub bits: u32,
+    pub offset: u64,
+}
+
+/// What BITFIELD SET and INCRBY do with a result that doesn't fit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitFieldOp {
+    Get(BitField),
+    Set(BitField, i64, Overflow),
+    IncrBy(BitField, i64, Overflow),
+}
+
+impl BitField {
+    fn read(&self, value: &[u8]) -> i64 {
+        let mut result: u64 = 0;
+        for offset in self.offset..self.offset + self.bits as u64 {
+            let (byte, mask) = position(offset);
+            let bit = value.get(byte).is_some_and(|byte| byte & mask != 0);
+            result = result << 1 | bit as u64;
+        }
+        if self.signed && self.bits < 64 && result >> (self.bits - 1) & 1 == 1 {
+            // sign extend
+            result |= u64::MAX << self.bits;
+        }
+        result as i64
+    }
+
+    /// Writes the low `bits` bits of `field`, the string being long enough.
+    fn write(&self, value: &mut [u8], field: i64) {
+        for (i, offset) in (self.offset..self.offset + self.bits as u64).enumerate() {
+            let (byte, mask) = position(offset);
+            if (field as u64) >> (self.bits as usize - 1 - i) & 1 == 1 {
+                value[byte] |= mask;
+            } else {
+                value[byte] &= !mask;
+            }
+        }
+    }
+
+    /// Fits `target` into the field, or `None` when it overflows with FAIL.
+    fn fit(&self, target: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = if self.signed {
+            (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1)
+        } else {
+            (0, (1i128 << self.bits) - 1)
+        };
+        if (min..=max).contains(&target) {
+            return Some(target as i64);
+        }
+        match overflow {
+            Overflow::Fail => None,
+            Overflow::Sat => Some(target.clamp(min, max) as i64),
+            Overflow::Wrap => {
+                let wrapped = target.rem_euclid(1i128 << self.bits);
+                Some(if wrapped > max {
+                    (wrapped - (1i128 << self.bits)) as i64
+                } else {
+                    wrapped as i64
+                })
+            }
+        }
+    }
+}
+
+impl DB {
+    /// Sets or clears the bit at `offset`, growing the string with zero bytes
+    /// as needed, and returns the bit's previous value.
+    pub fn setbit(&self, key: &str, offset: u64, bit: bool) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        let value = string_or_create(&mut guard, key)?;
+        let (byte, mask) = position(offset);
+        if value.len() <= byte {
+            value.resize(byte + 1, 0);
+        }
+        let old = value[byte] & mask != 0;
+        if bit {
+            value[byte] |= mask;
+        } else {
+            value[byte] &= !mask;
+        }
+        Ok(old)
+    }
+
+    /// The bit at `offset`, 0 past the end of the string or for a missing key.
+    pub fn getbit(&self, key: &str, offset: u64) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        let (byte, mask) = position(offset);
+        Ok(string_mut(&mut guard, key)?
+            .and_then(|value| value.get(byte))
+            .is_some_and(|byte| byte & mask != 0))
+    }
+
+    /// Set bits in the whole string, or between two byte or bit indexes,
+    /// both included.
+    pub fn bitcount(
+        &self,
+        key: &str,
+        range: Option<(i64, i64)>,
+        bits: bool,
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(value) = string_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let ones = |bytes: &[u8]| bytes.iter().map(|byte| byte.count_ones() as usize).sum();
+        let Some((start, end)) = range else {
+            return Ok(ones(value));
+        };
+        if !bits {
+            return Ok(
+                clamp(start, end, value.len()).map_or(0, |(start, end)| ones(&value[start..=end]))
+            );
+        }
+        let Some((start, end)) = clamp(start, end, value.len() * 8) else {
+            return Ok(0);
+        };
+        let (first, last) = (start / 8, end / 8);
+        // whole bytes, minus the bits before `start` and after `end`
+        let before = value[first] & !(0xff >> (start % 8));
+        let after = value[last] & !(0xff << (7 - end % 8));
+        Ok(ones(&value[first..=last]) - ones(&[before, after]))
+    }
+
+    /// Runs the BITFIELD operations in order, replying for each with the
+    /// value read, the previous value set, or the incremented value, and
+    /// `None` where OVERFLOW FAIL stopped a write. Only writes create the key.
+    pub fn bitfield(&self, key: &str, ops: &[BitFieldOp]) -> Result<Vec<Option<i64>>, DbError> {
+        let mut guard = self.lock();
+        let end = ops
+            .iter()
+            .filter_map(|op| match op {
+                BitFieldOp::Get(_) => None,
+                BitFieldOp::Set(field, ..) | BitFieldOp::IncrBy(field, ..) => {
+                    Some(field.offset + field.bits as u64)
+                }
+            })
+            .max();
+        let Some(end) = end else {
+            let value = string_mut(&mut guard, key)?.map_or(&[][..], |value| &value[..]);
+            return Ok(ops
+                .iter()
+                .map(|op| match op {
+                    BitFieldOp::Get(field) => Some(field.read(value)),
+                    _ => unreachable!("only reads"),
+                })
+                .collect());
+        };
+
+        let value = string_or_create(&mut guard, key)?;
+        let len = end.div_ceil(8) as usize;
+        if value.len() < len {
+            value.resize(len, 0);
+        }
+        Ok(ops
+            .iter()
+            .map(|op| match *op {
+                BitFieldOp::Get(field) => Some(field.read(value)),
+                BitFieldOp::Set(field, new, overflow) => {
+                    let old = field.read(value);
+                    let new = field.fit(new as i128, overflow)?;
+                    field.write(value, new);
+                    Some(old)
+                }
+                BitFieldOp::IncrBy(field, increment, overflow) => {
+                    let old = field.read(value);
+                    let new = field.fit(old as i128 + increment as i128, overflow)?;
+                    field.write(value, new);
+                    Some(new)
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,349 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use super::zset::{zset_mut, SortedSet};
+use super::{DbError, Entry, Value, DB};
+
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+/// The limits of Web Mercator, as in Redis, so hashes cover a square map
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+
+/// Bits per coordinate in a full precision hash, which then fits the 52-bit
+/// mantissa of a sorted set score.
+const STEP: u32 = 26;
+const EARTH_RADIUS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+/// Where a GEOSEARCH is centered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoFrom {
+    Member(String),
+    LonLat(f64, f64),
+}
+
+/// The area a GEOSEARCH covers, in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+/// Which way GEOSEARCH sorts by distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoOrder {
+    Asc,
+    Desc,
+}
+
+/// What a GEOSEARCH looks for. With a count only that many are found, the
+/// closest unless `any` takes the first found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoQuery {
+    pub from: GeoFrom,
+    pub shape: GeoShape,
+    pub order: Option<GeoOrder>,
+    pub count: Option<usize>,
+    pub any: bool,
+}
+
+#[derive(Debug)]
+pub struct GeoMatch {
+    pub member: String,
+    /// meters from the center
+    pub distance: f64,
+    pub hash: u64,
+    pub lon: f64,
+    pub lat: f64,
+}
+
+/// Spreads the low 32 bits of `x` over the even bits of the result.
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000ffff0000ffff;
+    x = (x | x << 8) & 0x00ff00ff00ff00ff;
+    x = (x | x << 4) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | x << 2) & 0x3333333333333333;
+    (x | x << 1) & 0x5555555555555555
+}
+
+/// Gathers the even bits of `x`, undoing `spread`.
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555555555555555;
+    x = (x | x >> 1) & 0x3333333333333333;
+    x = (x | x >> 2) & 0x0f0f0f0f0f0f0f0f;
+    x = (x | x >> 4) & 0x00ff00ff00ff00ff;
+    x = (x | x >> 8) & 0x0000ffff0000ffff;
+    (x | x >> 16) as u32
+}
+
+/// The cells of a `step` bits grid holding the coordinates.
+fn cells(lon: f64, lat: f64, step: u32) -> (u32, u32) {
+    let scale = (1u64 << step) as f64;
+    let lon = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * scale) as u64;
+    let lat = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * scale) as u64;
+    let last = (1u64 << step) - 1;
+    (lon.min(last) as u32, lat.min(last) as u32)
+}
+
+/// Interleaves grid cells into a hash, latitude on the even bits.
+fn interleave(lon: u32, lat: u32) -> u64 {
+    spread(lat) | spread(lon) << 1
+}
+
+/// Whether GEOADD can index the coordinates.
+pub fn in_bounds(lon: f64, lat: f64) -> bool {
+    (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat)
+}
+
+/// The 52-bit geohash that GEOADD stores as the score.
+pub fn geohash(lon: f64, lat: f64) -> u64 {
+    let (lon, lat) = cells(lon, lat, STEP);
+    interleave(lon, lat)
+}
+
+/// The center of the cell a stored hash names.
+fn decode(hash: u64) -> (f64, f64) {
+    let scale = (1u64 << STEP) as f64;
+    let center = |cell: u32, min: f64, max: f64| {
+        let low = min + cell as f64 / scale * (max - min);
+        let high = min + (cell as f64 + 1.0) / scale * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (
+        center(squash(hash >> 1), LON_MIN, LON_MAX),
+        center(squash(hash), LAT_MIN, LAT_MAX),
+    )
+}
+
+/// Great circle distance in meters, by the haversine formula.
+fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1).to_radians() / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+impl GeoShape {
+    /// The distance to a point inside the shape, `None` if it's outside.
+    fn contains(&self, (lon, lat): (f64, f64), (x, y): (f64, f64)) -> Option<f64> {
+        match *self {
+            GeoShape::Radius(radius) => {
+                Some(distance(lon, lat, x, y)).filter(|distance| *distance <= radius)
+            }
+            GeoShape::Box { width, height } => {
+                // measured along the meridian, then along the point's parallel
+                if EARTH_RADIUS * (y - lat).to_radians().abs() > height / 2.0
+                    || distance(lon, y, x, y) > width / 2.0
+                {
+                    return None;
+                }
+                Some(distance(lon, lat, x, y))
+            }
+        }
+    }
+
+    fn radius(&self) -> f64 {
+        match *self {
+            GeoShape::Radius(radius) => radius,
+            GeoShape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+        }
+    }
+
+    /// Longitude and latitude bounds around the center that hold the shape.
+    fn bounds(&self, lon: f64, lat: f64) -> (f64, f64, f64, f64) {
+        let (half_width, half_height) = match *self {
+            GeoShape::Radius(radius) => (radius, radius),
+            GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let lat_delta = (half_height / EARTH_RADIUS).to_degrees();
+        // parallels are shortest at the edge nearest a pole
+        let widest = (lat.abs() + lat_delta).min(90.0).to_radians();
+        let lon_delta = (half_width / (EARTH_RADIUS * widest.cos())).to_degrees();
+        (
+            lon - lon_delta,
+            lat - lat_delta,
+            lon + lon_delta,
+            lat + lat_delta,
+        )
+    }
+}
+
+/// The grid precision whose cells are about as large as the search radius.
+fn estimate_step(radius: f64, lat: f64) -> u32 {
+    if radius == 0.0 {
+        return STEP;
+    }
+    let mut radius = radius;
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    // cells narrow towards the poles
+    if lat.abs() > 66.0 {
+        step -= 1;
+        if lat.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, STEP as i32) as u32
+}
+
+/// Score ranges covering the shape: the cell of the center and the eight
+/// around it, on a grid coarse enough that together they hold the shape.
+fn areas(lon: f64, lat: f64, shape: &GeoShape) -> Vec<(u64, u64)> {
+    let (min_lon, min_lat, max_lon, max_lat) = shape.bounds(lon, lat);
+    let mut step = estimate_step(shape.radius(), lat);
+    let (x, y) = loop {
+        let (x, y) = cells(lon, lat, step);
+        let width = (LON_MAX - LON_MIN) / (1u64 << step) as f64;
+        let height = (LAT_MAX - LAT_MIN) / (1u64 << step) as f64;
+        let covered = LON_MIN + (x as f64 - 1.0) * width <= min_lon
+            && LON_MIN + (x as f64 + 2.0) * width >= max_lon
+            && LAT_MIN + (y as f64 - 1.0) * height <= min_lat
+            && LAT_MIN + (y as f64 + 2.0) * height >= max_lat;
+        if covered || step == 1 {
+            break (x, y);
+        }
+        step -= 1;
+    };
+
+    let size = 1i64 << step;
+    let shift = 2 * (STEP - step);
+    let mut areas = vec![];
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            // the grid wraps around, which at worst searches a cell twice over
+            let x = (x as i64 + dx).rem_euclid(size) as u32;
+            let y = (y as i64 + dy).rem_euclid(size) as u32;
+            let hash = interleave(x, y);
+            areas.push((hash << shift, (hash + 1) << shift));
+        }
+    }
+    areas.sort_unstable();
+    areas.dedup();
+    areas
+}
+
+impl DB {
+    /// Longitude and latitude of each member, from its stored hash.
+    pub fn geopos(
+        &self,
+        key: &str,
+        members: &[String],
+    ) -> Result<Vec<Option<(f64, f64)>>, DbError> {
+        Ok(self
+            .zmscore(key, members)?
+            .into_iter()
+            .map(|score| score.map(|score| decode(score as u64)))
+            .collect())
+    }
+
+    /// Meters between two members, `None` if either is missing.
+    pub fn geodist(&self, key: &str, from: &str, to: &str) -> Result<Option<f64>, DbError> {
+        let scores = self.zmscore(key, &[from.to_string(), to.to_string()])?;
+        let [Some(from), Some(to)] = scores[..] else {
+            return Ok(None);
+        };
+        let ((lon1, lat1), (lon2, lat2)) = (decode(from as u64), decode(to as u64));
+        Ok(Some(distance(lon1, lat1, lon2, lat2)))
+    }
+
+    /// Members matching the query.
+    pub fn geosearch(&self, key: &str, query: &GeoQuery) -> Result<Vec<GeoMatch>, DbError> {
+        let mut guard = self.lock();
+        match zset_mut(&mut guard, key)? {
+            None => Ok(vec![]),
+            Some(zset) => search(zset, query),
+        }
+    }
+
+    /// Stores the members matching the query in `destination`, scored by
+    /// their hash or, given `dist_unit`, their distance in that unit.
+    /// Returns how many there are; none leaves no destination.
+    pub fn geosearchstore(
+        &self,
+        destination: &str,
+        key: &str,
+        query: &GeoQuery,
+        dist_unit: Option<f64>,
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let matches = match zset_mut(&mut guard, key)? {
+            None => vec![],
+            Some(zset) => search(zset, query)?,
+        };
+        let len = matches.len();
+        if len == 0 {
+            guard.remove(destination);
+        } else {
+            let zset = matches
+                .into_iter()
+                .map(|found| match dist_unit {
+                    Some(unit) => (found.member, found.distance / unit),
+                    None => (found.member, found.hash as f64),
+                })
+                .collect();
+            guard.insert(destination.to_string(), Entry::new(Value::ZSet(zset), None));
+        }
+        Ok(len)
+    }
+}
+
+fn search(zset: &SortedSet, query: &GeoQuery) -> Result<Vec<GeoMatch>, DbError> {
+    let GeoQuery {
+        from,
+        shape,
+        order,
+        count,
+        any,
+    } = query;
+    let center = match from {
+        GeoFrom::LonLat(lon, lat) => (*lon, *lat),
+        GeoFrom::Member(member) => decode(zset.score(member).ok_or(DbError::NoSuchMember)? as u64),
+    };
+
+    let limit = if *any { *count } else { None };
+    let mut matches = vec![];
+    'search: for (min, max) in areas(center.0, center.1, shape) {
+        let members = zset.range_by_score(Bound::Included(min as f64), Bound::Excluded(max as f64));
+        for (member, score) in members {
+            let hash = score as u64;
+            let point = decode(hash);
+            let Some(distance) = shape.contains(center, point) else {
+                continue;
+            };
+            matches.push(GeoMatch {
+                member: member.to_string(),
+                distance,
+                hash,
+                lon: point.0,
+                lat: point.1,
+            });
+            if limit.is_some_and(|limit| matches.len() >= limit) {
+                break 'search;
+            }
+        }
+    }
+
+    // a count without ANY wants the closest members
+    let order = match order {
+        None if count.is_some() && !any => Some(GeoOrder::Asc),
+        order => *order,
+    };
+    let by_distance =
+        |a: &GeoMatch, b: &GeoMatch| -> Ordering { a.distance.total_cmp(&b.distance) };
+    match order {
+        Some(GeoOrder::Asc) => matches.sort_by(by_distance),
+        Some(GeoOrder::Desc) => matches.sort_by(|a, b| by_distance(b, a)),
+        None => {}
+    }
+    if let Some(count) = count {
+        matches.truncate(*count);
+    }
+    Ok(matches)
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use super::{live, remove_if_empty, sample, scan, DbError, Entry, Keyspace, Value, DB};
+use crate::glob;
+
+/// The live hash at `key`, if there is one.
+fn hash_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut HashMap<String, String>>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+/// The hash at `key`, created empty if the key doesn't exist.
+fn hash_or_create<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<&'a mut HashMap<String, String>, DbError> {
+    if hash_mut(keyspace, key)?.is_none() {
+        let entry = Entry::new(Value::Hash(HashMap::new()), None);
+        keyspace.insert(key.to_string(), entry);
+    }
+    match &mut keyspace.get_mut(key).expect("checked above").value {
+        Value::Hash(hash) => Ok(hash),
+        _ => unreachable!("checked above"),
+    }
+}
+
+impl DB {
+    /// Sets the given fields, returning how many of them are new.
+    pub fn hset(&self, key: &str, pairs: &[(String, String)]) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let hash = hash_or_create(&mut guard, key)?;
+        let added = pairs
+            .iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count();
+        Ok(added)
+    }
+
+    /// Sets the field only when it doesn't exist yet, returning whether it was set.
+    pub fn hsetnx(&self, key: &str, field: &str, value: &str) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        let hash = hash_or_create(&mut guard, key)?;
+        if hash.contains_key(field) {
+            return Ok(false);
+        }
+        hash.insert(field.to_string(), value.to_string());
+        Ok(true)
+    }
+
+    /// Adds `delta` to an integer field, treating a missing field as 0.
+    pub fn hincrby(&self, key: &str, field: &str, delta: i64) -> Result<i64, DbError> {
+        let mut guard = self.lock();
+        let hash = hash_or_create(&mut guard, key)?;
+        let current = match hash.get(field) {
+            None => 0,
+            Some(value) => value.parse::<i64>().map_err(|_| DbError::HashNotInteger)?,
+        };
+        let next = current.checked_add(delta).ok_or(DbError::Overflow)?;
+        hash.insert(field.to_string(), next.to_string());
+        Ok(next)
+    }
+
+    /// Adds `delta` to a float field, treating a missing field as 0, and
+    /// returns the stored text.
+    pub fn hincrbyfloat(&self, key: &str, field: &str, delta: f64) -> Result<String, DbError> {
+        let mut guard = self.lock();
+        let hash = hash_or_create(&mut guard, key)?;
+        let current = match hash.get(field) {
+            None => 0.0,
+            Some(value) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or(DbError::HashNotFloat)?,
+        };
+        let next = current + delta;
+        if !next.is_finite() {
+            return Err(DbError::NotFinite);
+        }
+        let next = next.to_string();
+        hash.insert(field.to_string(), next.clone());
+        Ok(next)
+    }
+
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<String>, DbError> {
+        let mut guard = self.lock();
+        Ok(hash_mut(&mut guard, key)?.and_then(|hash| hash.get(field).cloned()))
+    }
+
+    pub fn hmget(&self, key: &str, fields: &[String]) -> Result<Vec<Option<String>>, DbError> {
+        let mut guard = self.lock();
+        let hash = hash_mut(&mut guard, key)?;
+        Ok(fields
+            .iter()
+            .map(|field| hash.as_ref().and_then(|hash| hash.get(field).cloned()))
+            .collect())
+    }
+
+    /// Removes the given fields, deleting the hash once it is empty, and
+    /// returns how many existed.
+    pub fn hdel(&self, key: &str, fields: &[String]) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(hash) = hash_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        remove_if_empty(&mut guard, key);
+        Ok(removed)
+    }
+
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, String)>, DbError> {
+        let mut guard = self.lock();
+        Ok(hash_mut(&mut guard, key)?
+            .map(|hash| {
+                hash.iter()
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    pub fn hexists(&self, key: &str, field: &str) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        Ok(hash_mut(&mut guard, key)?.is_some_and(|hash| hash.contains_key(field)))
+    }
+
+    pub fn hlen(&self, key: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        Ok(hash_mut(&mut guard, key)?.map_or(0, |hash| hash.len()))
+    }
+
+    pub fn hkeys(&self, key: &str) -> Result<Vec<String>, DbError> {
+        Ok(self
+            .hgetall(key)?
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect())
+    }
+
+    pub fn hvals(&self, key: &str) -> Result<Vec<String>, DbError> {
+        Ok(self
+            .hgetall(key)?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    /// One page of fields from `cursor` on, filtered by `pattern` after
+    /// `count` fields were looked at, like Redis does.
+    pub fn hscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+    ) -> Result<(u64, Vec<(String, String)>), DbError> {
+        let mut guard = self.lock();
+        let Some(hash) = hash_mut(&mut guard, key)? else {
+            return Ok((0, vec![]));
+        };
+        let (cursor, page) = scan(hash.iter(), cursor, count);
+        let page = page
+            .into_iter()
+            .filter(|(field, _)| match pattern {
+                None => true,
+                Some(pattern) => glob::matches(pattern, field),
+            })
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect();
+        Ok((cursor, page))
+    }
+
+    /// Random fields with their values: `count` distinct ones when positive,
+    /// `-count` possibly repeated ones when negative.
+    pub fn hrandfield(&self, key: &str, count: i64) -> Result<Vec<(String, String)>, DbError> {
+        let mut guard = self.lock();
+        let Some(hash) = hash_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        Ok(sample(hash.iter().collect(), count)
+            .into_iter()
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect())
+    }
+}
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+
+use super::{live, normalize_range, remove_if_empty, DbError, Entry, Keyspace, Value, DB};
+
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            End::Left => "left",
+            End::Right => "right",
+        }
+    }
+}
+
+/// The live list at `key`, if there is one.
+fn list_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut VecDeque<String>>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::List(list)) => Ok(Some(list)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+/// The list at `key`, created empty if the key doesn't exist.
+fn list_or_create<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<&'a mut VecDeque<String>, DbError> {
+    if list_mut(keyspace, key)?.is_none() {
+        let entry = Entry::new(Value::List(VecDeque::new()), None);
+        keyspace.insert(key.to_string(), entry);
+    }
+    match &mut keyspace.get_mut(key).expect("checked above").value {
+        Value::List(list) => Ok(list),
+        _ => unreachable!("checked above"),
+    }
+}
+
+impl DB {
+    /// Pushes values one by one onto the given end, creating the list if
+    /// needed, and returns the new length.
+    pub fn push(&self, key: &str, values: &[String], end: End) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let list = list_or_create(&mut guard, key)?;
+        for value in values {
+            match end {
+                End::Left => list.push_front(value.clone()),
+                End::Right => list.push_back(value.clone()),
+            }
+        }
+        Ok(list.len())
+    }
+
+    /// Pops up to `count` values from the given end, deleting the list once
+    /// it is empty. `None` when the key doesn't exist.
+    pub fn pop(&self, key: &str, count: usize, end: End) -> Result<Option<Vec<String>>, DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(None);
+        };
+        let count = count.min(list.len());
+        let popped = match end {
+            End::Left => list.drain(..count).collect(),
+            End::Right => list.drain(list.len() - count..).rev().collect(),
+        };
+        remove_if_empty(&mut guard, key);
+        Ok(Some(popped))
+    }
+
+    /// Pops up to `count` values from the first of `keys` holding a list.
+    pub fn pop_first(
+        &self,
+        keys: &[String],
+        count: usize,
+        end: End,
+    ) -> Result<Option<(String, Vec<String>)>, DbError> {
+        for key in keys {
+            if let Some(values) = self.pop(key, count, end)? {
+                return Ok(Some((key.clone(), values)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Atomically pops from one end of `source` and pushes onto one end of
+    /// `destination`, returning the moved element. `None` when `source` doesn't exist.
+    pub fn lmove(
+        &self,
+        source: &str,
+        destination: &str,
+        from: End,
+        to: End,
+    ) -> Result<Option<String>, DbError> {
+        let mut guard = self.lock();
+        if list_mut(&mut guard, source)?.is_none() {
+            return Ok(None);
+        }
+        // fail before touching the source if the element would have nowhere to go
+        list_mut(&mut guard, destination)?;
+
+        let list = list_mut(&mut guard, source)?.expect("checked above");
+        let Some(element) = (match from {
+            End::Left => list.pop_front(),
+            End::Right => list.pop_back(),
+        }) else {
+            return Ok(None);
+        };
+        remove_if_empty(&mut guard, source);
+
+        let list = list_or_create(&mut guard, destination)?;
+        match to {
+            End::Left => list.push_front(element.clone()),
+            End::Right => list.push_back(element.clone()),
+        }
+        Ok(Some(element))
+    }
+
+    /// Indexes of the elements equal to `element`, skipping the first `rank - 1`
+    /// matches and scanning from the tail when `rank` is negative. A zero
+    /// `count` or `maxlen` means no limit.
+    pub fn lpos(
+        &self,
+        key: &str,
+        element: &str,
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>, DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        let count = if count == 0 { usize::MAX } else { count };
+        let maxlen = if maxlen == 0 { usize::MAX } else { maxlen };
+        let skip = (rank.unsigned_abs() - 1) as usize;
+
+        let indexes: Box<dyn Iterator<Item = usize>> = if rank < 0 {
+            Box::new((0..list.len()).rev())
+        } else {
+            Box::new(0..list.len())
+        };
+        Ok(indexes
+            .take(maxlen)
+            .filter(|index| list[*index] == element)
+            .skip(skip)
+            .take(count)
+            .collect())
+    }
+
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<String>, DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        match normalize_range(start, stop, list.len()) {
+            None => Ok(vec![]),
+            Some((start, stop)) => Ok(list.range(start..=stop).cloned().collect()),
+        }
+    }
+
+    pub fn llen(&self, key: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        Ok(list_mut(&mut guard, key)?.map_or(0, |list| list.len()))
+    }
+
+    /// Inserts `element` next to the first `pivot`, returning the new length,
+    /// -1 when the pivot isn't there and 0 when the key doesn't exist.
+    pub fn linsert(
+        &self,
+        key: &str,
+        before: bool,
+        pivot: &str,
+        element: &str,
+    ) -> Result<i64, DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let Some(index) = list.iter().position(|item| item == pivot) else {
+            return Ok(-1);
+        };
+        let index = if before { index } else { index + 1 };
+        list.insert(index, element.to_string());
+        Ok(list.len() as i64)
+    }
+
+    pub fn lset(&self, key: &str, index: i64, element: &str) -> Result<(), DbError> {
+        let mut guard = self.lock();
+        let list = list_mut(&mut guard, key)?.ok_or(DbError::NoSuchKey)?;
+        let index = if index < 0 {
+            list.len() as i64 + index
+        } else {
+            index
+        };
+        let item = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get_mut(index))
+            .ok_or(DbError::OutOfRange)?;
+        *item = element.to_string();
+        Ok(())
+    }
+
+    /// Removes up to `count` occurrences of `element`, scanning from the head
+    /// when positive, from the tail when negative and removing all when zero.
+    pub fn lrem(&self, key: &str, count: i64, element: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let limit = match count.unsigned_abs() {
+            0 => usize::MAX,
+            limit => limit as usize,
+        };
+        let mut removed = 0;
+        let mut kept: VecDeque<String> = VecDeque::with_capacity(list.len());
+        if count < 0 {
+            while let Some(item) = list.pop_back() {
+                if removed < limit && item == element {
+                    removed += 1;
+                } else {
+                    kept.push_front(item);
+                }
+            }
+        } else {
+            while let Some(item) = list.pop_front() {
+                if removed < limit && item == element {
+                    removed += 1;
+                } else {
+                    kept.push_back(item);
+                }
+            }
+        }
+        *list = kept;
+        remove_if_empty(&mut guard, key);
+        Ok(removed)
+    }
+
+    /// Keeps only the elements between `start` and `stop`, inclusive.
+    pub fn ltrim(&self, key: &str, start: i64, stop: i64) -> Result<(), DbError> {
+        let mut guard = self.lock();
+        let Some(list) = list_mut(&mut guard, key)? else {
+            return Ok(());
+        };
+        match normalize_range(start, stop, list.len()) {
+            None => list.clear(),
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+        }
+        remove_if_empty(&mut guard, key);
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use super::{live, remove_if_empty, sample, DbError, Entry, Keyspace, Value, DB};
+
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// The live set at `key`, if there is one.
+fn set_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut HashSet<String>>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+/// The set at `key`, created empty if the key doesn't exist.
+fn set_or_create<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<&'a mut HashSet<String>, DbError> {
+    if set_mut(keyspace, key)?.is_none() {
+        let entry = Entry::new(Value::Set(HashSet::new()), None);
+        keyspace.insert(key.to_string(), entry);
+    }
+    match &mut keyspace.get_mut(key).expect("checked above").value {
+        Value::Set(set) => Ok(set),
+        _ => unreachable!("checked above"),
+    }
+}
+
+/// The live sets at `keys`, `None` for missing keys.
+fn sets<'a>(
+    keyspace: &'a mut Keyspace,
+    keys: &[String],
+) -> Result<Vec<Option<&'a HashSet<String>>>, DbError> {
+    // drops expired keys and checks types, so the lookups below can share the map
+    for key in keys {
+        set_mut(keyspace, key)?;
+    }
+    let keyspace = &*keyspace;
+    Ok(keys
+        .iter()
+        .map(|key| match keyspace.get(key).map(|entry| &entry.value) {
+            Some(Value::Set(set)) => Some(set),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Combines the sets at `keys`, treating missing keys as empty sets.
+fn combine(
+    keyspace: &mut Keyspace,
+    op: SetOp,
+    keys: &[String],
+) -> Result<HashSet<String>, DbError> {
+    let sets = sets(keyspace, keys)?;
+    let empty = HashSet::new();
+    let (first, rest) = sets.split_first().expect("at least one key");
+    let first = first.unwrap_or(&empty);
+    let result = match op {
+        SetOp::Inter => first
+            .iter()
+            .filter(|member| {
+                rest.iter()
+                    .all(|set| set.is_some_and(|set| set.contains(*member)))
+            })
+            .cloned()
+            .collect(),
+        SetOp::Union => sets
+            .iter()
+            .flatten()
+            .flat_map(|set| set.iter())
+            .cloned()
+            .collect(),
+        SetOp::Diff => first
+            .iter()
+            .filter(|member| !rest.iter().flatten().any(|set| set.contains(*member)))
+            .cloned()
+            .collect(),
+    };
+    Ok(result)
+}
+
+impl DB {
+    /// Adds the members, returning how many weren't in the set already.
+    pub fn sadd(&self, key: &str, members: &[String]) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let set = set_or_create(&mut guard, key)?;
+        Ok(members
+            .iter()
+            .filter(|member| set.insert(member.to_string()))
+            .count())
+    }
+
+    /// Removes the members, deleting the set once it is empty, and returns
+    /// how many were in it.
+    pub fn srem(&self, key: &str, members: &[String]) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(set) = set_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        remove_if_empty(&mut guard, key);
+        Ok(removed)
+    }
+
+    /// Removes and returns up to `count` random members, deleting the set
+    /// once it is empty.
+    pub fn spop(&self, key: &str, count: usize) -> Result<Vec<String>, DbError> {
+        let mut guard = self.lock();
+        let Some(set) = set_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        let count = count.min(set.len()) as i64;
+        let members: Vec<String> = sample(set.iter().collect(), count)
+            .into_iter()
+            .cloned()
+            .collect();
+        for member in &members {
+            set.remove(member);
+        }
+        remove_if_empty(&mut guard, key);
+        Ok(members)
+    }
+
+    /// Random members: `count` distinct ones when positive, `-count`
+    /// possibly repeated ones when negative.
+    pub fn srandmember(&self, key: &str, count: i64) -> Result<Vec<String>, DbError> {
+        let mut guard = self.lock();
+        let Some(set) = set_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        Ok(sample(set.iter().collect(), count)
+            .into_iter()
+            .cloned()
+            .collect())
+    }
+
+    /// Moves `member` from `source` to `destination` under one lock,
+    /// returning whether it was in `source`.
+    pub fn smove(&self, source: &str, destination: &str, member: &str) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        // both types are checked before anything changes
+        set_mut(&mut guard, destination)?;
+        let Some(set) = set_mut(&mut guard, source)? else {
+            return Ok(false);
+        };
+        if !set.remove(member) {
+            return Ok(false);
+        }
+        remove_if_empty(&mut guard, source);
+        set_or_create(&mut guard, destination)?.insert(member.to_string());
+        Ok(true)
+    }
+
+    pub fn smismember(&self, key: &str, members: &[String]) -> Result<Vec<bool>, DbError> {
+        let mut guard = self.lock();
+        let set = set_mut(&mut guard, key)?;
+        Ok(members
+            .iter()
+            .map(|member| set.as_ref().is_some_and(|set| set.contains(member)))
+            .collect())
+    }
+
+    pub fn smembers(&self, key: &str) -> Result<Vec<String>, DbError> {
+        let mut guard = self.lock();
+        Ok(set_mut(&mut guard, key)?
+            .map(|set| set.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    pub fn sismember(&self, key: &str, member: &str) -> Result<bool, DbError> {
+        let mut guard = self.lock();
+        Ok(set_mut(&mut guard, key)?.is_some_and(|set| set.contains(member)))
+    }
+
+    pub fn scard(&self, key: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        Ok(set_mut(&mut guard, key)?.map_or(0, |set| set.len()))
+    }
+
+    /// Size of the intersection, stopping early at `limit` unless it is zero.
+    pub fn sintercard(&self, keys: &[String], limit: usize) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        let sets = sets(&mut guard, keys)?;
+        let Some(mut sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+            // a missing key empties the intersection
+            return Ok(0);
+        };
+        // probe the others with the members of the smallest set
+        sets.sort_by_key(|set| set.len());
+        let (smallest, rest) = sets.split_first().expect("at least one key");
+        Ok(smallest
+            .iter()
+            .filter(|member| rest.iter().all(|set| set.contains(*member)))
+            .take(limit)
+            .count())
+    }
+
+    pub fn setop(&self, op: SetOp, keys: &[String]) -> Result<Vec<String>, DbError> {
+        let mut guard = self.lock();
+        Ok(combine(&mut guard, op, keys)?.into_iter().collect())
+    }
+
+    /// Stores the result in `destination`, replacing whatever it held, and
+    /// returns its size. An empty result deletes `destination`.
+    pub fn setop_store(
+        &self,
+        op: SetOp,
+        destination: &str,
+        keys: &[String],
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let result = combine(&mut guard, op, keys)?;
+        let len = result.len();
+        if result.is_empty() {
+            guard.remove(destination);
+        } else {
+            guard.insert(
+                destination.to_string(),
+                Entry::new(Value::Set(result), None),
+            );
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,154 @@
+use super::{live, DbError, Entry, Keyspace, Value, DB};
+
+/// How SORT orders the elements and what it returns for them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SortOptions {
+    /// Weights from other keys, with `*` standing for the element
+    pub by: Option<String>,
+    /// Offset and count, where a negative count takes the rest
+    pub limit: Option<(i64, i64)>,
+    /// Values from other keys to return instead of the elements, with `#`
+    /// for the element itself
+    pub get: Vec<String>,
+    pub desc: bool,
+    pub alpha: bool,
+}
+
+/// The value a pattern names for an element: the string at the key made by
+/// putting the element in place of the first `*`, or a field of the hash
+/// there if the pattern goes on with `->field`.
+fn lookup(keyspace: &mut Keyspace, pattern: &str, element: &str) -> Option<Vec<u8>> {
+    if pattern == "#" {
+        return Some(element.as_bytes().to_vec());
+    }
+    let star = pattern.find('*')?;
+    let (key, field) = match pattern[star..].find("->") {
+        Some(arrow) if star + arrow + 2 < pattern.len() => {
+            let (key, field) = pattern.split_at(star + arrow);
+            (key, Some(&field[2..]))
+        }
+        _ => (pattern, None),
+    };
+    let key = key.replacen('*', element, 1);
+    match (&live(keyspace, &key)?.value, field) {
+        (Value::String(value), None) => Some(value.clone()),
+        (Value::Hash(hash), Some(field)) => hash.get(field).map(|value| value.clone().into_bytes()),
+        _ => None,
+    }
+}
+
+/// A weight as a number, with missing ones counting as 0.
+fn score(weight: Option<Vec<u8>>) -> Result<f64, DbError> {
+    let Some(weight) = weight else {
+        return Ok(0.0);
+    };
+    std::str::from_utf8(&weight)
+        .ok()
+        .and_then(|weight| weight.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or(DbError::NotSortable)
+}
+
+fn sort(
+    keyspace: &mut Keyspace,
+    key: &str,
+    options: &SortOptions,
+) -> Result<Vec<Option<Vec<u8>>>, DbError> {
+    let (mut elements, is_set): (Vec<String>, bool) =
+        match live(keyspace, key).map(|entry| &entry.value) {
+            None => (vec![], false),
+            Some(Value::List(list)) => (list.iter().cloned().collect(), false),
+            Some(Value::Set(set)) => (set.iter().cloned().collect(), true),
+            Some(Value::ZSet(zset)) => (
+                zset.iter().map(|(member, _)| member.to_string()).collect(),
+                false,
+            ),
+            Some(_) => return Err(DbError::WrongType),
+        };
+
+    // a BY pattern without `*` names the same weight for every element, so
+    // the elements keep their order, except that sets have none to keep
+    let by = options.by.as_deref();
+    let fixed = by.is_some_and(|by| !by.contains('*'));
+    if fixed && is_set {
+        elements.sort_unstable();
+    } else if !fixed {
+        let weights: Vec<_> = elements
+            .iter()
+            .map(|element| match by {
+                None => Some(element.as_bytes().to_vec()),
+                Some(by) => lookup(keyspace, by, element),
+            })
+            .collect();
+        // ties go by the element, so the order is the same on replicas
+        elements = if options.alpha {
+            let mut keyed: Vec<_> = weights.into_iter().zip(elements).collect();
+            keyed.sort_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.cmp(y)));
+            keyed.into_iter().map(|(_, element)| element).collect()
+        } else {
+            let scores = weights
+                .into_iter()
+                .map(score)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut keyed: Vec<_> = scores.into_iter().zip(elements).collect();
+            keyed.sort_by(|(a, x), (b, y)| a.total_cmp(b).then_with(|| x.cmp(y)));
+            keyed.into_iter().map(|(_, element)| element).collect()
+        };
+    }
+    if options.desc {
+        elements.reverse();
+    }
+
+    let (offset, count) = match options.limit {
+        None => (0, usize::MAX),
+        Some((offset, count)) => (
+            offset.max(0) as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+    };
+    let elements = elements.into_iter().skip(offset).take(count);
+    if options.get.is_empty() {
+        return Ok(elements.map(|element| Some(element.into_bytes())).collect());
+    }
+    Ok(elements
+        .flat_map(|element| {
+            options
+                .get
+                .iter()
+                .map(|pattern| lookup(keyspace, pattern, &element))
+                .collect::<Vec<_>>()
+        })
+        .collect())
+}
+
+impl DB {
+    /// SORT: the elements of a list, set or sorted set in order, or what the
+    /// GET patterns name for each, `None` where they name nothing.
+    pub fn sort(&self, key: &str, options: &SortOptions) -> Result<Vec<Option<Vec<u8>>>, DbError> {
+        let mut guard = self.lock();
+        sort(&mut guard, key, options)
+    }
+
+    /// SORT with STORE: replaces `destination` with the result as a list,
+    /// nothing becoming empty strings, and returns its length.
+    pub fn sort_store(
+        &self,
+        key: &str,
+        options: &SortOptions,
+        destination: &str,
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let sorted = sort(&mut guard, key, options)?;
+        let len = sorted.len();
+        if sorted.is_empty() {
+            guard.remove(destination);
+        } else {
+            let list = sorted
+                .into_iter()
+                .map(|value| String::from_utf8_lossy(&value.unwrap_or_default()).into_owned())
+                .collect();
+            guard.insert(destination.to_string(), Entry::new(Value::List(list), None));
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{live, DbError, Entry, Keyspace, Value, DB};
+
+/// A stream entry id, `ms-seq`, ordered by time and then sequence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl FromStr for StreamId {
+    type Err = ();
+
+    /// `ms-seq`, or just `ms` with the sequence at 0.
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = id.split_once('-').unwrap_or((id, "0"));
+        Ok(StreamId {
+            ms: ms.parse().map_err(|_| ())?,
+            seq: seq.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// The id XADD was asked for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum XAddId {
+    /// `*`, generated from the clock
+    Auto,
+    /// `ms-*`, with the next free sequence for that time
+    Partial(u64),
+    Explicit(StreamId),
+}
+
+impl FromStr for XAddId {
+    type Err = ();
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        match id.split_once('-') {
+            _ if id == "*" => Ok(XAddId::Auto),
+            Some((ms, "*")) => Ok(XAddId::Partial(ms.parse().map_err(|_| ())?)),
+            _ => Ok(XAddId::Explicit(id.parse()?)),
+        }
+    }
+}
+
+/// An entry id with its fields, in the order they were added.
+pub type StreamEntry = (StreamId, Vec<(String, String)>);
+
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    last_id: StreamId,
+    /// Every entry ever added, including deleted ones
+    entries_added: u64,
+    max_deleted_id: StreamId,
+}
+
+/// What XINFO STREAM reports.
+#[derive(Debug)]
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub first: Option<StreamEntry>,
+    pub last: Option<StreamEntry>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&StreamId, &Vec<(String, String)>)> {
+        self.entries.iter()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn entries_added(&self) -> u64 {
+        self.entries_added
+    }
+
+    pub fn max_deleted_id(&self) -> StreamId {
+        self.max_deleted_id
+    }
+
+    /// A stream as RDB stores it, with the entries left and what they
+    /// don't tell about the ones gone.
+    pub fn from_parts(
+        entries: BTreeMap<StreamId, Vec<(String, String)>>,
+        last_id: StreamId,
+        entries_added: u64,
+        max_deleted_id: StreamId,
+    ) -> Self {
+        Stream {
+            entries,
+            last_id,
+            entries_added,
+            max_deleted_id,
+        }
+    }
+
+    /// Up to `count` entries with an id above `id`.
+    fn after(&self, id: StreamId, count: usize) -> Vec<StreamEntry> {
+        self.entries
+            .range((Bound::Excluded(id), Bound::Unbounded))
+            .take(count)
+            .map(|(id, fields)| (*id, fields.clone()))
+            .collect()
+    }
+
+    /// The id an entry added now gets, which must be above the last one.
+    fn next_id(&self, id: XAddId) -> Result<StreamId, DbError> {
+        let last = self.last_id;
+        let id = match id {
+            XAddId::Explicit(id) if id == StreamId::default() => {
+                return Err(DbError::StreamIdZero);
+            }
+            XAddId::Explicit(id) => id,
+            XAddId::Partial(ms) if ms == last.ms => StreamId {
+                ms,
+                seq: last.seq.checked_add(1).ok_or(DbError::StreamIdTooSmall)?,
+            },
+            XAddId::Partial(ms) => StreamId { ms, seq: 0 },
+            XAddId::Auto => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |now| now.as_millis() as u64);
+                if now > last.ms {
+                    StreamId { ms: now, seq: 0 }
+                } else {
+                    // the clock went back, or the last sequence is used up
+                    match last.seq.checked_add(1) {
+                        Some(seq) => StreamId { ms: last.ms, seq },
+                        None => StreamId {
+                            ms: last.ms.checked_add(1).ok_or(DbError::StreamIdTooSmall)?,
+                            seq: 0,
+                        },
+                    }
+                }
+            }
+        };
+        if id <= last {
+            return Err(DbError::StreamIdTooSmall);
+        }
+        Ok(id)
+    }
+}
+
+/// The live stream at `key`, if there is one.
+fn stream_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut Stream>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+impl DB {
+    /// Appends an entry and returns its id. Without `create` a missing
+    /// stream isn't created and nothing is added.
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: XAddId,
+        fields: &[(String, String)],
+        create: bool,
+    ) -> Result<Option<StreamId>, DbError> {
+        let mut guard = self.lock();
+        let missing = stream_mut(&mut guard, key)?.is_none();
+        if missing && !create {
+            return Ok(None);
+        }
+        // a new stream only lands in the keyspace once its first id is valid
+        let mut new = Stream::default();
+        let stream = if missing {
+            &mut new
+        } else {
+            stream_mut(&mut guard, key)?.expect("checked above")
+        };
+        let id = stream.next_id(id)?;
+        stream.entries.insert(id, fields.to_vec());
+        stream.last_id = id;
+        stream.entries_added += 1;
+        if missing {
+            guard.insert(key.to_string(), Entry::new(Value::Stream(new), None));
+        }
+        Ok(Some(id))
+    }
+
+    /// The last id added to the stream, 0-0 when there is no stream.
+    pub fn xlast_id(&self, key: &str) -> Result<StreamId, DbError> {
+        let mut guard = self.lock();
+        Ok(stream_mut(&mut guard, key)?.map_or(StreamId::default(), |stream| stream.last_id))
+    }
+
+    /// For each stream with entries above its id, up to `count` of them.
+    /// Streams without any are left out.
+    pub fn xread(
+        &self,
+        keys: &[String],
+        ids: &[StreamId],
+        count: usize,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, DbError> {
+        let mut guard = self.lock();
+        let mut result = vec![];
+        for (key, id) in keys.iter().zip(ids) {
+            let Some(stream) = stream_mut(&mut guard, key)? else {
+                continue;
+            };
+            let entries = stream.after(*id, count);
+            if !entries.is_empty() {
+                result.push((key.clone(), entries));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Moves the stream's last id, and optionally its entries-added counter
+    /// and max deleted id, as XSETID does for replicas of trimmed streams.
+    pub fn xsetid(
+        &self,
+        key: &str,
+        id: StreamId,
+        entries_added: Option<u64>,
+        max_deleted_id: Option<StreamId>,
+    ) -> Result<(), DbError> {
+        let mut guard = self.lock();
+        let stream = stream_mut(&mut guard, key)?.ok_or(DbError::NoSuchKey)?;
+        if stream
+            .entries
+            .last_key_value()
+            .is_some_and(|(top, _)| id < *top)
+        {
+            return Err(DbError::StreamIdBelowTop);
+        }
+        if entries_added.is_some_and(|added| added < stream.len() as u64) {
+            return Err(DbError::EntriesAddedTooSmall);
+        }
+        stream.last_id = id;
+        if let Some(added) = entries_added {
+            stream.entries_added = added;
+        }
+        if let Some(max_deleted_id) = max_deleted_id {
+            stream.max_deleted_id = max_deleted_id;
+        }
+        Ok(())
+    }
+
+    pub fn xinfo_stream(&self, key: &str) -> Result<StreamInfo, DbError> {
+        let mut guard = self.lock();
+        let stream = stream_mut(&mut guard, key)?.ok_or(DbError::NoSuchKey)?;
+        let entry = |(id, fields): (&StreamId, &Vec<(String, String)>)| (*id, fields.clone());
+        Ok(StreamInfo {
+            length: stream.len(),
+            last_id: stream.last_id,
+            max_deleted_id: stream.max_deleted_id,
+            entries_added: stream.entries_added,
+            first: stream.entries.first_key_value().map(entry),
+            last: stream.entries.last_key_value().map(entry),
+        })
+    }
+}
@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+use std::ops::Bound;
+
+use super::{
+    live, normalize_range, remove_if_empty, sample, DbError, Entry, Keyspace, SetOp, Value, DB,
+};
+use skiplist::SkipList;
+
+mod skiplist;
+
+/// Members ordered by score, then by member for equal scores, with a
+/// member to score map for lookups.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: SkipList,
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Sets the member's score, returning the previous one.
+    pub fn insert(&mut self, member: &str, score: f64) -> Option<f64> {
+        // -0 is stored as 0, so the two sort and range as the same score
+        let score = if score == 0.0 { 0.0 } else { score };
+        let old = self.scores.insert(member.to_string(), score);
+        match old {
+            Some(old) if old.total_cmp(&score).is_eq() => return old.into(),
+            Some(old) => {
+                self.ordered.remove(member, old);
+            }
+            None => {}
+        }
+        self.ordered.insert(member.to_string(), score);
+        old
+    }
+
+    /// Removes the member, returning its score.
+    pub fn remove(&mut self, member: &str) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.ordered.remove(member, score);
+        Some(score)
+    }
+
+    /// Zero-based position of the member, lowest score first.
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        self.ordered.rank(member, self.score(member)?)
+    }
+
+    /// Members from rank `start` to `stop`, both included and in range.
+    pub fn range(&self, start: usize, stop: usize) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        self.ordered.range(start, stop)
+    }
+
+    /// Members with a score within the bounds, lowest score first.
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        self.ordered.range_by_score(min, max)
+    }
+
+    /// Members with their scores, lowest score first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        self.ordered.iter()
+    }
+}
+
+impl FromIterator<(String, f64)> for SortedSet {
+    fn from_iter<I: IntoIterator<Item = (String, f64)>>(members: I) -> Self {
+        let mut zset = SortedSet::default();
+        for (member, score) in members {
+            zset.insert(&member, score);
+        }
+        zset
+    }
+}
+
+/// Which members ZRANGESTORE, ZREMRANGEBY* and the counting commands select.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZRange {
+    Rank {
+        start: i64,
+        stop: i64,
+    },
+    Score {
+        min: Bound<f64>,
+        max: Bound<f64>,
+    },
+    Lex {
+        min: Bound<String>,
+        max: Bound<String>,
+    },
+}
+
+/// How scores of a member found in several inputs are combined.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(&self, a: f64, b: f64) -> f64 {
+        let result = match self {
+            Aggregate::Sum => a + b,
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        };
+        // inf + -inf, like Redis
+        if result.is_nan() {
+            0.0
+        } else {
+            result
+        }
+    }
+}
+
+/// How ZADD treats existing and new members.
+#[derive(Debug, Default, Clone, Copy, PartialOrd, PartialEq)]
+pub struct ZAddFlags {
+    /// only add new members
+    pub nx: bool,
+    /// only update existing members
+    pub xx: bool,
+    /// only update when the new score is greater
+    pub gt: bool,
+    /// only update when the new score is less
+    pub lt: bool,
+    /// count changed members in the reply, not just added ones
+    pub ch: bool,
+    /// add the score to the current one instead of replacing it
+    pub incr: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ZAddResult {
+    pub added: usize,
+    pub changed: usize,
+    /// The member's score after an INCR, `None` when the flags prevented it
+    pub score: Option<f64>,
+}
+
+/// The live sorted set at `key`, if there is one.
+pub(super) fn zset_mut<'a>(
+    keyspace: &'a mut Keyspace,
+    key: &str,
+) -> Result<Option<&'a mut SortedSet>, DbError> {
+    match live(keyspace, key).map(|entry| &mut entry.value) {
+        None => Ok(None),
+        Some(Value::ZSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(DbError::WrongType),
+    }
+}
+
+/// The sorted set at `key`, created empty if the key doesn't exist.
+fn zset_or_create<'a>(keyspace: &'a mut Keyspace, key: &str) -> Result<&'a mut SortedSet, DbError> {
+    if zset_mut(keyspace, key)?.is_none() {
+        let entry = Entry::new(Value::ZSet(SortedSet::default()), None);
+        keyspace.insert(key.to_string(), entry);
+    }
+    match &mut keyspace.get_mut(key).expect("checked above").value {
+        Value::ZSet(zset) => Ok(zset),
+        _ => unreachable!("checked above"),
+    }
+}
+
+/// Combines the sorted sets, or plain sets with every score at 1, at `keys`.
+/// Missing keys count as empty. Difference keeps the first input's scores.
+fn combine(
+    keyspace: &mut Keyspace,
+    op: SetOp,
+    keys: &[String],
+    weights: &[f64],
+    aggregate: Aggregate,
+) -> Result<SortedSet, DbError> {
+    let mut inputs: Vec<Vec<(String, f64)>> = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        let weight = weights.get(index).copied().unwrap_or(1.0);
+        let weighted = |score: f64| {
+            // inf * 0, like Redis
+            let score = score * weight;
+            if score.is_nan() {
+                0.0
+            } else {
+                score
+            }
+        };
+        let members = match live(keyspace, key).map(|entry| &entry.value) {
+            None => vec![],
+            Some(Value::ZSet(zset)) => zset
+                .iter()
+                .map(|(member, score)| (member.to_string(), weighted(score)))
+                .collect(),
+            Some(Value::Set(set)) => set
+                .iter()
+                .map(|member| (member.clone(), weighted(1.0)))
+                .collect(),
+            Some(_) => return Err(DbError::WrongType),
+        };
+        inputs.push(members);
+    }
+
+    let (first, rest) = inputs.split_first().expect("at least one key");
+    let mut result: HashMap<String, f64> = first.iter().cloned().collect();
+    for members in rest {
+        match op {
+            SetOp::Union => {
+                for (member, score) in members {
+                    result
+                        .entry(member.clone())
+                        .and_modify(|current| *current = aggregate.apply(*current, *score))
+                        .or_insert(*score);
+                }
+            }
+            SetOp::Inter => {
+                let members: HashMap<&String, f64> = members
+                    .iter()
+                    .map(|(member, score)| (member, *score))
+                    .collect();
+                result.retain(|member, _| members.contains_key(member));
+                for (member, current) in result.iter_mut() {
+                    *current = aggregate.apply(*current, members[member]);
+                }
+            }
+            SetOp::Diff => {
+                for (member, _) in members {
+                    result.remove(member);
+                }
+            }
+        }
+    }
+    Ok(result.into_iter().collect())
+}
+
+/// Whether `member` is on the right side of a lex range's lower bound.
+fn above(member: &str, min: &Bound<String>) -> bool {
+    match min {
+        Bound::Included(min) => member >= min.as_str(),
+        Bound::Excluded(min) => member > min.as_str(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether `member` is on the right side of a lex range's upper bound.
+fn below(member: &str, max: &Bound<String>) -> bool {
+    match max {
+        Bound::Included(max) => member <= max.as_str(),
+        Bound::Excluded(max) => member < max.as_str(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// Applies LIMIT and copies the members out.
+fn page<'a>(
+    members: impl Iterator<Item = (&'a str, f64)>,
+    offset: usize,
+    count: usize,
+) -> Vec<(String, f64)> {
+    members
+        .skip(offset)
+        .take(count)
+        .map(|(member, score)| (member.to_string(), score))
+        .collect()
+}
+
+fn by_rank(zset: &SortedSet, start: i64, stop: i64, rev: bool) -> Vec<(String, f64)> {
+    let Some((start, stop)) = normalize_range(start, stop, zset.len()) else {
+        return vec![];
+    };
+    if rev {
+        // ranks count from the highest score
+        let last = zset.len() - 1;
+        page(zset.range(last - stop, last - start).rev(), 0, usize::MAX)
+    } else {
+        page(zset.range(start, stop), 0, usize::MAX)
+    }
+}
+
+fn by_score(
+    zset: &SortedSet,
+    min: Bound<f64>,
+    max: Bound<f64>,
+    rev: bool,
+    offset: usize,
+    count: usize,
+) -> Vec<(String, f64)> {
+    let members = zset.range_by_score(min, max);
+    if rev {
+        page(members.rev(), offset, count)
+    } else {
+        page(members, offset, count)
+    }
+}
+
+fn by_lex(
+    zset: &SortedSet,
+    min: &Bound<String>,
+    max: &Bound<String>,
+    rev: bool,
+    offset: usize,
+    count: usize,
+) -> Vec<(String, f64)> {
+    if rev {
+        let members = zset
+            .iter()
+            .rev()
+            .skip_while(|(member, _)| !below(member, max))
+            .take_while(|(member, _)| above(member, min));
+        page(members, offset, count)
+    } else {
+        let members = zset
+            .iter()
+            .skip_while(|(member, _)| !above(member, min))
+            .take_while(|(member, _)| below(member, max));
+        page(members, offset, count)
+    }
+}
+
+fn select(
+    zset: &SortedSet,
+    range: &ZRange,
+    rev: bool,
+    offset: usize,
+    count: usize,
+) -> Vec<(String, f64)> {
+    match range {
+        ZRange::Rank { start, stop } => by_rank(zset, *start, *stop, rev),
+        ZRange::Score { min, max } => by_score(zset, *min, *max, rev, offset, count),
+        ZRange::Lex { min, max } => by_lex(zset, min, max, rev, offset, count),
+    }
+}
+
+impl DB {
+    pub fn zadd(
+        &self,
+        key: &str,
+        flags: ZAddFlags,
+        pairs: &[(f64, String)],
+    ) -> Result<ZAddResult, DbError> {
+        let mut guard = self.lock();
+        let zset = zset_or_create(&mut guard, key)?;
+        let mut result = ZAddResult::default();
+        for (score, member) in pairs {
+            let old = zset.score(member);
+            let score = match (flags.incr, old) {
+                (true, Some(old)) => old + score,
+                _ => *score,
+            };
+            if score.is_nan() {
+                remove_if_empty(&mut guard, key);
+                return Err(DbError::NotANumber);
+            }
+            let allowed = match old {
+                None => !flags.xx,
+                Some(old) => {
+                    let blocked =
+                        flags.nx || (flags.gt && score <= old) || (flags.lt && score >= old);
+                    !blocked
+                }
+            };
+            if !allowed {
+                continue;
+            }
+            match zset.insert(member, score) {
+                None => result.added += 1,
+                Some(old) if old != score => result.changed += 1,
+                Some(_) => {}
+            }
+            result.score = Some(score);
+        }
+        // XX on a missing key adds nothing
+        remove_if_empty(&mut guard, key);
+        Ok(result)
+    }
+
+    /// Adds `delta` to the member's score, adding it when missing, and returns the new score.
+    pub fn zincrby(&self, key: &str, delta: f64, member: &str) -> Result<f64, DbError> {
+        let flags = ZAddFlags {
+            incr: true,
+            ..ZAddFlags::default()
+        };
+        let result = self.zadd(key, flags, &[(delta, member.to_string())])?;
+        Ok(result.score.expect("no flag can block a plain increment"))
+    }
+
+    /// The member's rank and score, ranking from the highest score when `rev` is set.
+    pub fn zrank(
+        &self,
+        key: &str,
+        member: &str,
+        rev: bool,
+    ) -> Result<Option<(usize, f64)>, DbError> {
+        let mut guard = self.lock();
+        let Some(zset) = zset_mut(&mut guard, key)? else {
+            return Ok(None);
+        };
+        let (Some(rank), Some(score)) = (zset.rank(member), zset.score(member)) else {
+            return Ok(None);
+        };
+        let rank = if rev { zset.len() - 1 - rank } else { rank };
+        Ok(Some((rank, score)))
+    }
+
+    pub fn zscore(&self, key: &str, member: &str) -> Result<Option<f64>, DbError> {
+        let mut guard = self.lock();
+        Ok(zset_mut(&mut guard, key)?.and_then(|zset| zset.score(member)))
+    }
+
+    pub fn zmscore(&self, key: &str, members: &[String]) -> Result<Vec<Option<f64>>, DbError> {
+        let mut guard = self.lock();
+        let zset = zset_mut(&mut guard, key)?;
+        Ok(members
+            .iter()
+            .map(|member| zset.as_ref().and_then(|zset| zset.score(member)))
+            .collect())
+    }
+
+    /// Random members with their scores: `count` distinct ones when positive,
+    /// `-count` possibly repeated ones when negative.
+    pub fn zrandmember(&self, key: &str, count: i64) -> Result<Vec<(String, f64)>, DbError> {
+        let mut guard = self.lock();
+        let Some(zset) = zset_mut(&mut guard, key)? else {
+            return Ok(vec![]);
+        };
+        Ok(sample(zset.iter().collect(), count)
+            .into_iter()
+            .map(|(member, score)| (member.to_string(), score))
+            .collect())
+    }
+
+    pub fn zcard(&self, key: &str) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        Ok(zset_mut(&mut guard, key)?.map_or(0, |zset| zset.len()))
+    }
+
+    /// Members between two ranks, highest score first when `rev` is set.
+    pub fn zrange(
+        &self,
+        key: &str,
+        start: i64,
+        stop: i64,
+        rev: bool,
+    ) -> Result<Vec<(String, f64)>, DbError> {
+        let mut guard = self.lock();
+        Ok(zset_mut(&mut guard, key)?
+            .map(|zset| by_rank(zset, start, stop, rev))
+            .unwrap_or_default())
+    }
+
+    /// Members with a score within the bounds, highest first when `rev` is set,
+    /// skipping `offset` of them and returning at most `count`.
+    pub fn zrange_by_score(
+        &self,
+        key: &str,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>, DbError> {
+        let mut guard = self.lock();
+        Ok(zset_mut(&mut guard, key)?
+            .map(|zset| by_score(zset, min, max, rev, offset, count))
+            .unwrap_or_default())
+    }
+
+    /// Members within lexicographic bounds, assuming they all share a score as
+    /// Redis does, highest first when `rev` is set.
+    pub fn zrange_by_lex(
+        &self,
+        key: &str,
+        min: &Bound<String>,
+        max: &Bound<String>,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    ) -> Result<Vec<(String, f64)>, DbError> {
+        let mut guard = self.lock();
+        Ok(zset_mut(&mut guard, key)?
+            .map(|zset| by_lex(zset, min, max, rev, offset, count))
+            .unwrap_or_default())
+    }
+
+    /// Stores the selected members of `source` with their scores in
+    /// `destination`, replacing whatever it held, and returns how many there
+    /// are. An empty result deletes `destination`.
+    pub fn zrangestore(
+        &self,
+        destination: &str,
+        source: &str,
+        range: &ZRange,
+        rev: bool,
+        offset: usize,
+        count: usize,
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let members = match zset_mut(&mut guard, source)? {
+            None => vec![],
+            Some(zset) => select(zset, range, rev, offset, count),
+        };
+        let len = members.len();
+        if len == 0 {
+            guard.remove(destination);
+        } else {
+            let zset = members.into_iter().collect();
+            guard.insert(destination.to_string(), Entry::new(Value::ZSet(zset), None));
+        }
+        Ok(len)
+    }
+
+    /// How many members are in `range`, without copying them out.
+    pub fn zcount(&self, key: &str, range: &ZRange) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(zset) = zset_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        Ok(match range {
+            ZRange::Score { min, max } => zset.range_by_score(*min, *max).count(),
+            ZRange::Lex { min, max } => zset
+                .iter()
+                .skip_while(|(member, _)| !above(member, min))
+                .take_while(|(member, _)| below(member, max))
+                .count(),
+            ZRange::Rank { .. } => select(zset, range, false, 0, usize::MAX).len(),
+        })
+    }
+
+    /// Removes the members in `range`, deleting the sorted set once it is
+    /// empty, and returns how many there were.
+    pub fn zremrange(&self, key: &str, range: &ZRange) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let Some(zset) = zset_mut(&mut guard, key)? else {
+            return Ok(0);
+        };
+        let members = select(zset, range, false, 0, usize::MAX);
+        for (member, _) in &members {
+            zset.remove(member);
+        }
+        remove_if_empty(&mut guard, key);
+        Ok(members.len())
+    }
+
+    /// ZUNION, ZINTER and ZDIFF, lowest score first.
+    pub fn zcombine(
+        &self,
+        op: SetOp,
+        keys: &[String],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> Result<Vec<(String, f64)>, DbError> {
+        let mut guard = self.lock();
+        let zset = combine(&mut guard, op, keys, weights, aggregate)?;
+        Ok(zset
+            .iter()
+            .map(|(member, score)| (member.to_string(), score))
+            .collect())
+    }
+
+    /// Stores the combination in `destination`, replacing whatever it held,
+    /// and returns its size. An empty result deletes `destination`.
+    pub fn zcombine_store(
+        &self,
+        op: SetOp,
+        destination: &str,
+        keys: &[String],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> Result<usize, DbError> {
+        let mut guard = self.lock();
+        let zset = combine(&mut guard, op, keys, weights, aggregate)?;
+        let len = zset.len();
+        if len == 0 {
+            guard.remove(destination);
+        } else {
+            guard.insert(destination.to_string(), Entry::new(Value::ZSet(zset), None));
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,327 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use crate::db::random;
+
+const MAX_LEVEL: usize = 32;
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Link {
+    next: Option<usize>,
+    /// How many nodes this link moves forward, so ranks add up on the way down.
+    /// For the last link of a level it counts the nodes left to the end.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: String,
+    score: f64,
+    links: Vec<Link>,
+    prev: Option<usize>,
+}
+
+impl Node {
+    /// Orders by score with `total_cmp`, then by member.
+    fn cmp(&self, score: f64, member: &str) -> Ordering {
+        self.score
+            .total_cmp(&score)
+            .then_with(|| self.member.as_str().cmp(member))
+    }
+}
+
+/// Members ordered by score, then by member, in an indexable skiplist like
+/// the one behind Redis sorted sets: inserts, removals, ranks and lookups by
+/// rank or score are all O(log N). Nodes live in a vector and point at each
+/// other by index, with the head at index 0 and removed slots reused.
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: String::new(),
+            score: 0.0,
+            links: vec![Link::default(); MAX_LEVEL],
+            prev: None,
+        };
+        Self {
+            nodes: vec![head],
+            free: vec![],
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+/// Each level holds about a quarter of the nodes of the one below.
+fn random_level() -> usize {
+    let mut level = 1;
+    while level < MAX_LEVEL && random(4) == 0 {
+        level += 1;
+    }
+    level
+}
+
+impl SkipList {
+    fn link(&self, node: usize, level: usize) -> Link {
+        self.nodes[node].links[level]
+    }
+
+    /// Walks forward while `before` holds for the next node, returning the
+    /// last node it held for, the head if none, with how many nodes that is.
+    fn seek(&self, before: impl Fn(&Node) -> bool) -> (usize, usize) {
+        let (mut node, mut rank) = (HEAD, 0);
+        for level in (0..self.level).rev() {
+            while let Link {
+                next: Some(next),
+                span,
+            } = self.link(node, level)
+            {
+                if !before(&self.nodes[next]) {
+                    break;
+                }
+                rank += span;
+                node = next;
+            }
+        }
+        (node, rank)
+    }
+
+    /// The node at a zero-based index.
+    fn node_at(&self, index: usize) -> Option<usize> {
+        let (mut node, mut rank) = (HEAD, 0);
+        for level in (0..self.level).rev() {
+            while let Link {
+                next: Some(next),
+                span,
+            } = self.link(node, level)
+            {
+                if rank + span > index + 1 {
+                    break;
+                }
+                rank += span;
+                node = next;
+            }
+            if rank == index + 1 {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    /// Adds a member that isn't in the list yet.
+    pub fn insert(&mut self, member: String, score: f64) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut node = HEAD;
+        for level in (0..self.level).rev() {
+            rank[level] = if level + 1 == self.level {
+                0
+            } else {
+                rank[level + 1]
+            };
+            while let Link {
+                next: Some(next),
+                span,
+            } = self.link(node, level)
+            {
+                if self.nodes[next].cmp(score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[level] += span;
+                node = next;
+            }
+            update[level] = node;
+        }
+
+        let height = random_level();
+        if height > self.level {
+            for links in &mut self.nodes[HEAD].links[self.level..height] {
+                links.span = self.len;
+            }
+            self.level = height;
+        }
+
+        let new = Node {
+            member,
+            score,
+            links: Vec::with_capacity(height),
+            prev: Some(update[0]).filter(|prev| *prev != HEAD),
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = new;
+                id
+            }
+            None => {
+                self.nodes.push(new);
+                self.nodes.len() - 1
+            }
+        };
+        for level in 0..height {
+            let before = self.link(update[level], level);
+            let skipped = rank[0] - rank[level];
+            self.nodes[id].links.push(Link {
+                next: before.next,
+                span: before.span - skipped,
+            });
+            self.nodes[update[level]].links[level] = Link {
+                next: Some(id),
+                span: skipped + 1,
+            };
+        }
+        for (level, node) in update.iter().enumerate().take(self.level).skip(height) {
+            self.nodes[*node].links[level].span += 1;
+        }
+        match self.link(id, 0).next {
+            Some(next) => self.nodes[next].prev = Some(id),
+            None => self.tail = Some(id),
+        }
+        self.len += 1;
+    }
+
+    /// Removes the member, returning whether it was there with that score.
+    pub fn remove(&mut self, member: &str, score: f64) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut node = HEAD;
+        for level in (0..self.level).rev() {
+            while let Some(next) = self.link(node, level).next {
+                if self.nodes[next].cmp(score, member) != Ordering::Less {
+                    break;
+                }
+                node = next;
+            }
+            update[level] = node;
+        }
+        let Some(id) = self.link(node, 0).next else {
+            return false;
+        };
+        if self.nodes[id].cmp(score, member) != Ordering::Equal {
+            return false;
+        }
+
+        for (level, node) in update.iter().enumerate().take(self.level) {
+            let before = self.link(*node, level);
+            self.nodes[*node].links[level] = if before.next == Some(id) {
+                let removed = self.link(id, level);
+                Link {
+                    next: removed.next,
+                    span: before.span + removed.span - 1,
+                }
+            } else {
+                Link {
+                    span: before.span - 1,
+                    ..before
+                }
+            };
+        }
+        let prev = self.nodes[id].prev;
+        match self.link(id, 0).next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+        while self.level > 1 && self.link(HEAD, self.level - 1).next.is_none() {
+            self.level -= 1;
+        }
+
+        let removed = &mut self.nodes[id];
+        removed.member = String::new();
+        removed.links = vec![];
+        self.free.push(id);
+        self.len -= 1;
+        true
+    }
+
+    /// Zero-based position of the member, which must have that score.
+    pub fn rank(&self, member: &str, score: f64) -> Option<usize> {
+        let (node, rank) = self.seek(|node| node.cmp(score, member) == Ordering::Less);
+        let next = self.link(node, 0).next?;
+        (self.nodes[next].cmp(score, member) == Ordering::Equal).then_some(rank)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: self.link(HEAD, 0).next,
+            back: self.tail,
+            len: self.len,
+        }
+    }
+
+    /// Members from rank `start` to `stop`, both included and in range.
+    pub fn range(&self, start: usize, stop: usize) -> Iter<'_> {
+        Iter {
+            list: self,
+            front: self.node_at(start),
+            back: self.node_at(stop),
+            len: stop + 1 - start,
+        }
+    }
+
+    /// Members with a score within the bounds, where -0 and 0 are equal.
+    pub fn range_by_score(&self, min: Bound<f64>, max: Bound<f64>) -> Iter<'_> {
+        let (before, skipped) = self.seek(|node| match min {
+            Bound::Included(min) => node.score < min,
+            Bound::Excluded(min) => node.score <= min,
+            Bound::Unbounded => false,
+        });
+        let (last, end) = self.seek(|node| match max {
+            Bound::Included(max) => node.score <= max,
+            Bound::Excluded(max) => node.score < max,
+            Bound::Unbounded => true,
+        });
+        Iter {
+            list: self,
+            front: self.link(before, 0).next,
+            back: Some(last).filter(|last| *last != HEAD),
+            len: end.saturating_sub(skipped),
+        }
+    }
+}
+
+/// Walks the nodes between `front` and `back`, which `len` counts.
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: Option<usize>,
+    back: Option<usize>,
+    len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front?];
+        self.front = node.links[0].next;
+        self.len -= 1;
+        Some((node.member.as_str(), node.score))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.len == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back?];
+        self.back = node.prev;
+        self.len -= 1;
+        Some((node.member.as_str(), node.score))
+    }
+}
@@ -0,0 +1,592 @@
+use crate::command::Command;
+use crate::db::{DbError, SetCondition, StreamEntry, StreamId, StreamInfo, DB};
+use crate::parse::{
+    array, bulk_bytes, bulk_string, encoded_array, integer, null_array, simple_error, simple_string,
+};
+
+/// Runs a data command against the keyspace and returns its RESP reply,
+/// or `None` for commands that aren't about the data.
+///
+/// Shared by client connections and by replicas applying the master's stream.
+pub fn execute(db: &DB, command: &Command) -> Option<Vec<u8>> {
+    let reply = match command {
+        // string values are bytes, so this reply skips the text ones below
+        Command::Get { key } => {
+            return Some(match db.get(key) {
+                Ok(value) => bulk_bytes(value.as_deref()),
+                Err(err) => simple_error(&err.to_string()).into_bytes(),
+            })
+        }
+        Command::Dump { key } => return Some(bulk_bytes(db.dump(key).as_deref())),
+        Command::Sort {
+            key,
+            options,
+            store: None,
+        } => {
+            return Some(match db.sort(key, options) {
+                Ok(values) => {
+                    let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+                    for value in values {
+                        reply.extend(bulk_bytes(value.as_deref()));
+                    }
+                    reply
+                }
+                Err(err) => simple_error(&err.to_string()).into_bytes(),
+            })
+        }
+        Command::Sort {
+            key,
+            options,
+            store: Some(destination),
+        } => db
+            .sort_store(key, options, destination)
+            .map(|len| integer(len as i64)),
+        Command::Set {
+            key,
+            value,
+            condition,
+            expiry,
+            get,
+        } => {
+            let result = db.set_with(key, value.clone().into_bytes(), *condition, *expiry, *get);
+            return Some(match result {
+                Ok((_, old)) if *get => bulk_bytes(old.as_deref()),
+                Ok((true, _)) => simple_string("OK").into_bytes(),
+                Ok((false, _)) => bulk_bytes(None),
+                Err(err) => simple_error(&err.to_string()).into_bytes(),
+            });
+        }
+        Command::SetNx { key, value } => db
+            .set_with(
+                key,
+                value.clone().into_bytes(),
+                Some(SetCondition::IfAbsent),
+                None,
+                false,
+            )
+            .map(|(set, _)| integer(set as i64)),
+        Command::MGet { keys } => {
+            let values = db.mget(keys);
+            let mut reply = format!("*{}\r\n", values.len()).into_bytes();
+            for value in values {
+                reply.extend(bulk_bytes(value.as_deref()));
+            }
+            return Some(reply);
+        }
+        Command::Del { keys, lazy } => {
+            let removed = if *lazy {
+                db.unlink(keys)
+            } else {
+                db.remove_all(keys)
+            };
+            Ok(integer(removed as i64))
+        }
+        Command::Expire { key, at, flags } => Ok(integer(db.expire(key, *at, *flags) as i64)),
+        Command::Keys { pattern } => Ok(strings(&db.keys(pattern))),
+        Command::Scan {
+            cursor,
+            pattern,
+            count,
+            type_name,
+        } => {
+            let (cursor, keys) =
+                db.scan_keys(*cursor, pattern.as_deref(), *count, type_name.as_deref());
+            Ok(scan_reply(
+                cursor,
+                &keys.iter().map(String::as_str).collect(),
+            ))
+        }
+        Command::Type { key } => Ok(simple_string(db.type_of(key).unwrap_or("none"))),
+        Command::Copy {
+            source,
+            destination,
+            replace,
+        } => Ok(integer(db.copy(source, destination, *replace) as i64)),
+        Command::Restore {
+            key,
+            payload,
+            at,
+            replace,
+        } => db
+            .restore(key, payload, *at, *replace)
+            .map(|()| simple_string("OK")),
+        Command::Exists { keys } => Ok(integer(db.exists(keys) as i64)),
+        Command::Touch { keys } => Ok(integer(db.touch(keys) as i64)),
+        Command::Incr { key, delta } => db.incr_by(key, *delta).map(integer),
+        Command::IncrByFloat { key, delta } => db
+            .incr_by_float(key, *delta)
+            .map(|value| bulk_string(Some(&value))),
+        Command::Append { key, value } => db
+            .append(key, value.as_bytes())
+            .map(|len| integer(len as i64)),
+        Command::MSet {
+            pairs,
+            if_absent: false,
+        } => {
+            db.mset(pairs);
+            Ok(simple_string("OK"))
+        }
+        Command::MSet {
+            pairs,
+            if_absent: true,
+        } => Ok(integer(db.mset_if_absent(pairs) as i64)),
+        Command::StrLen { key } => db.strlen(key).map(|len| integer(len as i64)),
+        Command::Push { key, values, end } => {
+            db.push(key, values, *end).map(|len| integer(len as i64))
+        }
+        Command::Pop { key, count, end } => {
+            db.pop(key, count.unwrap_or(1), *end)
+                .map(|popped| match (popped, count) {
+                    (None, None) => bulk_string(None),
+                    (None, Some(_)) => null_array(),
+                    (Some(popped), None) => bulk_string(popped.first().map(String::as_str)),
+                    (Some(popped), Some(_)) => strings(&popped),
+                })
+        }
+        Command::Move {
+            source,
+            destination,
+            from,
+            to,
+        } => db
+            .lmove(source, destination, *from, *to)
+            .map(|element| bulk_string(element.as_deref())),
+        Command::MPop { keys, end, count } => {
+            db.pop_first(keys, *count, *end).map(|popped| match popped {
+                None => null_array(),
+                Some((key, values)) => popped_from(&key, &values),
+            })
+        }
+        Command::LRange { key, start, stop } => {
+            db.lrange(key, *start, *stop).map(|items| strings(&items))
+        }
+        Command::LLen { key } => db.llen(key).map(|len| integer(len as i64)),
+        Command::LPos {
+            key,
+            element,
+            rank,
+            count,
+            maxlen,
+        } => db
+            .lpos(key, element, *rank, count.unwrap_or(1), *maxlen)
+            .map(|indexes| match count {
+                None => match indexes.first() {
+                    None => bulk_string(None),
+                    Some(index) => integer(*index as i64),
+                },
+                Some(_) => {
+                    let items: Vec<String> =
+                        indexes.iter().map(|index| integer(*index as i64)).collect();
+                    encoded_array(&items)
+                }
+            }),
+        Command::LInsert {
+            key,
+            before,
+            pivot,
+            element,
+        } => db.linsert(key, *before, pivot, element).map(integer),
+        Command::LSet {
+            key,
+            index,
+            element,
+        } => db.lset(key, *index, element).map(|_| simple_string("OK")),
+        Command::LRem {
+            key,
+            count,
+            element,
+        } => db
+            .lrem(key, *count, element)
+            .map(|removed| integer(removed as i64)),
+        Command::LTrim { key, start, stop } => {
+            db.ltrim(key, *start, *stop).map(|_| simple_string("OK"))
+        }
+        Command::HSet { key, pairs } => db.hset(key, pairs).map(|added| integer(added as i64)),
+        Command::HSetNx { key, field, value } => {
+            db.hsetnx(key, field, value).map(|set| integer(set as i64))
+        }
+        Command::HIncrBy { key, field, delta } => db.hincrby(key, field, *delta).map(integer),
+        Command::HIncrByFloat { key, field, delta } => db
+            .hincrbyfloat(key, field, *delta)
+            .map(|value| bulk_string(Some(&value))),
+        Command::HGet { key, field } => db
+            .hget(key, field)
+            .map(|value| bulk_string(value.as_deref())),
+        Command::HMGet { key, fields } => db.hmget(key, fields).map(|values| {
+            let items: Vec<String> = values
+                .iter()
+                .map(|value| bulk_string(value.as_deref()))
+                .collect();
+            encoded_array(&items)
+        }),
+        Command::HDel { key, fields } => {
+            db.hdel(key, fields).map(|removed| integer(removed as i64))
+        }
+        Command::HGetAll { key } => db.hgetall(key).map(|pairs| array(&flatten(&pairs))),
+        Command::HExists { key, field } => {
+            db.hexists(key, field).map(|exists| integer(exists as i64))
+        }
+        Command::HLen { key } => db.hlen(key).map(|len| integer(len as i64)),
+        Command::HKeys { key } => db.hkeys(key).map(|fields| strings(&fields)),
+        Command::HVals { key } => db.hvals(key).map(|values| strings(&values)),
+        Command::HScan {
+            key,
+            cursor,
+            pattern,
+            count,
+        } => db
+            .hscan(key, *cursor, pattern.as_deref(), *count)
+            .map(|(cursor, pairs)| scan_reply(cursor, &flatten(&pairs))),
+        Command::HRandField {
+            key,
+            count,
+            with_values,
+        } => db
+            .hrandfield(key, count.unwrap_or(1))
+            .map(|pairs| match count {
+                None => bulk_string(pairs.first().map(|(field, _)| field.as_str())),
+                Some(_) if *with_values => array(&flatten(&pairs)),
+                Some(_) => array(&pairs.iter().map(|(field, _)| field.as_str()).collect()),
+            }),
+        Command::SAdd { key, members } => db.sadd(key, members).map(|added| integer(added as i64)),
+        Command::SRem { key, members } => {
+            db.srem(key, members).map(|removed| integer(removed as i64))
+        }
+        Command::SMembers { key } => db.smembers(key).map(|members| strings(&members)),
+        Command::SIsMember { key, member } => {
+            db.sismember(key, member).map(|found| integer(found as i64))
+        }
+        Command::SCard { key } => db.scard(key).map(|len| integer(len as i64)),
+        Command::SRandMember { key, count } => {
+            db.srandmember(key, count.unwrap_or(1))
+                .map(|members| match count {
+                    None => bulk_string(members.first().map(String::as_str)),
+                    Some(_) => strings(&members),
+                })
+        }
+        Command::SMove {
+            source,
+            destination,
+            member,
+        } => db
+            .smove(source, destination, member)
+            .map(|moved| integer(moved as i64)),
+        Command::SMIsMember { key, members } => db.smismember(key, members).map(|found| {
+            let items: Vec<String> = found.iter().map(|found| integer(*found as i64)).collect();
+            encoded_array(&items)
+        }),
+        Command::SInterCard { keys, limit } => db
+            .sintercard(keys, *limit)
+            .map(|count| integer(count as i64)),
+        Command::SetOp { op, keys } => db.setop(*op, keys).map(|members| strings(&members)),
+        Command::SetOpStore {
+            op,
+            destination,
+            keys,
+        } => db
+            .setop_store(*op, destination, keys)
+            .map(|len| integer(len as i64)),
+        Command::ZAdd { key, flags, pairs } => db.zadd(key, *flags, pairs).map(|result| {
+            if flags.incr {
+                bulk_string(result.score.map(|score| score.to_string()).as_deref())
+            } else if flags.ch {
+                integer((result.added + result.changed) as i64)
+            } else {
+                integer(result.added as i64)
+            }
+        }),
+        Command::ZScore { key, member } => db
+            .zscore(key, member)
+            .map(|score| bulk_string(score.map(|score| score.to_string()).as_deref())),
+        Command::ZIncrBy { key, delta, member } => db
+            .zincrby(key, *delta, member)
+            .map(|score| bulk_string(Some(&score.to_string()))),
+        Command::ZRank {
+            key,
+            member,
+            rev,
+            with_score,
+        } => db
+            .zrank(key, member, *rev)
+            .map(|rank| match (rank, with_score) {
+                (None, false) => bulk_string(None),
+                (None, true) => null_array(),
+                (Some((rank, _)), false) => integer(rank as i64),
+                (Some((rank, score)), true) => {
+                    encoded_array(&[integer(rank as i64), bulk_string(Some(&score.to_string()))])
+                }
+            }),
+        Command::ZMScore { key, members } => db.zmscore(key, members).map(|scores| {
+            let items: Vec<String> = scores
+                .iter()
+                .map(|score| bulk_string(score.map(|score| score.to_string()).as_deref()))
+                .collect();
+            encoded_array(&items)
+        }),
+        Command::ZCard { key } => db.zcard(key).map(|len| integer(len as i64)),
+        Command::ZRandMember {
+            key,
+            count,
+            with_scores,
+        } => db
+            .zrandmember(key, count.unwrap_or(1))
+            .map(|members| match count {
+                None => bulk_string(members.first().map(|(member, _)| member.as_str())),
+                Some(_) => scored(&members, *with_scores),
+            }),
+        Command::ZRange {
+            key,
+            start,
+            stop,
+            rev,
+            with_scores,
+        } => db
+            .zrange(key, *start, *stop, *rev)
+            .map(|members| scored(&members, *with_scores)),
+        Command::ZRangeByScore {
+            key,
+            min,
+            max,
+            rev,
+            with_scores,
+            offset,
+            count,
+        } => db
+            .zrange_by_score(key, *min, *max, *rev, *offset, *count)
+            .map(|members| scored(&members, *with_scores)),
+        Command::ZRangeByLex {
+            key,
+            min,
+            max,
+            rev,
+            offset,
+            count,
+        } => db
+            .zrange_by_lex(key, min, max, *rev, *offset, *count)
+            .map(|members| scored(&members, false)),
+        Command::ZRangeStore {
+            destination,
+            source,
+            range,
+            rev,
+            offset,
+            count,
+        } => db
+            .zrangestore(destination, source, range, *rev, *offset, *count)
+            .map(|len| integer(len as i64)),
+        Command::ZCount { key, range } => db.zcount(key, range).map(|len| integer(len as i64)),
+        Command::ZRemRange { key, range } => {
+            db.zremrange(key, range).map(|len| integer(len as i64))
+        }
+        Command::ZCombine {
+            op,
+            keys,
+            weights,
+            aggregate,
+            with_scores,
+        } => db
+            .zcombine(*op, keys, weights, *aggregate)
+            .map(|members| scored(&members, *with_scores)),
+        Command::ZCombineStore {
+            op,
+            destination,
+            keys,
+            weights,
+            aggregate,
+        } => db
+            .zcombine_store(*op, destination, keys, weights, *aggregate)
+            .map(|len| integer(len as i64)),
+        Command::SetBit { key, offset, bit } => {
+            db.setbit(key, *offset, *bit).map(|old| integer(old as i64))
+        }
+        Command::GetBit { key, offset } => db.getbit(key, *offset).map(|bit| integer(bit as i64)),
+        Command::BitCount { key, range, bits } => db
+            .bitcount(key, *range, *bits)
+            .map(|count| integer(count as i64)),
+        Command::BitField { key, ops } => db.bitfield(key, ops).map(|values| {
+            let values: Vec<String> = values
+                .into_iter()
+                .map(|value| value.map_or_else(|| bulk_string(None), integer))
+                .collect();
+            encoded_array(&values)
+        }),
+        Command::GeoPos { key, members } => db.geopos(key, members).map(|points| {
+            let points: Vec<String> = points
+                .into_iter()
+                .map(|point| match point {
+                    Some((lon, lat)) => strings(&[lon.to_string(), lat.to_string()]),
+                    None => null_array(),
+                })
+                .collect();
+            encoded_array(&points)
+        }),
+        Command::GeoDist {
+            key,
+            from,
+            to,
+            unit,
+        } => db.geodist(key, from, to).map(|distance| {
+            bulk_string(
+                distance
+                    .map(|distance| format!("{:.4}", distance / unit))
+                    .as_deref(),
+            )
+        }),
+        Command::GeoSearch {
+            key,
+            query,
+            unit,
+            with_coord,
+            with_dist,
+            with_hash,
+        } => db.geosearch(key, query).map(|matches| {
+            let matches: Vec<String> = matches
+                .into_iter()
+                .map(|found| {
+                    if !(*with_coord || *with_dist || *with_hash) {
+                        return bulk_string(Some(&found.member));
+                    }
+                    let mut item = vec![bulk_string(Some(&found.member))];
+                    if *with_dist {
+                        item.push(bulk_string(Some(&format!("{:.4}", found.distance / unit))));
+                    }
+                    if *with_hash {
+                        item.push(integer(found.hash as i64));
+                    }
+                    if *with_coord {
+                        item.push(strings(&[found.lon.to_string(), found.lat.to_string()]));
+                    }
+                    encoded_array(&item)
+                })
+                .collect();
+            encoded_array(&matches)
+        }),
+        Command::GeoSearchStore {
+            destination,
+            source,
+            query,
+            unit,
+            store_dist,
+        } => db
+            .geosearchstore(destination, source, query, store_dist.then_some(*unit))
+            .map(|len| integer(len as i64)),
+        Command::XAdd {
+            key,
+            id,
+            fields,
+            create,
+        } => db
+            .xadd(key, *id, fields, *create)
+            .map(|id| bulk_string(id.map(|id| id.to_string()).as_deref())),
+        Command::XRead {
+            keys,
+            ids,
+            count,
+            block: None,
+        } => ids
+            .iter()
+            .zip(keys)
+            .map(|(id, key)| match id {
+                Some(id) => Ok(*id),
+                None => db.xlast_id(key),
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|ids| db.xread(keys, &ids, *count))
+            .map(|streams| stream_reply(&streams)),
+        Command::XSetId {
+            key,
+            id,
+            entries_added,
+            max_deleted_id,
+        } => db
+            .xsetid(key, *id, *entries_added, *max_deleted_id)
+            .map(|()| simple_string("OK")),
+        Command::XInfoStream { key } => db.xinfo_stream(key).map(|info| stream_info(&info)),
+        _ => return None,
+    };
+    let reply = reply.unwrap_or_else(|err: DbError| simple_error(&err.to_string()));
+    Some(reply.into_bytes())
+}
+
+/// The `[key, [element ...]]` reply of LMPOP and BLMPOP
+pub fn popped_from(key: &str, values: &[String]) -> String {
+    let values = strings(values);
+    encoded_array(&[bulk_string(Some(key)), values])
+}
+
+fn strings(items: &[String]) -> String {
+    array(&items.iter().map(String::as_str).collect())
+}
+
+/// Field/value pairs as the flat list Redis replies with
+fn flatten(pairs: &[(String, String)]) -> Vec<&str> {
+    pairs
+        .iter()
+        .flat_map(|(field, value)| [field.as_str(), value.as_str()])
+        .collect()
+}
+
+/// The `[cursor, [item ...]]` reply of the SCAN family
+fn scan_reply(cursor: u64, items: &Vec<&str>) -> String {
+    encoded_array(&[bulk_string(Some(&cursor.to_string())), array(items)])
+}
+
+/// XREAD's reply: each stream with its entries, or a null array when
+/// there are none.
+pub fn stream_reply(streams: &[(String, Vec<StreamEntry>)]) -> String {
+    if streams.is_empty() {
+        return null_array();
+    }
+    let streams: Vec<String> = streams
+        .iter()
+        .map(|(key, entries)| {
+            let entries: Vec<String> = entries.iter().map(stream_entry).collect();
+            encoded_array(&[bulk_string(Some(key)), encoded_array(&entries)])
+        })
+        .collect();
+    encoded_array(&streams)
+}
+
+fn stream_entry((id, fields): &StreamEntry) -> String {
+    let id = id.to_string();
+    encoded_array(&[bulk_string(Some(&id)), array(&flatten(fields))])
+}
+
+fn stream_info(info: &StreamInfo) -> String {
+    let id = |id: StreamId| bulk_string(Some(&id.to_string()));
+    let entry = |entry: &Option<StreamEntry>| match entry {
+        Some(entry) => stream_entry(entry),
+        None => bulk_string(None),
+    };
+    let first_id = info.first.as_ref().map(|(id, _)| *id).unwrap_or_default();
+    encoded_array(&[
+        bulk_string(Some("length")),
+        integer(info.length as i64),
+        bulk_string(Some("last-generated-id")),
+        id(info.last_id),
+        bulk_string(Some("max-deleted-entry-id")),
+        id(info.max_deleted_id),
+        bulk_string(Some("entries-added")),
+        integer(info.entries_added as i64),
+        bulk_string(Some("recorded-first-entry-id")),
+        id(first_id),
+        bulk_string(Some("groups")),
+        integer(0),
+        bulk_string(Some("first-entry")),
+        entry(&info.first),
+        bulk_string(Some("last-entry")),
+        entry(&info.last),
+    ])
+}
+
+/// Sorted set members, each followed by its score when asked for
+fn scored(members: &[(String, f64)], with_scores: bool) -> String {
+    let mut items = vec![];
+    for (member, score) in members {
+        items.push(member.clone());
+        if with_scores {
+            items.push(score.to_string());
+        }
+    }
+    strings(&items)
+}
@@ -0,0 +1,84 @@
+/// Redis-style glob matching as used by MATCH and KEYS: `*`, `?`, `[abc]`,
+/// `[^abc]`, `[a-z]` and `\` to escape the next character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // as in Redis's stringmatchlen, only the last star is ever retried: the
+    // pattern after it, and how much of the text it has taken so far
+    let mut star = None;
+    while t < text.len() {
+        let next = match pattern.get(p) {
+            Some('*') => {
+                while pattern.get(p) == Some(&'*') {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                star = Some((p, t));
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => {
+                let (matched, rest) = match_class(&pattern[p + 1..], text[t]);
+                matched.then_some(pattern.len() - rest.len())
+            }
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(p + 2),
+            Some(literal) => (*literal == text[t]).then_some(p + 1),
+            None => None,
+        };
+        match (next, star.as_mut()) {
+            (Some(next), _) => {
+                p = next;
+                t += 1;
+            }
+            // let the star take one more character and try again from there
+            (None, Some((star_p, star_t))) => {
+                *star_t += 1;
+                p = *star_p;
+                t = *star_t;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against a character class whose opening `[` was already
+/// consumed, returning the result and the pattern after the closing `]`.
+fn match_class(mut pattern: &[char], c: char) -> (bool, &[char]) {
+    let negated = pattern.first() == Some(&'^');
+    if negated {
+        pattern = &pattern[1..];
+    }
+    let mut matched = false;
+    loop {
+        match pattern {
+            // an unterminated class ends with the pattern, like in Redis
+            [] => break,
+            [']', rest @ ..] => {
+                pattern = rest;
+                break;
+            }
+            ['\\', escaped, rest @ ..] => {
+                matched |= *escaped == c;
+                pattern = rest;
+            }
+            [start, '-', end, rest @ ..] if *end != ']' => {
+                let (low, high) = if start <= end {
+                    (*start, *end)
+                } else {
+                    (*end, *start)
+                };
+                matched |= (low..=high).contains(&c);
+                pattern = rest;
+            }
+            [literal, rest @ ..] => {
+                matched |= *literal == c;
+                pattern = rest;
+            }
+        }
+    }
+    (matched != negated, pattern)
+}
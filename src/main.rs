@@ -1,124 +1,678 @@
-use std::sync::Arc;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use clap::{Arg, Command as ClapCommand};
-use tokio::net::{TcpListener, TcpStream};
+use clap::{Arg, ArgAction, Command as ClapCommand};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use tokio::{select, time};
 
 use db::DB;
 
-use crate::master::Replicas;
-use crate::replica::sync_with_master;
+use crate::aof::{Aof, Fsync};
+use crate::config::Config;
+use crate::master::{Replicas, Waiters};
+use crate::replica::{replicate, MasterState};
 
+mod analytics;
+mod aof;
+mod check;
 mod command;
+mod config;
+mod dataset;
 mod db;
+mod exec;
+mod glob;
 mod master;
+mod memcache;
 mod parse;
+mod rdb;
 mod replica;
+mod systemd;
 
-const EMPTY: &[u8] = b"524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2";
 const PONG: &[u8] = b"+PONG\r\n";
 const OK: &[u8] = b"+OK\r\n";
-const ERR: &[u8] = b"-ERR\r\n";
+/// Seconds to wait after a failed background save before the next save point
+const BGSAVE_RETRY_DELAY: u64 = 5;
 
-#[allow(dead_code)]
 #[derive(Debug)]
 enum Role {
     Master,
     Replica { host: String, port: String },
 }
 
+/// Replication ids as in Redis: the current one, and the one before it,
+/// which replicas may still resume with up to the offset it ended at.
+#[derive(Debug, Clone)]
+pub struct ReplIds {
+    pub id: String,
+    pub id2: String,
+    /// The last offset replicas may resume from with `id2`, -1 for none
+    pub second_offset: i64,
+}
+
+impl ReplIds {
+    fn new() -> Self {
+        Self {
+            id: random_replid(),
+            id2: "0".repeat(40),
+            second_offset: -1,
+        }
+    }
+}
+
+/// 40 random hex characters, from the random keys std seeds hashers with.
+fn random_replid() -> String {
+    let mut id = String::new();
+    while id.len() < 40 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        id += &format!("{:016x}", hasher.finish());
+    }
+    id.truncate(40);
+    id
+}
+
 #[derive(Debug)]
 struct Server {
-    port: String,
-    role: Role,
+    config: Config,
+    /// Changed by REPLICAOF at runtime
+    role: RwLock<Role>,
+    /// The task following the master while a replica
+    sync_task: Mutex<Option<AbortHandle>>,
+    /// UNIX time in seconds of the last successful save, or of startup
+    last_save: AtomicU64,
+    bgsave_in_progress: AtomicBool,
+    last_bgsave_ok: AtomicBool,
+    /// Writes since the last successful save, counted as they propagate
+    dirty: Arc<AtomicU64>,
+    /// Bytes written to the replication stream, shared with the replicas
+    repl_offset: Arc<AtomicU64>,
+    replids: RwLock<ReplIds>,
+    /// Where the RDB loaded at startup left off in its master's stream, for
+    /// the first sync to resume from
+    resume: Mutex<Option<MasterState>>,
+    /// Set while a shutdown waits on the replicas, which refuses writes
+    shutting_down: AtomicBool,
+    /// Connections open, for maxclients
+    clients: AtomicUsize,
 }
 
 impl Server {
-    pub fn new(port: String, role: Role) -> Self {
-        Self { port, role }
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            role: RwLock::new(Role::Master),
+            sync_task: Mutex::new(None),
+            last_save: AtomicU64::new(unix_time()),
+            bgsave_in_progress: AtomicBool::new(false),
+            last_bgsave_ok: AtomicBool::new(true),
+            dirty: Arc::new(AtomicU64::new(0)),
+            repl_offset: Arc::new(AtomicU64::new(0)),
+            replids: RwLock::new(ReplIds::new()),
+            resume: Mutex::new(None),
+            shutting_down: AtomicBool::new(false),
+            clients: AtomicUsize::new(0),
+        }
     }
-    pub fn replid(&self) -> &str {
-        "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb"
+    pub fn port(&self) -> String {
+        self.config.port()
     }
-    pub fn offset(&self) -> &str {
-        "0"
+    pub fn is_replica(&self) -> bool {
+        matches!(*self.role.read().unwrap(), Role::Replica { .. })
     }
 
-    pub fn info(&self) -> Vec<(&str, &str)> {
-        let mut result = vec![];
-        let role = match self.role {
-            Role::Master => "master",
-            Role::Replica { .. } => "slave",
+    /// REPLICAOF host port: follows that master from now on, in place of any
+    /// before it, syncing from scratch. False if it already did.
+    pub fn follow(
+        self: &Arc<Self>,
+        host: &str,
+        port: &str,
+        db: &DB,
+        replicas: &Replicas,
+        waiters: &Waiters,
+        synced: Option<oneshot::Sender<()>>,
+    ) -> bool {
+        let mut role = self.role.write().unwrap();
+        if let Role::Replica { host: h, port: p } = &*role {
+            if h == host && p == port {
+                return false;
+            }
+        }
+        *role = Role::Replica {
+            host: host.to_string(),
+            port: port.to_string(),
+        };
+        db.set_replica(true);
+        println!("[INFO] Connecting to MASTER {host}:{port}");
+        let task = tokio::spawn(replicate(
+            format!("{host}:{port}"),
+            self.clone(),
+            db.clone(),
+            replicas.clone(),
+            waiters.clone(),
+            synced,
+        ));
+        if let Some(previous) = self.sync_task.lock().unwrap().replace(task.abort_handle()) {
+            previous.abort();
+        }
+        true
+    }
+
+    /// REPLICAOF NO ONE: stops following the master and takes over from it
+    /// with the dataset as it is. Our own replicas may go on with the id we
+    /// had up to where we are now.
+    pub fn promote(&self, db: &DB) {
+        let mut role = self.role.write().unwrap();
+        if matches!(*role, Role::Master) {
+            return;
+        }
+        *role = Role::Master;
+        if let Some(task) = self.sync_task.lock().unwrap().take() {
+            task.abort();
+        }
+        db.set_replica(false);
+        let mut replids = self.replids.write().unwrap();
+        replids.id2 = std::mem::replace(&mut replids.id, random_replid());
+        replids.second_offset = self.offset() as i64 + 1;
+        println!("[INFO] MASTER MODE enabled");
+    }
+    pub fn replids(&self) -> ReplIds {
+        self.replids.read().unwrap().clone()
+    }
+    /// Takes on the history of the master we fully resynced with, so our own
+    /// replicas can go on with its id wherever they sync from.
+    pub fn adopt_replid(&self, id: &str) {
+        *self.replids.write().unwrap() = ReplIds {
+            id: id.to_string(),
+            id2: "0".repeat(40),
+            second_offset: -1,
         };
+    }
+    /// Our master went on with a new id, as after a failover. Our replicas
+    /// may still resume with the old one up to where we are now.
+    pub fn switch_replid(&self, id: &str) {
+        let mut replids = self.replids.write().unwrap();
+        replids.id2 = std::mem::replace(&mut replids.id, id.to_string());
+        replids.second_offset = self.offset() as i64 + 1;
+    }
+    pub fn offset(&self) -> u64 {
+        self.repl_offset.load(Ordering::Relaxed)
+    }
+    /// Where a replica is in its master's stream, which it passes on as is.
+    pub fn set_offset(&self, offset: u64) {
+        self.repl_offset.store(offset, Ordering::Relaxed);
+    }
+
+    /// The state saved from the RDB at startup, taken by the first sync.
+    pub fn take_resume(&self) -> Option<MasterState> {
+        self.resume.lock().unwrap().take()
+    }
 
-        result.push(("role", role));
-        result.push(("master_replid", self.replid()));
-        result.push(("master_repl_offset", self.offset()));
+    /// What a saved RDB records of replication: our id and offset, which a
+    /// replica got from its master, and that master's address.
+    fn repl_info(&self) -> rdb::ReplInfo {
+        let master = match &*self.role.read().unwrap() {
+            Role::Master => None,
+            Role::Replica { host, port } => Some(format!("{host} {port}")),
+        };
+        rdb::ReplInfo {
+            replid: self.replids().id,
+            offset: self.offset(),
+            master,
+        }
+    }
+
+    pub fn rdb_path(&self) -> PathBuf {
+        Path::new(&self.config.get("dir")).join(self.config.get("dbfilename"))
+    }
+
+    /// SAVE: writes the dataset to the RDB file before returning.
+    pub fn save(&self, db: &DB) -> anyhow::Result<()> {
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        let entries = db.snapshot();
+        rdb::save(&entries, Some(&self.repl_info()), &self.rdb_path())?;
+        self.saved(dirty);
+        println!("[INFO] DB saved on disk");
+        Ok(())
+    }
+
+    /// SHUTDOWN, and SIGTERM and SIGINT: stops taking writes and, on a
+    /// master, gives its replicas up to shutdown-timeout seconds to get all
+    /// written so far, unless `now`. Then syncs the AOF and saves the RDB if
+    /// `save`, by default if save points are set. False if the save failed
+    /// and not `force`: the server then goes on taking writes, as in Redis.
+    pub async fn shutdown(
+        &self,
+        db: &DB,
+        replicas: &Replicas,
+        save: Option<bool>,
+        now: bool,
+        force: bool,
+    ) -> bool {
+        self.shutting_down.store(true, Ordering::Relaxed);
+        let timeout: u64 = self.config.get("shutdown-timeout").parse().unwrap_or(0);
+        if !now && timeout > 0 && !self.is_replica() {
+            let lagging = replicas.handoff(Duration::from_secs(timeout)).await;
+            if lagging > 0 {
+                println!("[WARN] {lagging} replicas are lagging when shutting down");
+            }
+        }
+        replicas.sync_aof();
+        if save.unwrap_or(!self.config.save_points().is_empty()) {
+            println!("[INFO] Saving the final RDB snapshot before exiting.");
+            if let Err(err) = self.save(db) {
+                if !force {
+                    eprintln!("[ERROR] Error trying to save the DB, can't exit: {err}");
+                    self.shutting_down.store(false, Ordering::Relaxed);
+                    return false;
+                }
+                eprintln!("[ERROR] Error trying to save the DB, exiting anyway: {err}");
+            }
+        }
+        println!("[INFO] Redis is now ready to exit, bye bye...");
+        true
+    }
+    pub fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::Relaxed)
+    }
+
+    /// Records a save of a snapshot taken when there were `dirty` writes,
+    /// leaving those made since.
+    fn saved(&self, dirty: u64) {
+        self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+        self.last_save.store(unix_time(), Ordering::Relaxed);
+    }
+
+    /// BGSAVE: takes a snapshot of the dataset now and writes it on a
+    /// blocking thread. Returns false if a background save is running.
+    pub fn bgsave(self: &Arc<Self>, db: &DB) -> bool {
+        if self.bgsave_in_progress.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        let entries = db.snapshot();
+        let repl = self.repl_info();
+        let path = self.rdb_path();
+        let server = self.clone();
+        println!("[INFO] Background saving started");
+        tokio::task::spawn_blocking(move || {
+            let result = rdb::save(&entries, Some(&repl), &path);
+            match &result {
+                Ok(()) => {
+                    server.saved(dirty);
+                    println!("[INFO] Background saving terminated with success");
+                }
+                Err(err) => eprintln!("[ERROR] Background saving error: {err}"),
+            }
+            server
+                .last_bgsave_ok
+                .store(result.is_ok(), Ordering::Relaxed);
+            server.bgsave_in_progress.store(false, Ordering::Release);
+        });
+        true
+    }
+
+    pub fn info(&self) -> Vec<(&str, String)> {
+        let mut result = vec![];
+        match &*self.role.read().unwrap() {
+            Role::Master => result.push(("role", "master".to_string())),
+            Role::Replica { host, port } => {
+                result.push(("role", "slave".to_string()));
+                result.push(("master_host", host.clone()));
+                result.push(("master_port", port.clone()));
+            }
+        }
+        let replids = self.replids();
+        result.push(("master_replid", replids.id));
+        result.push(("master_replid2", replids.id2));
+        result.push(("master_repl_offset", self.offset().to_string()));
+        result.push(("second_repl_offset", replids.second_offset.to_string()));
+
+        let flag = |on: bool| if on { "1" } else { "0" }.to_string();
+        let dirty = self.dirty.load(Ordering::Relaxed);
+        let bgsave_in_progress = self.bgsave_in_progress.load(Ordering::Acquire);
+        let last_bgsave_ok = self.last_bgsave_ok.load(Ordering::Relaxed);
+        let aof_enabled = self.config.get("appendonly") == "yes";
+        result.push(("rdb_changes_since_last_save", dirty.to_string()));
+        result.push(("rdb_bgsave_in_progress", flag(bgsave_in_progress)));
+        result.push((
+            "rdb_last_save_time",
+            self.last_save.load(Ordering::Relaxed).to_string(),
+        ));
+        let status = if last_bgsave_ok { "ok" } else { "err" };
+        result.push(("rdb_last_bgsave_status", status.to_string()));
+        result.push(("aof_enabled", flag(aof_enabled)));
 
         result
     }
 }
 
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
 #[tokio::main]
 async fn main() {
     let matches = ClapCommand::new("App Command Parser")
         .version("1.0")
         .author("Your Name")
         .about("Parses app command with port and optional replicaof")
+        .args(Config::args())
         .arg(
-            Arg::new("port")
-                .short('p')
-                .long("port")
-                .value_name("PORT")
-                .help("Sets the port number")
+            Arg::new("check-rdb")
+                .long("check-rdb")
+                .value_name("FILE")
+                .help("Validates an RDB file, prints a report and exits")
                 .required(false),
         )
         .arg(
-            Arg::new("replicaof")
-                .long("replicaof")
-                .value_names(["MASTER_HOST", "MASTER_PORT"])
-                .help("Sets the master host and port for replication")
+            Arg::new("check-aof")
+                .long("check-aof")
+                .value_name("FILE")
+                .help("Validates an AOF file and exits")
                 .required(false),
         )
+        .arg(
+            Arg::new("fix")
+                .long("fix")
+                .action(ArgAction::SetTrue)
+                .requires("check-aof")
+                .help("Truncates the AOF checked by --check-aof to its last complete command"),
+        )
         .get_matches();
 
-    let port = matches
-        .get_one::<String>("port")
-        .map_or("6379".to_string(), |v| v.clone());
-    let role = match matches.get_many::<String>("replicaof") {
-        Some(mut values) => Role::Replica {
-            host: values.next().unwrap().clone(),
-            port: values.next().unwrap().clone(),
-        },
-        None => Role::Master,
-    };
+    if let Some(path) = matches.get_one::<String>("check-rdb") {
+        if let Err(err) = check::check_rdb(path) {
+            eprintln!("--- RDB ERROR DETECTED ---");
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(path) = matches.get_one::<String>("check-aof") {
+        if let Err(err) = check::check_aof(path, matches.get_flag("fix")) {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let server = Server::new(port, role);
+    let config = match Config::load(&matches) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("*** FATAL CONFIG ERROR ***");
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    };
+    let server = Server::new(config);
 
     start_server(server).await;
 }
 
+/// Replays the AOF into the dataset and opens it for appending, when
+/// appendonly is on. Exits if it can't be loaded, as Redis does.
+fn open_aof(server: &Server, db: &DB) -> Option<Aof> {
+    if server.config.get("appendonly") != "yes" {
+        return None;
+    }
+    let path = Path::new(&server.config.get("dir")).join(server.config.get("appendfilename"));
+    let load_truncated = server.config.get("aof-load-truncated") == "yes";
+    match aof::load(&path, load_truncated, db) {
+        Ok((keys, commands)) => println!(
+            "[INFO] DB loaded from append only file: {keys} keys from the RDB preamble, {commands} commands"
+        ),
+        Err(err) => {
+            eprintln!("*** FATAL AOF ERROR ***");
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+    }
+    let fsync = Fsync::parse(&server.config.get("appendfsync"));
+    match Aof::open(&path, fsync) {
+        Ok(aof) => Some(aof),
+        Err(err) => {
+            eprintln!("*** FATAL AOF ERROR ***");
+            eprintln!("Can't open the append-only file {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads the RDB file into the dataset, which is what persists it when
+/// appendonly is off, returning the replication state saved with it. Exits
+/// if it can't be read, as Redis does.
+fn load_rdb(server: &Server, db: &DB) -> Option<rdb::ReplInfo> {
+    let path = server.rdb_path();
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            eprintln!("*** FATAL RDB ERROR ***");
+            eprintln!("Can't read {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    };
+    match rdb::parse(&bytes) {
+        Ok(rdb) => {
+            let repl = rdb.repl_info();
+            let entries = rdb.databases.into_iter().flat_map(|db| db.entries);
+            let keys = db.load(entries.collect());
+            println!("[INFO] DB loaded from disk: {keys} keys");
+            repl
+        }
+        Err(err) => {
+            eprintln!("*** FATAL RDB ERROR ***");
+            eprintln!("Bad RDB file {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+}
+
 async fn start_server(server: Server) {
     let db = DB::new();
     let server = Arc::new(server);
-    let replicas = Replicas::new();
+    let aof = open_aof(&server, &db);
+    let saved = match aof {
+        None => load_rdb(&server, &db),
+        Some(_) => None,
+    };
+    let backlog_size = server.config.memory("repl-backlog-size") as usize;
+    let replicas = Replicas::new(
+        aof.clone(),
+        server.dirty.clone(),
+        server.repl_offset.clone(),
+        backlog_size,
+    );
+    let waiters = Waiters::new();
+
+    // a replica goes on following the master it saved with, unless told
+    // otherwise, and from where the saved dataset left off in the stream
+    let master = server.config.replicaof().or_else(|| {
+        let master = saved.as_ref()?.master.as_ref()?;
+        let (host, port) = master.split_once(' ')?;
+        Some((host.to_string(), port.to_string()))
+    });
+    let (synced_tx, synced_rx) = oneshot::channel();
+    if let Some((host, port)) = master {
+        if let Some(saved) = saved {
+            println!(
+                "[INFO] Resuming replication from offset {} of {}",
+                saved.offset, saved.replid
+            );
+            server.adopt_replid(&saved.replid);
+            server.set_offset(saved.offset);
+            *server.resume.lock().unwrap() = Some(MasterState {
+                replid: saved.replid,
+                offset: saved.offset as usize,
+            });
+        }
+        server.follow(&host, &port, &db, &replicas, &waiters, Some(synced_tx));
+    } else {
+        let _ = synced_tx.send(());
+    }
+
+    {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let mut hangup = signal(SignalKind::hangup()).unwrap();
+            while hangup.recv().await.is_some() {
+                println!("[INFO] Received SIGHUP, reloading configuration");
+                if let Err(err) = server.config.reload() {
+                    eprintln!("[ERROR] Config reload failed, keeping current values: {err}");
+                }
+            }
+        });
+    }
 
-    if let Role::Replica { host, port } = &server.role {
-        let master_addr = format!("{host}:{port}",);
+    // the saved dataset records where it stands in replication, so a
+    // replica restarted from it can resume rather than resync
+    {
         let server = server.clone();
         let db = db.clone();
+        let replicas = replicas.clone();
         tokio::spawn(async move {
-            let stream = TcpStream::connect(master_addr.clone()).await.unwrap();
-            if let Err(err) = sync_with_master(stream, server, db).await {
-                eprintln!("[ERROR] Replica: Disconnected from master with error: {err}")
-            } else {
-                eprintln!("[INFO] Replica: Disconnected from master")
+            let mut terminate = signal(SignalKind::terminate()).unwrap();
+            let mut interrupt = signal(SignalKind::interrupt()).unwrap();
+            loop {
+                select! {
+                    _ = terminate.recv() => {}
+                    _ = interrupt.recv() => {}
+                }
+                println!("[INFO] Received a shutdown signal, scheduling shutdown...");
+                if server.shutdown(&db, &replicas, None, false, false).await {
+                    std::process::exit(0);
+                }
             }
         });
     }
 
-    let addr = format!("127.0.0.1:{port}", port = server.port);
-    let listener = TcpListener::bind(addr).await.unwrap();
-    println!("Server listening on port :{port}", port = server.port);
+    // active expiry, which reclaims expired keys nobody looks up again,
+    // given a quarter of each cycle at most as in Redis
+    {
+        let db = db.clone();
+        let server = server.clone();
+        let mut replicas = replicas.clone();
+        tokio::spawn(async move {
+            loop {
+                let hz: u64 = server.config.get("hz").parse().unwrap_or(10);
+                let period = Duration::from_millis(1000 / hz);
+                time::sleep(period).await;
+                let effort = server
+                    .config
+                    .get("active-expire-effort")
+                    .parse()
+                    .unwrap_or(1);
+                // its deletes would move the offset the replicas catch up to
+                if server.shutting_down() {
+                    continue;
+                }
+                db.expire_cycle(effort, period / 4);
+                master::propagate_expired(&db, &mut replicas);
+            }
+        });
+    }
+
+    // save points, checked every second as they may change at runtime; after
+    // a failed background save the next try waits a few seconds, as in Redis
+    {
+        let db = db.clone();
+        let server = server.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(1));
+            let mut last_try = 0;
+            loop {
+                interval.tick().await;
+                let now = unix_time();
+                let failed = !server.last_bgsave_ok.load(Ordering::Relaxed);
+                if failed && now.saturating_sub(last_try) < BGSAVE_RETRY_DELAY {
+                    continue;
+                }
+                let dirty = server.dirty.load(Ordering::Relaxed);
+                let since = now.saturating_sub(server.last_save.load(Ordering::Relaxed));
+                let due = server
+                    .config
+                    .save_points()
+                    .into_iter()
+                    .find(|&(seconds, changes)| dirty >= changes && since >= seconds);
+                if let Some((seconds, changes)) = due {
+                    if !server.bgsave_in_progress.load(Ordering::Acquire) {
+                        println!("[INFO] {changes} changes in {seconds} seconds. Saving...");
+                        last_try = now;
+                        server.bgsave(&db);
+                    }
+                }
+            }
+        });
+    }
+
+    // appendfsync can change on a reload, so it is read again every time
+    if let Some(aof) = aof {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                let fsync = Fsync::parse(&server.config.get("appendfsync"));
+                aof.set_fsync(fsync);
+                if fsync == Fsync::EverySec {
+                    aof.sync();
+                }
+            }
+        });
+    }
+
+    if server.config.get("memcached-port") != "0" {
+        let addr = format!(
+            "{bind}:{port}",
+            bind = server.config.get("bind"),
+            port = server.config.get("memcached-port")
+        );
+        tokio::spawn(memcache::listen(
+            addr,
+            db.clone(),
+            server.clone(),
+            replicas.clone(),
+        ));
+    }
+
+    let listener = match systemd::inherited_listener() {
+        Some(listener) => {
+            println!("Server listening on socket passed by systemd");
+            TcpListener::from_std(listener).unwrap()
+        }
+        None => {
+            let addr = format!(
+                "{bind}:{port}",
+                bind = server.config.get("bind"),
+                port = server.port()
+            );
+            let listener = TcpListener::bind(addr).await.unwrap();
+            println!("Server listening on port :{port}", port = server.port());
+            listener
+        }
+    };
+
+    // a replica is only ready once its first sync with the master is done
+    tokio::spawn(async move {
+        if synced_rx.await.is_ok() {
+            systemd::notify("READY=1\nSTATUS=Ready to accept connections");
+        }
+    });
 
     while let Ok((stream, peer)) = listener.accept().await {
         println!("Client connected: {}", peer);
@@ -126,7 +680,10 @@ async fn start_server(server: Server) {
         let db = db.clone();
         let server = server.clone();
         let replicas = replicas.clone();
+        let waiters = waiters.clone();
 
-        tokio::spawn(master::client_handler(stream, peer, db, server, replicas));
+        tokio::spawn(master::client_handler(
+            stream, peer, db, server, replicas, waiters,
+        ));
     }
 }
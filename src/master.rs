@@ -1,69 +1,337 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
-use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::{select, time};
+use tokio::sync::{mpsc, Notify};
+use tokio::{select, task, time};
 
-use crate::command::{Command, Replconf};
-use crate::db::DB;
-use crate::parse::{array, bulk_string, pairs, tokenize};
-use crate::{Server, EMPTY, ERR, OK, PONG};
+use crate::analytics;
+use crate::aof::Aof;
+use crate::command::{Command, ObjectField, Replconf};
+use crate::dataset::{self, Source};
+use crate::db::{DbError, End, Expiry, SetCondition, Value, DB};
+use crate::parse::{
+    array, array_bytes, bulk_string, integer, null_array, pairs, simple_error, tokenize,
+};
+use crate::{exec, rdb, Server, OK, PONG};
 
-type Tx = mpsc::UnboundedSender<String>;
+type Tx = mpsc::UnboundedSender<Vec<u8>>;
+
+/// Commands a connection may run before giving other connections a turn
+const COMMAND_BUDGET: usize = 64;
+
+const IDLETIME_NOT_TRACKED: &str = "ERR An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.";
+const BGSAVE_IN_PROGRESS: &str = "ERR Background save already in progress";
+const AOF_REWRITE_IN_PROGRESS: &str =
+    "ERR Background append only file rewriting already in progress";
+const NOT_AN_INTEGER: &str = "ERR value is not an integer or out of range";
+const MAX_CLIENTS: &str = "ERR max number of clients reached";
+const NOAUTH: &str = "NOAUTH Authentication required.";
+const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+const AUTH_WITHOUT_PASSWORD: &str = "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?";
+const READONLY: &str = "READONLY You can't write against a read only replica.";
+const SHUTTING_DOWN: &str = "ERR The server is shutting down.";
+const FREQ_NOT_TRACKED: &str = "ERR An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.";
 
 #[derive(Clone)]
 struct Peer {
     addr: SocketAddr,
     tx: Tx,
+    /// The port a replica said it listens on, with REPLCONF listening-port
+    listening_port: Option<u16>,
 }
 
-struct Replica(Arc<Mutex<Peer>>);
+struct Replica {
+    peer: Mutex<Peer>,
+    /// How much of the stream the replica last said it has processed
+    acked: AtomicU64,
+    /// When it last did, or attached
+    last_ack: Mutex<Instant>,
+    /// Attached with SYNC, so it never acknowledges anything
+    legacy: bool,
+}
 
 impl Replica {
-    pub fn new(peer: Peer) -> Self {
-        Self(Arc::new(Mutex::new(peer)))
+    pub fn new(peer: Peer, acked: u64, legacy: bool) -> Self {
+        Self {
+            peer: Mutex::new(peer),
+            acked: AtomicU64::new(acked),
+            last_ack: Mutex::new(Instant::now()),
+            legacy,
+        }
     }
 
-    pub fn send(&self, val: String) {
-        self.0.lock().unwrap().tx.send(val).unwrap()
+    /// Queues part of the stream. A replica that hung up may still be here
+    /// until its connection removes it, and it simply misses out.
+    pub fn send(&self, val: Vec<u8>) {
+        let _ = self.peer.lock().unwrap().tx.send(val);
+    }
+}
+
+/// The tail of the replication stream, so a replica that lost its
+/// connection can pick up where it left off instead of resyncing fully.
+struct Backlog {
+    buf: VecDeque<u8>,
+    size: usize,
+    /// The server's master_repl_offset, the bytes written to the stream so
+    /// far, which only changes with the backlog locked
+    offset: Arc<AtomicU64>,
+}
+
+impl Backlog {
+    fn push(&mut self, msg: &[u8]) {
+        self.buf.extend(msg);
+        let excess = self.buf.len().saturating_sub(self.size);
+        self.buf.drain(..excess);
+        self.offset.fetch_add(msg.len() as u64, Ordering::Relaxed);
+    }
+
+    /// The stream after the first `offset` bytes, if that part is still kept.
+    fn since(&self, offset: u64) -> Option<Vec<u8>> {
+        let end = self.offset.load(Ordering::Relaxed);
+        let start = end - self.buf.len() as u64;
+        if !(start..=end).contains(&offset) {
+            return None;
+        }
+        Some(
+            self.buf
+                .range((offset - start) as usize..)
+                .copied()
+                .collect(),
+        )
     }
 }
 
 #[derive(Clone)]
 pub struct Replicas {
     peers: Arc<RwLock<HashMap<SocketAddr, Replica>>>,
+    /// Created when the first replica attaches, as in Redis, so one from
+    /// before a restart can't resume against a stream that never was.
+    /// Held while sending to the peers, to keep both in the same order.
+    backlog: Arc<Mutex<Option<Backlog>>>,
+    backlog_size: usize,
+    /// Woken by every REPLCONF ACK, for WAIT
+    acks: Arc<Notify>,
+    aof: Option<Aof>,
+    /// The server's count of writes since the last save
+    dirty: Arc<AtomicU64>,
+    /// The server's master_repl_offset
+    offset: Arc<AtomicU64>,
 }
 
 impl Replicas {
-    pub fn new() -> Self {
+    pub fn new(
+        aof: Option<Aof>,
+        dirty: Arc<AtomicU64>,
+        offset: Arc<AtomicU64>,
+        backlog_size: usize,
+    ) -> Self {
         Self {
             peers: Arc::new(RwLock::new(HashMap::new())),
+            backlog: Arc::new(Mutex::new(None)),
+            backlog_size,
+            acks: Arc::new(Notify::new()),
+            aof,
+            dirty,
+            offset,
+        }
+    }
+
+    /// Sends a write to every replica, and logs it to the AOF if there is one.
+    pub fn broadcast(&mut self, msg: impl AsRef<[u8]>) {
+        self.dirty.fetch_add(1, Ordering::Relaxed);
+        if let Some(aof) = &self.aof {
+            aof.append(msg.as_ref());
+        }
+        self.stream(msg.as_ref());
+    }
+
+    /// Passes our master's stream on to our own replicas as it came, so their
+    /// offsets and ours are the ones our master has. Only writes are logged,
+    /// and only those go to replicas that attached with SYNC.
+    pub fn proxy(&mut self, msg: &[u8], write: bool) {
+        if write {
+            self.broadcast(msg);
+        } else {
+            self.stream_to(msg, false);
         }
     }
 
-    pub fn broadcast(&mut self, msg: &str) {
+    /// Appends to the replication stream.
+    fn stream(&self, msg: &[u8]) {
+        self.stream_to(msg, true);
+    }
+
+    /// Appends to the replication stream, leaving out the replicas that
+    /// attached with SYNC unless `legacy`.
+    fn stream_to(&self, msg: &[u8], legacy: bool) {
+        let mut backlog = self.backlog.lock().unwrap();
+        if let Some(backlog) = backlog.as_mut() {
+            backlog.push(msg);
+        }
         // read lock only
         for (_, replica) in self.peers.read().unwrap().iter() {
-            replica.send(msg.to_owned())
+            if legacy || !replica.legacy {
+                replica.send(msg.to_vec())
+            }
+        }
+    }
+
+    /// Records a replica's REPLCONF ACK.
+    fn ack(&self, addr: &SocketAddr, offset: u64) {
+        if let Some(replica) = self.peers.read().unwrap().get(addr) {
+            replica.acked.fetch_max(offset, Ordering::Relaxed);
+            *replica.last_ack.lock().unwrap() = Instant::now();
         }
+        self.acks.notify_waiters();
     }
 
-    pub fn len(&self) -> usize {
-        self.peers.read().unwrap().len()
+    /// How many replicas have acknowledged the first `offset` bytes, of
+    /// those that acknowledge at all.
+    fn acked(&self, offset: u64) -> usize {
+        let peers = self.peers.read().unwrap();
+        peers
+            .values()
+            .filter(|replica| !replica.legacy && replica.acked.load(Ordering::Relaxed) >= offset)
+            .count()
     }
 
-    fn add(&mut self, peer: &Peer) {
+    /// Before shutting down: waits up to `timeout` for every replica to
+    /// have everything written so far, returning how many still lag behind.
+    pub async fn handoff(&self, timeout: Duration) -> usize {
+        // those that attached with SYNC never tell where they are
+        let count = self
+            .peers
+            .read()
+            .unwrap()
+            .values()
+            .filter(|replica| !replica.legacy)
+            .count();
+        if count == 0 {
+            return 0;
+        }
+        count - self.wait(count, Some(timeout)).await.min(count)
+    }
+
+    /// Flushes the AOF to disk, whatever appendfsync says.
+    pub fn sync_aof(&self) {
+        if let Some(aof) = &self.aof {
+            aof.sync();
+        }
+    }
+
+    /// WAIT: until `count` replicas have everything written so far, or the
+    /// timeout runs out, returning how many have. Replicas are asked for
+    /// their offset in the stream itself, so theirs stays in step with ours.
+    async fn wait(&self, count: usize, timeout: Option<Duration>) -> usize {
+        let offset = self.offset.load(Ordering::Relaxed);
+        // one too far off to represent never comes
+        let deadline = timeout.and_then(|timeout| time::Instant::now().checked_add(timeout));
+        let mut asked = false;
+        loop {
+            // enabled before counting, so an ACK in between still wakes us
+            let notified = self.acks.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let acked = self.acked(offset);
+            if acked >= count {
+                return acked;
+            }
+            if !asked {
+                // SYNC replicas would take it for a write
+                self.stream_to(array(&vec!["replconf", "getack", "*"]).as_bytes(), false);
+                asked = true;
+            }
+            let woken = match deadline {
+                None => {
+                    notified.await;
+                    true
+                }
+                Some(deadline) => time::timeout_at(deadline, notified).await.is_ok(),
+            };
+            if !woken {
+                return self.acked(offset);
+            }
+        }
+    }
+
+    /// Adds a replica for a full resync, returning the offset in the stream
+    /// that its snapshot starts from. `legacy` if it asked with SYNC.
+    fn attach(&mut self, peer: &Peer, legacy: bool) -> u64 {
+        let mut backlog = self.backlog.lock().unwrap();
+        // the stream goes on from where the last backlog left it
+        backlog.get_or_insert_with(|| Backlog {
+            buf: VecDeque::new(),
+            size: self.backlog_size,
+            offset: self.offset.clone(),
+        });
+        let offset = self.offset.load(Ordering::Relaxed);
+        self.add(peer, offset, legacy);
+        offset
+    }
+
+    /// Adds a replica that already has the first `offset` bytes of the
+    /// stream, sending it what it missed. False if that is no longer kept.
+    fn resume(&mut self, peer: &Peer, offset: u64) -> bool {
+        let backlog = self.backlog.lock().unwrap();
+        let Some(missed) = backlog.as_ref().and_then(|backlog| backlog.since(offset)) else {
+            return false;
+        };
+        // queued ahead of anything broadcast once the lock is released
+        if !missed.is_empty() {
+            let _ = peer.tx.send(missed);
+        }
+        self.add(peer, offset, false);
+        true
+    }
+
+    /// The replicas for INFO: how many, then a line for each.
+    pub fn info(&self) -> Vec<(String, String)> {
+        let peers = self.peers.read().unwrap();
+        let mut replicas: Vec<_> = peers.iter().collect();
+        replicas.sort_by_key(|(addr, _)| **addr);
+
+        let mut info = vec![("connected_slaves".to_string(), replicas.len().to_string())];
+        for (i, (addr, replica)) in replicas.into_iter().enumerate() {
+            let port = replica.peer.lock().unwrap().listening_port;
+            let line = format!(
+                "ip={ip},port={port},state=online,offset={offset},lag={lag}",
+                ip = addr.ip(),
+                port = port.unwrap_or(addr.port()),
+                offset = replica.acked.load(Ordering::Relaxed),
+                lag = replica.last_ack.lock().unwrap().elapsed().as_secs(),
+            );
+            info.push((format!("slave{i}"), line));
+        }
+        info
+    }
+
+    /// Drops every replica and the backlog, once the dataset was replaced by
+    /// a full resync with our own master, so sub-replicas sync from scratch.
+    pub fn reset(&mut self) {
+        let mut backlog = self.backlog.lock().unwrap();
+        *backlog = None;
+        for (_, replica) in self.peers.write().unwrap().drain() {
+            // an empty message tells the connection to close
+            let _ = replica.peer.lock().unwrap().tx.send(vec![]);
+        }
+    }
+
+    fn add(&self, peer: &Peer, acked: u64, legacy: bool) {
         let peer = peer.clone();
         // write lock
         self.peers
             .write()
             .unwrap()
-            .insert(peer.addr, Replica::new(peer));
+            .insert(peer.addr, Replica::new(peer, acked, legacy));
     }
 
     pub fn remove(&mut self, addr: &SocketAddr) {
@@ -72,21 +340,119 @@ impl Replicas {
     }
 }
 
+/// Tells replicas to delete the keys that expired here, ahead of any
+/// command that ran into them, which they would otherwise replay against
+/// the stale values.
+pub fn propagate_expired(db: &DB, replicas: &mut Replicas) {
+    for key in db.take_expired() {
+        replicas.broadcast(array(&vec!["del", &key]));
+    }
+}
+
+/// The command that recreates `value` at `key` on a replica or from the
+/// AOF, expiring `at` if given. Strings go as SET where they can, as other
+/// commands only take UTF-8; anything else as RESTORE.
+pub fn recreate(key: &str, value: &Value, at: Option<SystemTime>) -> Vec<u8> {
+    let millis = at
+        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |at| at.as_millis().max(1))
+        .to_string();
+    if let Value::String(bytes) = value {
+        if let Ok(value) = std::str::from_utf8(bytes) {
+            return match at {
+                None => array(&vec!["set", key, value]),
+                Some(_) => array(&vec!["set", key, value, "pxat", &millis]),
+            }
+            .into_bytes();
+        }
+    }
+    array_bytes(&[
+        b"restore".as_slice(),
+        key.as_bytes(),
+        millis.as_bytes(),
+        &rdb::dump(value),
+        b"replace",
+        b"absttl",
+    ])
+}
+
+/// Connections parked in a blocking command, by the keys they wait on.
+/// Each connection has one `Notify`, so a write to any of its keys wakes it.
+#[derive(Clone)]
+pub struct Waiters {
+    keys: Arc<Mutex<HashMap<String, Vec<Arc<Notify>>>>>,
+}
+
+impl Waiters {
+    pub fn new() -> Self {
+        Self {
+            keys: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn watch(&self, keys: &[String], notify: &Arc<Notify>) {
+        let mut waiters = self.keys.lock().unwrap();
+        for key in keys {
+            waiters.entry(key.clone()).or_default().push(notify.clone());
+        }
+    }
+
+    fn unwatch(&self, keys: &[String], notify: &Arc<Notify>) {
+        let mut waiters = self.keys.lock().unwrap();
+        for key in keys {
+            if let Some(list) = waiters.get_mut(key) {
+                list.retain(|waiter| !Arc::ptr_eq(waiter, notify));
+                if list.is_empty() {
+                    waiters.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Wakes those waiting on the key a write may have given them something
+    /// for, as it added to a list or stream.
+    pub fn wake_for(&self, command: &Command) {
+        match command {
+            Command::Push { key, .. }
+            | Command::Restore { key, .. }
+            | Command::XAdd { key, .. } => self.wake(key),
+            Command::Move { destination, .. }
+            | Command::Copy { destination, .. }
+            | Command::Sort {
+                store: Some(destination),
+                ..
+            } => self.wake(destination),
+            _ => {}
+        }
+    }
+
+    /// Wakes everyone waiting on `key`; they race to pop and the losers wait again.
+    fn wake(&self, key: &str) {
+        if let Some(list) = self.keys.lock().unwrap().get(key) {
+            for waiter in list {
+                // stores a permit if the waiter is between checks, so no wakeup is lost
+                waiter.notify_one();
+            }
+        }
+    }
+}
+
 enum PeerType {
     Client,
-    Replica {
-        offset: usize,
-        interval: time::Interval,
-    },
+    Replica,
 }
 
 struct MasterConnection {
     internal: PeerType,
-    rx: UnboundedReceiver<String>,
+    rx: UnboundedReceiver<Vec<u8>>,
     peer: Peer,
     db: DB,
     server: Arc<Server>,
     replicas: Replicas,
+    waiters: Waiters,
+    /// Whether the client may run commands: it has AUTHed, or connected
+    /// while there was no password to AUTH with
+    authenticated: bool,
 }
 
 impl MasterConnection {
@@ -97,52 +463,59 @@ impl MasterConnection {
     ) -> Option<Self> {
         match self.internal {
             PeerType::Client => {
-                let result = tokenize(reader).await;
+                // idle clients are let go, though not those blocked in a command
+                let timeout: u64 = self.server.config.get("timeout").parse().unwrap_or(0);
+                let result = if timeout > 0 {
+                    let idle = Duration::from_secs(timeout);
+                    match time::timeout(idle, tokenize(reader)).await {
+                        Ok(result) => result,
+                        Err(_) => {
+                            println!("[INFO] Closing idle client {}", self.peer.addr);
+                            return None;
+                        }
+                    }
+                } else {
+                    tokenize(reader).await
+                };
                 match result {
-                    Ok(None) | Err(_) => None,
+                    Ok(None) => None,
+                    // the stream can't be followed any further, so say why
+                    // and hang up, as Redis does
+                    Err(err) => {
+                        let reply = simple_error(&format!("ERR {err}"));
+                        let _ = writer.write_all(reply.as_ref()).await;
+                        None
+                    }
                     Ok(Some((arr, _count))) => {
                         let command = Command::parse(&arr);
-                        let next = self.handle_client_command(command, writer).await.unwrap();
+                        let next = self
+                            .handle_client_command(command, &arr, writer)
+                            .await
+                            .unwrap();
                         Some(next)
                     }
                 }
             }
-            PeerType::Replica {
-                mut offset,
-                mut interval,
-            } => {
+            PeerType::Replica => {
                 select! {
-                        // A message was received from a peer. Send it to the current user.
-                        Some(msg) = self.rx.recv() => {
-                            // get send messages
-                            let msg: &[u8] = msg.as_ref();
-                            offset += msg.len();
-                            if writer.write_all(msg).await.is_err(){
-                                return None;
-                            }
+                    // the replication stream, written as it comes
+                    msg = self.rx.recv() => {
+                        let msg = msg.filter(|msg| !msg.is_empty())?;
+                        writer.write_all(&msg).await.ok()?;
+                    }
+                    // replicas only speak to acknowledge their offset
+                    eof = async { reader.fill_buf().await.map(|buf| buf.is_empty()) } => {
+                        if eof.unwrap_or(true) {
+                            return None;
                         }
-                        _ = interval.tick() => {
-                            let msg = array(&vec!["replconf", "getack", "*"]);
-                            let msg: &[u8] = msg.as_ref();
-                            offset  += msg.len();
-                            if  writer.write_all(msg).await.is_err(){
-                                return None;
-                            }
-                            if let Ok(Some((arr, _))) = tokenize(reader).await{
-                                let command = Command::parse(&arr);
-                                match command {
-                                    Command::Replconf(Replconf::Ack(_)) => {
-                                        println!("got ack from replica sending to channel ");
-                                    },
-                                    _ => {
-                                        return None
-                                    }
-                                }
+                        let (arr, _) = tokenize(reader).await.ok()??;
+                        if let Command::Replconf(Replconf::Ack(offset)) = Command::parse(&arr) {
+                            if let Ok(offset) = offset.parse() {
+                                self.replicas.ack(&self.peer.addr, offset);
                             }
-
                         }
+                    }
                 }
-                self.internal = PeerType::Replica { offset, interval };
                 Some(self)
             }
         }
@@ -151,8 +524,128 @@ impl MasterConnection {
     async fn handle_client_command(
         mut self,
         command: Command,
+        args: &[Vec<u8>],
         stream: &mut WriteHalf<'_>,
     ) -> anyhow::Result<Self> {
+        if !self.authenticated
+            && !matches!(command, Command::Auth { .. })
+            && !self.server.config.get("requirepass").is_empty()
+        {
+            stream.write_all(simple_error(NOAUTH).as_ref()).await?;
+            return Ok(self);
+        }
+        // it would only diverge from the master
+        if command.is_write()
+            && self.server.is_replica()
+            && self.server.config.get("replica-read-only") == "yes"
+        {
+            stream.write_all(simple_error(READONLY).as_ref()).await?;
+            return Ok(self);
+        }
+        // replicas are catching up with the last writes
+        if command.is_write() && self.server.shutting_down() {
+            stream
+                .write_all(simple_error(SHUTTING_DOWN).as_ref())
+                .await?;
+            return Ok(self);
+        }
+        // a relative TTL is pinned to a time here, so the key and what the
+        // replicas and the AOF replay expire at the same moment
+        let command = match command {
+            Command::Set {
+                key,
+                value,
+                condition,
+                expiry: Some(Expiry::In(ttl)),
+                get,
+            } => Command::Set {
+                key,
+                value,
+                condition,
+                expiry: Some(Expiry::At(SystemTime::now() + ttl)),
+                get,
+            },
+            command => command,
+        };
+        let reply = match &command {
+            // handled below, since replicas need the id that was picked
+            Command::XAdd { .. } => None,
+            _ => exec::execute(&self.db, &command),
+        };
+        propagate_expired(&self.db, &mut self.replicas);
+        if let Some(reply) = reply {
+            // replicas replay the command as the client sent it, except that
+            // EXPIRE, SET with a TTL and RESTORE are given absolute times so
+            // they expire at the same time.
+            // It goes out before the reply, so a snapshot for a full resync
+            // or AOF rewrite taken meanwhile can't miss it.
+            if command.is_write() && !reply.starts_with(b"-") {
+                let msg = match &command {
+                    // the conditions refused it, so there is nothing to replay
+                    Command::Expire { .. } if reply == integer(0).as_bytes() => None,
+                    Command::Expire { key, at, .. } => {
+                        let millis = at
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |at| at.as_millis())
+                            .to_string();
+                        Some(array(&vec!["pexpireat", key, &millis]).into_bytes())
+                    }
+                    Command::Set {
+                        key,
+                        value,
+                        condition,
+                        expiry: Some(Expiry::At(at)),
+                        get,
+                    } => {
+                        let millis = at
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |at| at.as_millis())
+                            .to_string();
+                        let mut msg = vec!["set", key, value];
+                        match condition {
+                            Some(SetCondition::IfAbsent) => msg.push("nx"),
+                            Some(SetCondition::IfPresent) => msg.push("xx"),
+                            None => {}
+                        }
+                        if *get {
+                            msg.push("get");
+                        }
+                        msg.extend(["pxat", &millis]);
+                        Some(array(&msg).into_bytes())
+                    }
+                    Command::Restore {
+                        key,
+                        payload,
+                        at,
+                        replace,
+                    } => {
+                        let millis = at
+                            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                            .map_or(0, |at| at.as_millis().max(1))
+                            .to_string();
+                        let mut msg: Vec<&[u8]> = vec![
+                            b"restore",
+                            key.as_bytes(),
+                            millis.as_bytes(),
+                            payload,
+                            b"absttl",
+                        ];
+                        if *replace {
+                            msg.push(b"replace");
+                        }
+                        Some(array_bytes(&msg))
+                    }
+                    _ => Some(array_bytes(args)),
+                };
+                if let Some(msg) = msg {
+                    self.replicas.broadcast(&msg);
+                }
+            }
+            stream.write_all(&reply).await?;
+            self.waiters.wake_for(&command);
+            return Ok(self);
+        }
+
         match &command {
             Command::Ping => {
                 stream.write_all(PONG).await?;
@@ -160,58 +653,427 @@ impl MasterConnection {
             Command::Echo(value) => {
                 stream.write_all(bulk_string(Some(value)).as_ref()).await?;
             }
-            Command::Get { key } => {
-                let val = bulk_string(self.db.get(key).as_deref());
+            Command::BlockingPop {
+                keys,
+                timeout,
+                end,
+                count,
+            } => {
+                let attempt = |db: &DB, replicas: &mut Replicas| {
+                    let Some((key, values)) = db.pop_first(keys, count.unwrap_or(1), *end)? else {
+                        return Ok(None);
+                    };
+                    propagate_expired(db, replicas);
+                    // replicas replay the pop that actually happened
+                    let pop = if *end == End::Left { "lpop" } else { "rpop" };
+                    let n = values.len().to_string();
+                    replicas.broadcast(array(&vec![pop, &key, &n]));
+                    Ok(Some(match count {
+                        None => array(&vec![&key, &values[0]]),
+                        Some(_) => exec::popped_from(&key, &values),
+                    }))
+                };
+                let val = self.block_on(keys, *timeout, null_array(), attempt).await;
                 stream.write_all(val.as_ref()).await?;
             }
-            Command::Set { key, value, ex } => {
-                self.db
-                    .set(key.to_owned(), value.to_string(), ex.to_owned());
-                stream.write_all(OK).await?;
-
-                let msg = array(&vec!["set", key, value]);
-                self.replicas.broadcast(&msg);
+            Command::SPop { key, count } => {
+                let members = self.db.spop(key, count.unwrap_or(1));
+                let val = match (members, count) {
+                    (Err(err), _) => simple_error(&err.to_string()),
+                    (Ok(members), count) => {
+                        propagate_expired(&self.db, &mut self.replicas);
+                        // the members are picked at random, so replicas get the outcome
+                        if !members.is_empty() {
+                            let mut msg = vec!["srem", key.as_str()];
+                            msg.extend(members.iter().map(String::as_str));
+                            self.replicas.broadcast(array(&msg));
+                        }
+                        match count {
+                            None => bulk_string(members.first().map(String::as_str)),
+                            Some(_) => array(&members.iter().map(String::as_str).collect()),
+                        }
+                    }
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::XAdd {
+                key,
+                id,
+                fields,
+                create,
+            } => {
+                let val = match self.db.xadd(key, *id, fields, *create) {
+                    Err(err) => simple_error(&err.to_string()),
+                    Ok(None) => bulk_string(None),
+                    Ok(Some(id)) => {
+                        propagate_expired(&self.db, &mut self.replicas);
+                        let id = id.to_string();
+                        let mut msg = vec!["xadd", key.as_str(), id.as_str()];
+                        for (field, value) in fields {
+                            msg.extend([field.as_str(), value.as_str()]);
+                        }
+                        self.replicas.broadcast(array(&msg));
+                        self.waiters.wake(key);
+                        bulk_string(Some(&id))
+                    }
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::XRead {
+                keys,
+                ids,
+                count,
+                block: Some(timeout),
+            } => {
+                // `$` is the last id when the command starts, so only newer entries count
+                let ids: Result<Vec<_>, _> = ids
+                    .iter()
+                    .zip(keys)
+                    .map(|(id, key)| match id {
+                        Some(id) => Ok(*id),
+                        None => self.db.xlast_id(key),
+                    })
+                    .collect();
+                let val = match ids {
+                    Err(err) => simple_error(&err.to_string()),
+                    Ok(ids) => {
+                        let attempt = |db: &DB, _: &mut Replicas| {
+                            let streams = db.xread(keys, &ids, *count)?;
+                            Ok((!streams.is_empty()).then(|| exec::stream_reply(&streams)))
+                        };
+                        let timeout = (!timeout.is_zero()).then_some(*timeout);
+                        self.block_on(keys, timeout, null_array(), attempt).await
+                    }
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::BlockingMove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => {
+                let waiters = self.waiters.clone();
+                let attempt = |db: &DB, replicas: &mut Replicas| {
+                    let Some(element) = db.lmove(source, destination, *from, *to)? else {
+                        return Ok(None);
+                    };
+                    propagate_expired(db, replicas);
+                    let (from, to) = (from.as_str(), to.as_str());
+                    replicas.broadcast(array(&vec!["lmove", source, destination, from, to]));
+                    waiters.wake(destination);
+                    Ok(Some(bulk_string(Some(&element))))
+                };
+                let sources = [source.clone()];
+                let val = self
+                    .block_on(&sources, *timeout, bulk_string(None), attempt)
+                    .await;
+                stream.write_all(val.as_ref()).await?;
             }
             Command::Info => {
-                let val = pairs(self.server.info().into_iter());
+                let mut info: Vec<(String, String)> = self
+                    .server
+                    .info()
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect();
+                // the replicas go before the replication ids, as in Redis
+                let at = info
+                    .iter()
+                    .position(|(key, _)| key == "master_replid")
+                    .unwrap_or(info.len());
+                info.splice(at..at, self.replicas.info());
+                let aof_rewrite_in_progress =
+                    self.replicas.aof.as_ref().is_some_and(Aof::rewriting);
+                info.push((
+                    "aof_rewrite_in_progress".to_string(),
+                    (aof_rewrite_in_progress as u8).to_string(),
+                ));
+                info.push((
+                    "expired_keys".to_string(),
+                    self.db.expired_keys().to_string(),
+                ));
+                let val = pairs(
+                    info.iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                );
                 stream.write_all(val.as_ref()).await?;
             }
-            Command::Replconf(Replconf::ListeningPort(_) | Replconf::Capa(_)) => {
-                // todo: save info
+            Command::Replconf(Replconf::ListeningPort(port)) => {
+                let val = match port.parse() {
+                    Ok(port) => {
+                        self.peer.listening_port = Some(port);
+                        OK.to_vec()
+                    }
+                    Err(_) => simple_error(NOT_AN_INTEGER).into_bytes(),
+                };
+                stream.write_all(&val).await?;
+            }
+            Command::Replconf(Replconf::Capa(_)) => {
+                // only psync2 is ever offered, which is all we speak
                 stream.write_all(OK).await?;
             }
-            Command::Psync => {
-                let val = format!(
-                    "+FULLRESYNC {repl_id} {offset}\r\n",
-                    repl_id = self.server.replid(),
-                    offset = self.server.offset()
-                );
+            Command::Psync { replid, offset } => {
+                // the offset is that of the first byte the replica wants; a
+                // replica of the master we took over from may go on up to
+                // where its history ends
+                let replids = self.server.replids();
+                let known = *replid == replids.id
+                    || (*replid == replids.id2 && *offset <= replids.second_offset);
+                let resumed =
+                    known && *offset > 0 && self.replicas.resume(&self.peer, *offset as u64 - 1);
+                if resumed {
+                    println!(
+                        "Master: partial resync of {} from offset {offset}",
+                        self.peer.addr
+                    );
+                    let val = if *replid == replids.id {
+                        "+CONTINUE\r\n".to_string()
+                    } else {
+                        format!("+CONTINUE {}\r\n", replids.id)
+                    };
+                    stream.write_all(val.as_ref()).await?;
+                    self.internal = PeerType::Replica;
+                    return Ok(self);
+                }
+                let offset = self.replicas.attach(&self.peer, false);
+                let val = format!("+FULLRESYNC {} {offset}\r\n", replids.id);
                 stream.write_all(val.as_ref()).await?;
-
-                let empty = hex::decode(EMPTY).unwrap();
-                let val = format!("${}\r\n", empty.len());
+                return self.full_sync(stream).await;
+            }
+            Command::Sync => {
+                self.replicas.attach(&self.peer, true);
+                return self.full_sync(stream).await;
+            }
+            // its GETACK would put bytes in the stream our master never sent
+            Command::Wait(..) if self.server.is_replica() => {
+                let reply = simple_error("ERR WAIT cannot be used with replica instances");
+                stream.write_all(reply.as_ref()).await?;
+            }
+            Command::Wait(count, timeout) => {
+                let timeout = (*timeout > 0).then(|| Duration::from_millis(*timeout));
+                let acked = self.replicas.wait(*count, timeout).await;
+                stream.write_all(integer(acked as i64).as_ref()).await?;
+            }
+            Command::Export { filename, format } => {
+                let val = if filename.contains('/') {
+                    simple_error("ERR export file name must not contain a path")
+                } else {
+                    let path = Path::new(&self.server.config.get("dir")).join(filename);
+                    match dataset::export(&self.db, &path, format) {
+                        Ok(count) => integer(count as i64),
+                        Err(err) => simple_error(&format!("ERR export failed: {err}")),
+                    }
+                };
                 stream.write_all(val.as_ref()).await?;
-                stream.write_all(&empty).await?;
-                println!("Master: finish sending file");
-
-                self.replicas.add(&self.peer);
-                self.internal = PeerType::Replica {
-                    offset: 0,
-                    interval: time::interval(time::Duration::from_millis(500)),
+            }
+            Command::Import { source, conflict } => {
+                let replicas = &mut self.replicas;
+                let waiters = &self.waiters;
+                let propagate = |key: &str, value: &Value, at: Option<SystemTime>| {
+                    replicas.broadcast(recreate(key, value, at));
+                    waiters.wake(key);
                 };
-                return Ok(self);
+                let reply = |name: &str, report: anyhow::Result<dataset::ImportReport>| match report
+                {
+                    Ok(report) => {
+                        println!(
+                            "Import: finished {name}, {} keys loaded, {} skipped",
+                            report.imported, report.skipped
+                        );
+                        integer(report.imported as i64)
+                    }
+                    Err(err) => simple_error(&format!("ERR import failed: {err}")),
+                };
+                let val = match source {
+                    Source::File(filename) if filename.contains('/') => {
+                        simple_error("ERR import file name must not contain a path")
+                    }
+                    Source::File(filename) => {
+                        let path = Path::new(&self.server.config.get("dir")).join(filename);
+                        reply(
+                            filename,
+                            dataset::import(&self.db, &path, conflict, propagate),
+                        )
+                    }
+                    Source::Redis(remote) => {
+                        println!("Import: copying keys from {remote}");
+                        let report = dataset::copy(&self.db, remote, conflict, propagate).await;
+                        reply(&remote.to_string(), report)
+                    }
+                };
+                stream.write_all(val.as_ref()).await?;
             }
-            Command::Wait(_reps, _timeout) => {
-                let count = self.replicas.len();
+            Command::Object { field, key } => {
+                // IDLETIME and FREQ are only kept up for the policy in use,
+                // as Redis shares one field between them
+                let lfu = self.server.config.get("maxmemory-policy").ends_with("lfu");
+                let val = match (field, self.db.object(key)) {
+                    (_, None) => bulk_string(None),
+                    (ObjectField::IdleTime, _) if lfu => simple_error(IDLETIME_NOT_TRACKED),
+                    (ObjectField::Freq, _) if !lfu => simple_error(FREQ_NOT_TRACKED),
+                    (ObjectField::Encoding, Some(info)) => bulk_string(Some(info.encoding)),
+                    // values are never shared
+                    (ObjectField::RefCount, Some(_)) => integer(1),
+                    (ObjectField::IdleTime, Some(info)) => integer(info.idle.as_secs() as i64),
+                    (ObjectField::Freq, Some(info)) => integer(info.frequency as i64),
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::KeyStats { samples } => {
+                let report = analytics::keyspace_report(&self.db, *samples);
+                let val = pairs(report.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::Save if self.server.bgsave_in_progress.load(Ordering::Acquire) => {
                 stream
-                    .write_all(format!(":{}\r\n", count).as_bytes())
+                    .write_all(simple_error(BGSAVE_IN_PROGRESS).as_ref())
                     .await?;
             }
-            Command::Err => {
-                stream.write_all(ERR).await?;
+            Command::Save => match self.server.save(&self.db) {
+                Ok(()) => stream.write_all(OK).await?,
+                Err(err) => {
+                    eprintln!("[ERROR] Failed saving the DB: {err}");
+                    stream.write_all(simple_error("ERR").as_ref()).await?;
+                }
+            },
+            Command::BgSave => {
+                let val = if self.server.bgsave(&self.db) {
+                    "+Background saving started\r\n".to_string()
+                } else {
+                    simple_error(BGSAVE_IN_PROGRESS)
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::LastSave => {
+                let last_save = self.server.last_save.load(Ordering::Relaxed);
+                stream.write_all(integer(last_save as i64).as_ref()).await?;
+            }
+            Command::BgRewriteAof => {
+                let preamble = self.server.config.get("aof-use-rdb-preamble") == "yes";
+                let val = match &self.replicas.aof {
+                    None => simple_error("ERR Append only file is not enabled"),
+                    Some(aof) if aof.rewrite(&self.db, preamble) => {
+                        "+Background append only file rewriting started\r\n".to_string()
+                    }
+                    Some(_) => simple_error(AOF_REWRITE_IN_PROGRESS),
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::ConfigGet { pattern } => {
+                let matching = self.server.config.matching(pattern);
+                let flat = matching
+                    .iter()
+                    .flat_map(|(name, value)| [*name, value.as_str()])
+                    .collect();
+                stream.write_all(array(&flat).as_ref()).await?;
+            }
+            Command::ConfigSet { name, value } => match self.server.config.set(name, value) {
+                Ok(()) => stream.write_all(OK).await?,
+                Err(message) => stream.write_all(simple_error(&message).as_ref()).await?,
+            },
+            Command::Auth { username, password } => {
+                let requirepass = self.server.config.get("requirepass");
+                let val = if requirepass.is_empty() {
+                    simple_error(AUTH_WITHOUT_PASSWORD)
+                } else if username.as_deref().unwrap_or("default") == "default"
+                    && *password == requirepass
+                {
+                    self.authenticated = true;
+                    String::from_utf8_lossy(OK).into_owned()
+                } else {
+                    simple_error(WRONGPASS)
+                };
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::Shutdown { save, now, force } => {
+                let replicas = &self.replicas;
+                if self
+                    .server
+                    .shutdown(&self.db, replicas, *save, *now, *force)
+                    .await
+                {
+                    std::process::exit(0);
+                }
+                let val = simple_error("ERR Errors trying to SHUTDOWN. Check logs.");
+                stream.write_all(val.as_ref()).await?;
+            }
+            Command::ReplicaOf(None) => {
+                self.server.promote(&self.db);
+                stream.write_all(OK).await?;
+            }
+            Command::ReplicaOf(Some((host, port))) => {
+                let val: &[u8] =
+                    if self
+                        .server
+                        .follow(host, port, &self.db, &self.replicas, &self.waiters, None)
+                    {
+                        OK
+                    } else {
+                        b"+OK Already connected to specified master\r\n"
+                    };
+                stream.write_all(val).await?;
+            }
+            Command::Err(message) => {
+                stream.write_all(simple_error(message).as_ref()).await?;
             }
             _ => {}
         };
+        // whatever reads above ran into
+        propagate_expired(&self.db, &mut self.replicas);
+        Ok(self)
+    }
+
+    /// Retries `attempt` until it has a reply, parking the connection in between
+    /// until another client writes to one of `keys` or the timeout runs out.
+    async fn block_on(
+        &mut self,
+        keys: &[String],
+        timeout: Option<Duration>,
+        on_timeout: String,
+        mut attempt: impl FnMut(&DB, &mut Replicas) -> Result<Option<String>, DbError>,
+    ) -> String {
+        // one too far off to represent never comes
+        let deadline = timeout.and_then(|timeout| time::Instant::now().checked_add(timeout));
+        let notify = Arc::new(Notify::new());
+        // registered before the first attempt, so a push in between still wakes us
+        self.waiters.watch(keys, &notify);
+
+        let val = loop {
+            match attempt(&self.db, &mut self.replicas) {
+                Ok(Some(val)) => break val,
+                Ok(None) => {}
+                Err(err) => break simple_error(&err.to_string()),
+            }
+            let woken = match deadline {
+                None => {
+                    notify.notified().await;
+                    true
+                }
+                Some(deadline) => time::timeout_at(deadline, notify.notified()).await.is_ok(),
+            };
+            if !woken {
+                break on_timeout;
+            }
+        };
+        self.waiters.unwatch(keys, &notify);
+        val
+    }
+
+    /// Sends an RDB snapshot of the dataset to a replica just attached and
+    /// turns this connection into its replication stream.
+    async fn full_sync(mut self, stream: &mut WriteHalf<'_>) -> anyhow::Result<Self> {
+        // writes since attaching queue up for the replica while the snapshot
+        // is sent, so none fall between the two; one still on its way may be
+        // in both
+        let snapshot = rdb::write(&self.db.snapshot(), None);
+        let val = format!("${}\r\n", snapshot.len());
+        stream.write_all(val.as_ref()).await?;
+        stream.write_all(&snapshot).await?;
+        println!("Master: finish sending file");
+
+        self.internal = PeerType::Replica;
         Ok(self)
     }
 }
@@ -222,14 +1084,28 @@ pub async fn client_handler(
     db: DB,
     server: Arc<Server>,
     mut replicas: Replicas,
+    waiters: Waiters,
 ) {
-    let (tx, rx) = mpsc::unbounded_channel::<String>();
+    let (tx, rx) = mpsc::unbounded_channel::<Vec<u8>>();
     let peer = Peer {
         addr: peer_addr,
         tx,
+        listening_port: None,
     };
 
     let (mut reader, mut writer) = stream.split();
+    let maxclients: usize = server
+        .config
+        .get("maxclients")
+        .parse()
+        .unwrap_or(usize::MAX);
+    if server.clients.fetch_add(1, Ordering::Relaxed) >= maxclients {
+        server.clients.fetch_sub(1, Ordering::Relaxed);
+        println!("[WARN] Refused client {peer_addr}: max number of clients reached");
+        let _ = writer.write_all(simple_error(MAX_CLIENTS).as_ref()).await;
+        return;
+    }
+    let authenticated = server.config.get("requirepass").is_empty();
     let mut reader = BufReader::new(&mut reader);
     let mut master = Some(MasterConnection {
         internal: PeerType::Client,
@@ -237,13 +1113,25 @@ pub async fn client_handler(
         replicas: replicas.clone(),
         rx,
         db,
-        server,
+        server: server.clone(),
+        waiters,
+        authenticated,
     });
 
+    let mut budget = COMMAND_BUDGET;
     while let Some(x) = master {
         master = x.handle(&mut reader, &mut writer).await;
+
+        // commands already sitting in the read buffer never hit the socket,
+        // so a pipelining client would otherwise run without ever yielding
+        budget -= 1;
+        if budget == 0 {
+            task::yield_now().await;
+            budget = COMMAND_BUDGET;
+        }
     }
 
     println!("client disconnected {}", peer.addr);
     replicas.remove(&peer.addr);
+    server.clients.fetch_sub(1, Ordering::Relaxed);
 }
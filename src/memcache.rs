@@ -0,0 +1,205 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::db::{Expiry, Value, DB};
+use crate::master::{propagate_expired, recreate, Replicas};
+use crate::parse::{array, MAX_BULK_LEN};
+use crate::Server;
+
+// exptime values above this are absolute UNIX timestamps
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+const READONLY: &str = "SERVER_ERROR You can't write against a read only replica.\r\n";
+const SHUTTING_DOWN: &str = "SERVER_ERROR The server is shutting down.\r\n";
+
+/// Serves the memcached text protocol on top of the same DB.
+///
+/// Supports get/gets, set, delete, incr/decr, version and quit. Item flags
+/// are accepted but not stored (always reported as 0). Writes go to replicas
+/// and the AOF as the Redis commands they amount to.
+pub async fn listen(addr: String, db: DB, server: Arc<Server>, replicas: Replicas) {
+    let listener = TcpListener::bind(&addr).await.unwrap();
+    println!("Memcached listening on {addr}");
+
+    while let Ok((stream, peer)) = listener.accept().await {
+        println!("Memcached client connected: {}", peer);
+        let db = db.clone();
+        let server = server.clone();
+        let replicas = replicas.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, db, server, replicas).await {
+                eprintln!("[ERROR] Memcached: client {peer} failed with: {err}");
+            }
+        });
+    }
+}
+
+async fn handle(
+    mut stream: TcpStream,
+    db: DB,
+    server: Arc<Server>,
+    mut replicas: Replicas,
+) -> Result<()> {
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let noreply = words.last() == Some(&"noreply");
+        // it would only diverge from the master
+        let replica = server.is_replica() && server.config.get("replica-read-only") == "yes";
+        // replicas are catching up with the last writes
+        let shutting_down = server.shutting_down();
+        let read_only = replica || shutting_down;
+        let refusal = if shutting_down {
+            SHUTTING_DOWN
+        } else {
+            READONLY
+        };
+
+        let response = match words.as_slice() {
+            ["get" | "gets", keys @ ..] if !keys.is_empty() => {
+                let mut response = vec![];
+                for key in keys {
+                    // keys holding other Redis types are invisible to memcached
+                    if let Ok(Some(value)) = db.get(key) {
+                        response.extend(format!("VALUE {key} 0 {}\r\n", value.len()).as_bytes());
+                        response.extend(value);
+                        response.extend(b"\r\n");
+                    }
+                }
+                response.extend(b"END\r\n");
+                response
+            }
+            ["set", key, _flags, exptime, bytes, ..] => {
+                let (Ok(exptime), Ok(bytes)) = (exptime.parse::<i64>(), bytes.parse::<usize>())
+                else {
+                    reply(&mut writer, "CLIENT_ERROR bad command line format\r\n").await?;
+                    continue;
+                };
+                if bytes > MAX_BULK_LEN {
+                    reply(&mut writer, "SERVER_ERROR object too large for cache\r\n").await?;
+                    // the data is skipped as it comes, to get back to commands
+                    let data = (bytes as u64).saturating_add(2);
+                    tokio::io::copy(&mut (&mut reader).take(data), &mut tokio::io::sink()).await?;
+                    continue;
+                }
+                let mut data = vec![0; bytes + 2];
+                reader.read_exact(&mut data).await?;
+                if !data.ends_with(b"\r\n") {
+                    reply(&mut writer, "CLIENT_ERROR bad data chunk\r\n").await?;
+                    continue;
+                }
+                data.truncate(bytes);
+                if read_only {
+                    reply(&mut writer, refusal).await?;
+                    continue;
+                }
+
+                match expiration(exptime) {
+                    Some(ttl) if ttl.is_zero() => {
+                        if db.remove(key) {
+                            propagate_expired(&db, &mut replicas);
+                            replicas.broadcast(array(&vec!["del", key]));
+                        }
+                    }
+                    ttl => {
+                        // pinned to a time here, so replicas and the AOF
+                        // expire it when we do
+                        let expires_at = ttl.map(|ttl| SystemTime::now() + ttl);
+                        let msg = recreate(key, &Value::String(data.clone()), expires_at);
+                        db.set_with(key, data, None, expires_at.map(Expiry::At), false)?;
+                        propagate_expired(&db, &mut replicas);
+                        replicas.broadcast(msg);
+                    }
+                }
+                "STORED\r\n".into()
+            }
+            ["delete", ..] if read_only => refusal.into(),
+            ["delete", key, ..] => {
+                if db.remove(key) {
+                    propagate_expired(&db, &mut replicas);
+                    replicas.broadcast(array(&vec!["del", key]));
+                    "DELETED\r\n".into()
+                } else {
+                    "NOT_FOUND\r\n".into()
+                }
+            }
+            ["incr" | "decr", ..] if read_only => refusal.into(),
+            [op @ ("incr" | "decr"), key, delta, ..] => {
+                let Ok(delta) = delta.parse::<u64>() else {
+                    reply(
+                        &mut writer,
+                        "CLIENT_ERROR invalid numeric delta argument\r\n",
+                    )
+                    .await?;
+                    continue;
+                };
+                let result = db.update(key, |value| {
+                    let current = std::str::from_utf8(value)
+                        .ok()?
+                        .trim()
+                        .parse::<u64>()
+                        .ok()?;
+                    // incr wraps around at 64 bits, decr stops at zero
+                    let next = if *op == "incr" {
+                        current.wrapping_add(delta)
+                    } else {
+                        current.saturating_sub(delta)
+                    };
+                    *value = next.to_string().into_bytes();
+                    Some(next)
+                });
+                propagate_expired(&db, &mut replicas);
+                match result {
+                    Ok(None) => "NOT_FOUND\r\n".into(),
+                    Ok(Some(None)) | Err(_) => {
+                        "CLIENT_ERROR cannot increment or decrement non-numeric value\r\n".into()
+                    }
+                    Ok(Some(Some(value))) => {
+                        let value = value.to_string();
+                        replicas.broadcast(array(&vec!["set", key, &value, "keepttl"]));
+                        format!("{value}\r\n").into_bytes()
+                    }
+                }
+            }
+            ["version"] => format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION")).into_bytes(),
+            ["quit"] => return Ok(()),
+            _ => "ERROR\r\n".into(),
+        };
+
+        if !noreply {
+            reply(&mut writer, &response).await?;
+        }
+    }
+}
+
+async fn reply(
+    writer: &mut (impl AsyncWriteExt + Unpin),
+    response: impl AsRef<[u8]>,
+) -> Result<()> {
+    writer.write_all(response.as_ref()).await?;
+    Ok(())
+}
+
+/// Converts a memcached exptime into a TTL: `None` for no expiration,
+/// a zero duration for items that are already expired.
+fn expiration(exptime: i64) -> Option<Duration> {
+    match exptime {
+        0 => None,
+        e if e < 0 => Some(Duration::ZERO),
+        e if e <= MAX_RELATIVE_EXPTIME => Some(Duration::from_secs(e as u64)),
+        e => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+            Some(Duration::from_secs(e as u64).saturating_sub(now))
+        }
+    }
+}
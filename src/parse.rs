@@ -1,5 +1,5 @@
 use anyhow::anyhow;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::net::tcp::ReadHalf;
 
 pub fn pairs<'a>(pairs: impl ExactSizeIterator<Item = (&'a str, &'a str)>) -> String {
@@ -20,6 +20,31 @@ pub fn bulk_string(string: Option<&str>) -> String {
     }
 }
 
+/// A bulk string that may not be valid UTF-8
+pub fn bulk_bytes(bytes: Option<&[u8]>) -> Vec<u8> {
+    match bytes {
+        None => b"$-1\r\n".to_vec(),
+        Some(bytes) => {
+            let mut result = format!("${}\r\n", bytes.len()).into_bytes();
+            result.extend_from_slice(bytes);
+            result.extend_from_slice(b"\r\n");
+            result
+        }
+    }
+}
+
+pub fn integer(value: i64) -> String {
+    format!(":{value}\r\n")
+}
+
+pub fn simple_string(value: &str) -> String {
+    format!("+{value}\r\n")
+}
+
+pub fn simple_error(message: &str) -> String {
+    format!("-{message}\r\n")
+}
+
 pub fn array(arr: &Vec<&str>) -> String {
     let mut result = format!("*{len}\r\n", len = arr.len());
     for val in arr {
@@ -28,54 +53,101 @@ pub fn array(arr: &Vec<&str>) -> String {
     result
 }
 
+/// An array of bulk strings that may not be valid UTF-8
+pub fn array_bytes<T: AsRef<[u8]>>(items: &[T]) -> Vec<u8> {
+    let mut result = format!("*{len}\r\n", len = items.len()).into_bytes();
+    for item in items {
+        result.extend(bulk_bytes(Some(item.as_ref())));
+    }
+    result
+}
+
+/// An array of items that are already RESP encoded, such as integers or nested arrays
+pub fn encoded_array(items: &[String]) -> String {
+    let mut result = format!("*{len}\r\n", len = items.len());
+    for item in items {
+        result += item;
+    }
+    result
+}
+
+/// The RESP2 null array, for replies that have no array to give
+pub fn null_array() -> String {
+    "*-1\r\n".to_string()
+}
+
+/// Most arguments a command may have, as Redis allows unauthenticated clients
+const MAX_MULTIBULK_LEN: usize = 1024 * 1024;
+/// Largest argument, Redis's default proto-max-bulk-len
+pub const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+/// Buffers start no bigger than this whatever length is declared, and grow
+/// as the data actually arrives
+pub const PREALLOC_LIMIT: usize = 64 * 1024;
+
 pub async fn tokenize(
     input: &mut BufReader<&mut ReadHalf<'_>>,
-) -> anyhow::Result<Option<(Vec<String>, usize)>> {
+) -> anyhow::Result<Option<(Vec<Vec<u8>>, usize)>> {
     let mut count = 0;
-    let mut response = String::new();
-    let n = input.read_line(&mut response).await?;
+    // one line buffer reused for the array header and every length prefix
+    let mut line = String::new();
+    let n = input.read_line(&mut line).await?;
     if n == 0 {
         return Ok(None);
     }
     count += n;
 
-    if !response.starts_with('*') {
-        return Err(anyhow!(
-            "Expected an array (starts with *) but got {response} "
-        ));
+    if !line.starts_with('*') {
+        return Err(anyhow!("Expected an array (starts with *) but got {line} "));
+    }
+    let length = parse_length(&line)?;
+    if length > MAX_MULTIBULK_LEN {
+        return Err(anyhow!("Protocol error: invalid multibulk length"));
     }
 
-    let length = match response[1..]
-        .strip_suffix("\r\n")
-        .expect("split by lines")
-        .parse::<usize>()
-    {
-        Ok(size) => size,
-        Err(err) => return Err(anyhow!("Failed to parse size {err}")),
-    };
-
-    let mut array = vec![];
+    let mut array = Vec::with_capacity(length.min(PREALLOC_LIMIT));
     for _ in 0..length {
         // read value size
-        let mut response = String::new();
-        let n = input.read_line(&mut response).await?;
+        line.clear();
+        let n = input.read_line(&mut line).await?;
         if n == 0 {
             return Err(anyhow!("EOF"));
         }
         count += n;
+        if !line.starts_with('$') {
+            return Err(anyhow!(
+                "Expected a bulk string (starts with $) but got {line}"
+            ));
+        }
+        let size = parse_length(&line)?;
+        if size > MAX_BULK_LEN {
+            return Err(anyhow!("Protocol error: invalid bulk length"));
+        }
 
-        let mut response = String::new();
-        let n = input.read_line(&mut response).await?;
-        if n == 0 {
+        // read the payload by its declared size so values may contain CRLF,
+        // straight into the buffer that becomes the argument
+        let mut value = Vec::with_capacity((size + 2).min(PREALLOC_LIMIT));
+        (&mut *input)
+            .take(size as u64 + 2)
+            .read_to_end(&mut value)
+            .await?;
+        if value.len() != size + 2 {
             return Err(anyhow!("EOF"));
         }
-        count += n;
-        array.push(
-            response
-                .strip_suffix("\r\n")
-                .expect("split by lines")
-                .to_string(),
-        );
+        count += value.len();
+        if !value.ends_with(b"\r\n") {
+            return Err(anyhow!("Expected CRLF after bulk string"));
+        }
+        value.truncate(size);
+        // kept as bytes, since RESTORE payloads aren't text
+        array.push(value);
     }
     Ok(Some((array, count)))
 }
+
+fn parse_length(line: &str) -> anyhow::Result<usize> {
+    line[1..]
+        .strip_suffix("\r\n")
+        .ok_or_else(|| anyhow!("Expected CRLF terminated line"))?
+        .parse::<usize>()
+        .map_err(|err| anyhow!("Failed to parse size {err}"))
+}
@@ -0,0 +1,772 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Result};
+
+use crate::db::{SortedSet, Stream, StreamId, Value};
+
+mod listpack;
+mod lzf;
+
+use listpack::Item;
+
+const MAGIC: &[u8] = b"REDIS";
+/// The version written, that of Redis 7.2
+pub const VERSION: u16 = 11;
+
+/// A Redis 7 function library
+const OP_FUNCTION: u8 = 0xF5;
+const OP_MODULE_AUX: u8 = 0xF7;
+/// LRU idle time of the next key, in seconds
+const OP_IDLE: u8 = 0xF8;
+/// LFU access frequency of the next key
+const OP_FREQ: u8 = 0xF9;
+const OP_AUX: u8 = 0xFA;
+const OP_RESIZE_DB: u8 = 0xFB;
+const OP_EXPIRE_MS: u8 = 0xFC;
+const OP_EXPIRE_SECS: u8 = 0xFD;
+const OP_SELECT_DB: u8 = 0xFE;
+const OP_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+/// Scores as text
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+/// Scores as binary doubles
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_STREAM_LISTPACKS: u8 = 15;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const TYPE_SET_LISTPACK: u8 = 20;
+const TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+/// Quicklist nodes holding a single large element rather than a listpack
+const QUICKLIST_PLAIN: usize = 1;
+
+/// Stream entry flags
+const STREAM_DELETED: i64 = 1;
+const STREAM_SAME_FIELDS: i64 = 2;
+
+#[derive(Debug)]
+pub struct RdbEntry {
+    pub key: String,
+    pub value: Value,
+    /// Absolute UNIX time in milliseconds
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct Database {
+    pub index: usize,
+    pub entries: Vec<RdbEntry>,
+}
+
+#[derive(Debug)]
+pub enum Checksum {
+    Valid(u64),
+    Disabled,
+    /// Versions before 5 have no checksum trailer
+    Missing,
+}
+
+/// Where a dataset stands in the replication stream, saved with it so a
+/// replica can resume from there after a restart.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplInfo {
+    pub replid: String,
+    pub offset: u64,
+    /// "host port" of the master, when saved on a replica
+    pub master: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Rdb {
+    pub version: u32,
+    pub aux: Vec<(String, String)>,
+    pub databases: Vec<Database>,
+    pub checksum: Checksum,
+    /// Bytes up to the end of the checksum, where the AOF tail of an AOF
+    /// with an RDB preamble starts
+    pub len: usize,
+}
+
+impl Rdb {
+    /// The replication state saved in the aux fields, if there is one.
+    pub fn repl_info(&self) -> Option<ReplInfo> {
+        let field = |name: &str| {
+            self.aux
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        Some(ReplInfo {
+            replid: field("repl-id").filter(|id| id.len() == 40)?,
+            offset: field("repl-offset")?.parse().ok()?,
+            master: field("repl-master"),
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+enum Length {
+    Len(usize),
+    Int(i64),
+    Lzf,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.bytes.len() - self.pos {
+            return Err(anyhow!(
+                "unexpected end of file at offset {} (wanted {n} more bytes)",
+                self.pos
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32_le(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64_le(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn length_encoding(&mut self) -> Result<Length> {
+        let first = self.byte()?;
+        let len = match first >> 6 {
+            0b00 => Length::Len((first & 0x3F) as usize),
+            0b01 => Length::Len((((first & 0x3F) as usize) << 8) | self.byte()? as usize),
+            0b10 => match first {
+                0x80 => Length::Len(u32::from_be_bytes(self.take(4)?.try_into()?) as usize),
+                0x81 => Length::Len(u64::from_be_bytes(self.take(8)?.try_into()?) as usize),
+                _ => return Err(anyhow!("invalid length encoding {first:#04x}")),
+            },
+            _ => match first & 0x3F {
+                0 => Length::Int(self.byte()? as i8 as i64),
+                1 => Length::Int(i16::from_le_bytes(self.take(2)?.try_into()?) as i64),
+                2 => Length::Int(i32::from_le_bytes(self.take(4)?.try_into()?) as i64),
+                3 => Length::Lzf,
+                other => return Err(anyhow!("invalid special string encoding {other}")),
+            },
+        };
+        Ok(len)
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        match self.length_encoding()? {
+            Length::Len(len) => Ok(len),
+            _ => Err(anyhow!("expected a length at offset {}", self.pos)),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>> {
+        match self.length_encoding()? {
+            Length::Len(len) => Ok(self.take(len)?.to_vec()),
+            Length::Int(value) => Ok(value.to_string().into_bytes()),
+            Length::Lzf => {
+                let compressed_len = self.length()?;
+                let len = self.length()?;
+                lzf::decompress(self.take(compressed_len)?, len)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.bytes()?).into_owned())
+    }
+
+    /// A score stored as text, with a length byte that may instead flag
+    /// NaN or an infinity.
+    fn text_score(&mut self) -> Result<f64> {
+        match self.byte()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => Ok(std::str::from_utf8(self.take(len as usize)?)?.parse()?),
+        }
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>> {
+        (0..self.length()?).map(|_| self.string()).collect()
+    }
+
+    fn value(&mut self, value_type: u8) -> Result<Value> {
+        let value = match value_type {
+            TYPE_STRING => Value::String(self.bytes()?),
+            TYPE_LIST => Value::List(self.strings()?.into()),
+            TYPE_SET => Value::Set(self.strings()?.into_iter().collect()),
+            TYPE_ZSET | TYPE_ZSET_2 => {
+                let mut zset = SortedSet::default();
+                for _ in 0..self.length()? {
+                    let member = self.string()?;
+                    let score = match value_type {
+                        TYPE_ZSET => self.text_score()?,
+                        _ => f64::from_le_bytes(self.take(8)?.try_into()?),
+                    };
+                    zset.insert(&member, score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_HASH => {
+                let mut hash = HashMap::new();
+                for _ in 0..self.length()? {
+                    hash.insert(self.string()?, self.string()?);
+                }
+                Value::Hash(hash)
+            }
+            TYPE_SET_INTSET => {
+                let members = intset(&self.bytes()?)?;
+                Value::Set(members.iter().map(i64::to_string).collect())
+            }
+            TYPE_SET_LISTPACK => {
+                let node = self.bytes()?;
+                let members = listpack::decode(&node)?;
+                Value::Set(members.iter().map(Item::text).collect())
+            }
+            TYPE_HASH_LISTPACK => {
+                let node = self.bytes()?;
+                let items = listpack::decode(&node)?;
+                let pairs = items.chunks_exact(2);
+                Value::Hash(pairs.map(|pair| (pair[0].text(), pair[1].text())).collect())
+            }
+            TYPE_ZSET_LISTPACK => {
+                let node = self.bytes()?;
+                let items = listpack::decode(&node)?;
+                let mut zset = SortedSet::default();
+                for pair in items.chunks_exact(2) {
+                    let score = match pair[1] {
+                        Item::Int(score) => score as f64,
+                        Item::Str(score) => std::str::from_utf8(score)?.parse()?,
+                    };
+                    zset.insert(&pair[0].text(), score);
+                }
+                Value::ZSet(zset)
+            }
+            TYPE_LIST_QUICKLIST_2 => {
+                let mut list = VecDeque::new();
+                for _ in 0..self.length()? {
+                    let container = self.length()?;
+                    let node = self.bytes()?;
+                    if container == QUICKLIST_PLAIN {
+                        list.push_back(String::from_utf8_lossy(&node).into_owned());
+                    } else {
+                        list.extend(listpack::decode(&node)?.iter().map(Item::text));
+                    }
+                }
+                Value::List(list)
+            }
+            TYPE_STREAM_LISTPACKS | TYPE_STREAM_LISTPACKS_2 | TYPE_STREAM_LISTPACKS_3 => {
+                Value::Stream(self.stream(value_type)?)
+            }
+            other => {
+                return Err(anyhow!(
+                    "unsupported value type {other} at offset {}",
+                    self.pos - 1
+                ))
+            }
+        };
+        Ok(value)
+    }
+
+    fn stream_id(&mut self) -> Result<StreamId> {
+        Ok(StreamId {
+            ms: self.length()? as u64,
+            seq: self.length()? as u64,
+        })
+    }
+
+    /// Stream nodes are listpacks keyed by a master id, whose first entry
+    /// holds fields later entries may share.
+    fn stream(&mut self, value_type: u8) -> Result<Stream> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..self.length()? {
+            let key = self.bytes()?;
+            let key: [u8; 16] = key
+                .try_into()
+                .map_err(|_| anyhow!("stream node key is not an id"))?;
+            let master = StreamId {
+                ms: u64::from_be_bytes(key[..8].try_into()?),
+                seq: u64::from_be_bytes(key[8..].try_into()?),
+            };
+            let node = self.bytes()?;
+            let items = listpack::decode(&node)?;
+            stream_node(master, &items, &mut entries)?;
+        }
+
+        let length = self.length()?;
+        let last_id = self.stream_id()?;
+        let (entries_added, max_deleted_id) = if value_type == TYPE_STREAM_LISTPACKS {
+            (length as u64, StreamId::default())
+        } else {
+            let _first_id = self.stream_id()?;
+            let max_deleted_id = self.stream_id()?;
+            (self.length()? as u64, max_deleted_id)
+        };
+        if self.length()? > 0 {
+            return Err(anyhow!("stream consumer groups are not supported"));
+        }
+        Ok(Stream::from_parts(
+            entries,
+            last_id,
+            entries_added,
+            max_deleted_id,
+        ))
+    }
+}
+
+/// Adds the live entries of a stream node.
+fn stream_node(
+    master: StreamId,
+    items: &[Item],
+    entries: &mut BTreeMap<StreamId, Vec<(String, String)>>,
+) -> Result<()> {
+    let mut items = items.iter();
+    let mut next = || items.next().ok_or_else(|| anyhow!("truncated stream node"));
+    // live count and deleted count, which the entries tell anyway
+    next()?;
+    next()?;
+    let master_fields = (0..next()?.int()?)
+        .map(|_| Ok(next()?.text()))
+        .collect::<Result<Vec<_>>>()?;
+    next()?;
+
+    loop {
+        let flags = match next() {
+            Ok(flags) => flags.int()?,
+            Err(_) => break,
+        };
+        let id = StreamId {
+            ms: master.ms.wrapping_add(next()?.int()? as u64),
+            seq: master.seq.wrapping_add(next()?.int()? as u64),
+        };
+        let fields = if flags & STREAM_SAME_FIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), next()?.text())))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            (0..next()?.int()?)
+                .map(|_| Ok((next()?.text(), next()?.text())))
+                .collect::<Result<Vec<_>>>()?
+        };
+        // the element count, for walking backwards
+        next()?;
+        if flags & STREAM_DELETED == 0 {
+            entries.insert(id, fields);
+        }
+    }
+    Ok(())
+}
+
+/// Sorted integers of 2, 4 or 8 bytes, after the width and the count.
+fn intset(bytes: &[u8]) -> Result<Vec<i64>> {
+    let header = |range: std::ops::Range<usize>| -> Result<usize> {
+        let bytes = bytes
+            .get(range)
+            .ok_or_else(|| anyhow!("truncated intset"))?;
+        Ok(u32::from_le_bytes(bytes.try_into()?) as usize)
+    };
+    let (width, count) = (header(0..4)?, header(4..8)?);
+    let body = &bytes[8..];
+    if !matches!(width, 2 | 4 | 8) || body.len() != width * count {
+        return Err(anyhow!("invalid intset"));
+    }
+    Ok(body
+        .chunks_exact(width)
+        .map(|int| match width {
+            2 => i16::from_le_bytes([int[0], int[1]]) as i64,
+            4 => i32::from_le_bytes(int.try_into().unwrap()) as i64,
+            _ => i64::from_le_bytes(int.try_into().unwrap()),
+        })
+        .collect())
+}
+
+fn write_length(out: &mut Vec<u8>, len: usize) {
+    match len {
+        0..=0x3F => out.push(len as u8),
+        0x40..=0x3FFF => out.extend([0x40 | (len >> 8) as u8, len as u8]),
+        _ => match u32::try_from(len) {
+            Ok(len) => {
+                out.push(0x80);
+                out.extend(len.to_be_bytes());
+            }
+            Err(_) => {
+                out.push(0x81);
+                out.extend((len as u64).to_be_bytes());
+            }
+        },
+    }
+}
+
+/// Strings longer than this are LZF compressed when that makes them
+/// shorter, as in Redis
+const COMPRESS_OVER: usize = 20;
+const ENCODING_LZF: u8 = 0xC3;
+
+fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    if bytes.len() > COMPRESS_OVER {
+        if let Some(compressed) = lzf::compress(bytes) {
+            out.push(ENCODING_LZF);
+            write_length(out, compressed.len());
+            write_length(out, bytes.len());
+            out.extend(compressed);
+            return;
+        }
+    }
+    write_length(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// The type byte that goes before a value.
+fn value_type(value: &Value) -> u8 {
+    match value {
+        Value::String(_) => TYPE_STRING,
+        Value::List(_) => TYPE_LIST,
+        Value::Set(_) => TYPE_SET,
+        Value::Hash(_) => TYPE_HASH,
+        Value::ZSet(_) => TYPE_ZSET_2,
+        Value::Stream(_) => TYPE_STREAM_LISTPACKS_3,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::String(value) => write_string(out, value),
+        Value::List(list) => {
+            write_length(out, list.len());
+            for item in list {
+                write_string(out, item.as_bytes());
+            }
+        }
+        Value::Set(set) => {
+            write_length(out, set.len());
+            for member in set {
+                write_string(out, member.as_bytes());
+            }
+        }
+        Value::Hash(hash) => {
+            write_length(out, hash.len());
+            for (field, value) in hash {
+                write_string(out, field.as_bytes());
+                write_string(out, value.as_bytes());
+            }
+        }
+        Value::ZSet(zset) => {
+            write_length(out, zset.len());
+            for (member, score) in zset.iter() {
+                write_string(out, member.as_bytes());
+                out.extend(score.to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => write_stream(out, stream),
+    }
+}
+
+/// A stream as a single node keyed by its first id. Entries with the same
+/// fields as the first only store their values.
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let write_id = |out: &mut Vec<u8>, id: StreamId| {
+        write_length(out, id.ms as usize);
+        write_length(out, id.seq as usize);
+    };
+    let first = stream.iter().next();
+    match first {
+        None => write_length(out, 0),
+        Some((master, master_fields)) => {
+            write_length(out, 1);
+            let mut key = master.ms.to_be_bytes().to_vec();
+            key.extend(master.seq.to_be_bytes());
+            write_string(out, &key);
+
+            let count = master_fields.len() as i64;
+            let mut items = vec![
+                Item::Int(stream.len() as i64),
+                Item::Int(0),
+                Item::Int(count),
+            ];
+            items.extend(
+                master_fields
+                    .iter()
+                    .map(|(field, _)| Item::Str(field.as_bytes())),
+            );
+            items.push(Item::Int(0));
+            for (id, fields) in stream.iter() {
+                let same = fields.len() == master_fields.len()
+                    && fields.iter().zip(master_fields).all(|(a, b)| a.0 == b.0);
+                let flags = if same { STREAM_SAME_FIELDS } else { 0 };
+                items.push(Item::Int(flags));
+                items.push(Item::Int(id.ms.wrapping_sub(master.ms) as i64));
+                items.push(Item::Int(id.seq.wrapping_sub(master.seq) as i64));
+                let count = fields.len() as i64;
+                if same {
+                    items.extend(fields.iter().map(|(_, value)| Item::Str(value.as_bytes())));
+                    items.push(Item::Int(count + 3));
+                } else {
+                    items.push(Item::Int(count));
+                    for (field, value) in fields {
+                        items.push(Item::Str(field.as_bytes()));
+                        items.push(Item::Str(value.as_bytes()));
+                    }
+                    items.push(Item::Int(2 * count + 4));
+                }
+            }
+            write_string(out, &listpack::encode(&items));
+        }
+    }
+    write_length(out, stream.len());
+    write_id(out, stream.last_id());
+    write_id(out, first.map_or(StreamId::default(), |(id, _)| *id));
+    write_id(out, stream.max_deleted_id());
+    write_length(out, stream.entries_added() as usize);
+    // no consumer groups
+    write_length(out, 0);
+}
+
+/// A value serialized as DUMP does: its RDB encoding, then the RDB version
+/// and a CRC64 of it all.
+pub fn dump(value: &Value) -> Vec<u8> {
+    let mut out = vec![value_type(value)];
+    write_value(&mut out, value);
+    out.extend(VERSION.to_le_bytes());
+    let crc = crc64(&out);
+    out.extend(crc.to_le_bytes());
+    out
+}
+
+/// A whole RDB file holding the entries as database 0, and where they
+/// stand in the replication stream if given.
+pub fn write(entries: &[RdbEntry], repl: Option<&ReplInfo>) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend(format!("{VERSION:04}").as_bytes());
+    let ctime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut aux = vec![
+        ("redis-ver", "7.2.0".to_string()),
+        ("redis-bits", "64".to_string()),
+        ("ctime", ctime.to_string()),
+        ("aof-base", "0".to_string()),
+    ];
+    if let Some(repl) = repl {
+        aux.push(("repl-stream-db", "0".to_string()));
+        aux.push(("repl-id", repl.replid.clone()));
+        aux.push(("repl-offset", repl.offset.to_string()));
+        // not a Redis field, which Redis skips when loading
+        if let Some(master) = &repl.master {
+            aux.push(("repl-master", master.clone()));
+        }
+    }
+    for (key, value) in aux {
+        out.push(OP_AUX);
+        write_string(&mut out, key.as_bytes());
+        write_string(&mut out, value.as_bytes());
+    }
+
+    out.push(OP_SELECT_DB);
+    write_length(&mut out, 0);
+    out.push(OP_RESIZE_DB);
+    write_length(&mut out, entries.len());
+    let expires = entries.iter().filter(|entry| entry.expires_at.is_some());
+    write_length(&mut out, expires.count());
+    for entry in entries {
+        if let Some(at) = entry.expires_at {
+            out.push(OP_EXPIRE_MS);
+            out.extend(at.to_le_bytes());
+        }
+        out.push(value_type(&entry.value));
+        write_string(&mut out, entry.key.as_bytes());
+        write_value(&mut out, &entry.value);
+    }
+
+    out.push(OP_EOF);
+    let crc = crc64(&out);
+    out.extend(crc.to_le_bytes());
+    out
+}
+
+/// Writes the entries to an RDB file at `path`, through a temporary file
+/// renamed over it, so a crash midway leaves the old file whole.
+pub fn save(entries: &[RdbEntry], repl: Option<&ReplInfo>, path: &Path) -> Result<()> {
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let result = File::create(&temp).and_then(|mut file| {
+        file.write_all(&write(entries, repl))?;
+        file.sync_all()
+    });
+    if let Err(err) = result.and_then(|_| fs::rename(&temp, path)) {
+        let _ = fs::remove_file(&temp);
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// The RDB encoding in a DUMP payload, `None` if its version is newer than
+/// this server reads or its checksum doesn't match.
+pub fn undump(payload: &[u8]) -> Option<&[u8]> {
+    let body_len = payload.len().checked_sub(10)?;
+    let (body, footer) = payload.split_at(body_len);
+    let version = u16::from_le_bytes([footer[0], footer[1]]);
+    let crc = u64::from_le_bytes(footer[2..].try_into().ok()?);
+    if version > VERSION || crc != crc64(&payload[..body_len + 2]) {
+        return None;
+    }
+    Some(body)
+}
+
+/// The value in the RDB encoding of a DUMP payload.
+pub fn read_value(bytes: &[u8]) -> Result<Value> {
+    let mut reader = Reader { bytes, pos: 0 };
+    let value_type = reader.byte()?;
+    let value = reader.value(value_type)?;
+    if reader.pos != bytes.len() {
+        return Err(anyhow!("trailing bytes after value"));
+    }
+    Ok(value)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Rdb> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(anyhow!("missing REDIS magic string"));
+    }
+    let version = std::str::from_utf8(reader.take(4)?)?
+        .parse::<u32>()
+        .map_err(|err| anyhow!("invalid RDB version: {err}"))?;
+
+    let mut aux = vec![];
+    let mut databases: Vec<Database> = vec![];
+    let mut expires_at = None;
+
+    loop {
+        let opcode = reader.byte()?;
+        match opcode {
+            OP_AUX => {
+                let key = reader.string()?;
+                let value = reader.string()?;
+                aux.push((key, value));
+            }
+            OP_SELECT_DB => {
+                let index = reader.length()?;
+                databases.push(Database {
+                    index,
+                    entries: vec![],
+                });
+            }
+            OP_RESIZE_DB => {
+                reader.length()?;
+                reader.length()?;
+            }
+            OP_EXPIRE_SECS => {
+                expires_at = Some(reader.u32_le()? as u64 * 1000);
+            }
+            OP_EXPIRE_MS => {
+                expires_at = Some(reader.u64_le()?);
+            }
+            // eviction hints, of no use without maxmemory
+            OP_IDLE => {
+                reader.length()?;
+            }
+            OP_FREQ => {
+                reader.byte()?;
+            }
+            OP_FUNCTION => {
+                return Err(anyhow!(
+                    "function libraries are not supported (opcode 0xF5 at offset {})",
+                    reader.pos - 1
+                ))
+            }
+            OP_MODULE_AUX => {
+                return Err(anyhow!(
+                    "module data is not supported (opcode 0xF7 at offset {})",
+                    reader.pos - 1
+                ))
+            }
+            OP_EOF => break,
+            value_type => {
+                let key = reader.string()?;
+                let value = reader.value(value_type)?;
+                if databases.is_empty() {
+                    databases.push(Database::default());
+                }
+                databases.last_mut().unwrap().entries.push(RdbEntry {
+                    key,
+                    value,
+                    expires_at: expires_at.take(),
+                });
+            }
+        }
+    }
+
+    let body_len = reader.pos;
+    let checksum = if version < 5 {
+        Checksum::Missing
+    } else {
+        match reader.u64_le()? {
+            0 => Checksum::Disabled,
+            expected => {
+                let actual = crc64(&bytes[..body_len]);
+                if actual != expected {
+                    return Err(anyhow!(
+                        "checksum mismatch: expected {expected:016x}, computed {actual:016x}"
+                    ));
+                }
+                Checksum::Valid(actual)
+            }
+        }
+    };
+
+    Ok(Rdb {
+        version,
+        aux,
+        databases,
+        checksum,
+        len: reader.pos,
+    })
+}
+
+// CRC-64/Jones (reflected), the variant used for the RDB trailer
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95AC_9329_AC4B_C9B5
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc, byte| {
+        CRC64_TABLE[((crc ^ *byte as u64) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+
+/// Total bytes as a u32 and element count as a u16
+const HEADER_LEN: usize = 6;
+const EOF: u8 = 0xFF;
+
+/// A listpack element, which Redis stores as an integer whenever it can.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Item<'a> {
+    Int(i64),
+    Str(&'a [u8]),
+}
+
+impl Item<'_> {
+    pub fn text(&self) -> String {
+        match self {
+            Item::Int(value) => value.to_string(),
+            Item::Str(value) => String::from_utf8_lossy(value).into_owned(),
+        }
+    }
+
+    pub fn int(&self) -> Result<i64> {
+        match self {
+            Item::Int(value) => Ok(*value),
+            Item::Str(value) => std::str::from_utf8(value)?
+                .parse()
+                .map_err(|_| anyhow!("expected an integer in listpack")),
+        }
+    }
+}
+
+/// Bytes of the trailing length each element carries, so a listpack can
+/// be walked backwards: 7 bits per byte, with the same cutoffs as Redis.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Item<'_>>> {
+    let truncated = || anyhow!("truncated listpack");
+    let take = |start: usize, len: usize| bytes.get(start..start + len).ok_or_else(truncated);
+    let total = u32::from_le_bytes(take(0, 4)?.try_into()?) as usize;
+    if total != bytes.len() {
+        return Err(anyhow!(
+            "listpack claims {total} bytes but has {}",
+            bytes.len()
+        ));
+    }
+
+    let mut items = vec![];
+    let mut pos = HEADER_LEN;
+    loop {
+        let first = *bytes.get(pos).ok_or_else(truncated)?;
+        let (item, len) = match first {
+            EOF => break,
+            0x00..=0x7F => (Item::Int(first as i64), 1),
+            0x80..=0xBF => {
+                let len = (first & 0x3F) as usize;
+                (Item::Str(take(pos + 1, len)?), 1 + len)
+            }
+            0xC0..=0xDF => {
+                let value = ((first as i64 & 0x1F) << 8) | take(pos + 1, 1)?[0] as i64;
+                // 13-bit two's complement
+                let value = if value >= 1 << 12 {
+                    value - (1 << 13)
+                } else {
+                    value
+                };
+                (Item::Int(value), 2)
+            }
+            0xE0..=0xEF => {
+                let len = ((first as usize & 0x0F) << 8) | take(pos + 1, 1)?[0] as usize;
+                (Item::Str(take(pos + 2, len)?), 2 + len)
+            }
+            0xF0 => {
+                let len = u32::from_le_bytes(take(pos + 1, 4)?.try_into()?) as usize;
+                (Item::Str(take(pos + 5, len)?), 5 + len)
+            }
+            0xF1 => {
+                let value = i16::from_le_bytes(take(pos + 1, 2)?.try_into()?);
+                (Item::Int(value as i64), 3)
+            }
+            0xF2 => {
+                let [a, b, c] = take(pos + 1, 3)?.try_into()?;
+                // shifted back down to sign extend the 24 bits
+                (Item::Int((i32::from_le_bytes([0, a, b, c]) >> 8) as i64), 4)
+            }
+            0xF3 => {
+                let value = i32::from_le_bytes(take(pos + 1, 4)?.try_into()?);
+                (Item::Int(value as i64), 5)
+            }
+            0xF4 => {
+                let value = i64::from_le_bytes(take(pos + 1, 8)?.try_into()?);
+                (Item::Int(value), 9)
+            }
+            other => return Err(anyhow!("invalid listpack encoding {other:#04x}")),
+        };
+        items.push(item);
+        pos += len + backlen_size(len);
+    }
+    if pos + 1 != bytes.len() {
+        return Err(anyhow!("trailing bytes after listpack end"));
+    }
+    Ok(items)
+}
+
+pub fn encode(items: &[Item]) -> Vec<u8> {
+    let mut out = vec![0; HEADER_LEN];
+    for item in items {
+        let start = out.len();
+        match *item {
+            Item::Int(value @ 0..=127) => out.push(value as u8),
+            Item::Int(value) if (-4096..4096).contains(&value) => {
+                let value = value as u16 & 0x1FFF;
+                out.extend([0xC0 | (value >> 8) as u8, value as u8]);
+            }
+            Item::Int(value) if i16::try_from(value).is_ok() => {
+                out.push(0xF1);
+                out.extend((value as i16).to_le_bytes());
+            }
+            Item::Int(value) if (-(1 << 23)..1 << 23).contains(&value) => {
+                out.push(0xF2);
+                out.extend(&(value as i32).to_le_bytes()[..3]);
+            }
+            Item::Int(value) if i32::try_from(value).is_ok() => {
+                out.push(0xF3);
+                out.extend((value as i32).to_le_bytes());
+            }
+            Item::Int(value) => {
+                out.push(0xF4);
+                out.extend(value.to_le_bytes());
+            }
+            Item::Str(value) => {
+                let len = value.len();
+                match len {
+                    0..=63 => out.push(0x80 | len as u8),
+                    64..=4095 => out.extend([0xE0 | (len >> 8) as u8, len as u8]),
+                    _ => {
+                        out.push(0xF0);
+                        out.extend((len as u32).to_le_bytes());
+                    }
+                }
+                out.extend_from_slice(value);
+            }
+        }
+        // the high bit marks every byte but the first
+        let len = out.len() - start;
+        let size = backlen_size(len);
+        for i in (0..size).rev() {
+            let group = (len >> (7 * i)) as u8 & 0x7F;
+            out.push(if i == size - 1 { group } else { group | 0x80 });
+        }
+    }
+    out.push(EOF);
+
+    let total = out.len() as u32;
+    // counts that don't fit are left for readers to find by walking
+    let count = items.len().min(u16::MAX as usize) as u16;
+    out[..4].copy_from_slice(&total.to_le_bytes());
+    out[4..HEADER_LEN].copy_from_slice(&count.to_le_bytes());
+    out
+}
@@ -0,0 +1,127 @@
+use anyhow::{anyhow, Result};
+
+/// Literal runs are at most this long
+const MAX_LITERAL: usize = 32;
+/// Back references reach at most this far
+const MAX_OFFSET: usize = 1 << 13;
+/// Back references copy at most this many bytes
+const MAX_REF: usize = (1 << 8) + (1 << 3);
+const HASH_LOG: u32 = 14;
+
+/// Decompresses an LZF block that must come to exactly `len` bytes.
+pub fn decompress(input: &[u8], len: usize) -> Result<Vec<u8>> {
+    let corrupt = || anyhow!("corrupt LZF compressed string");
+    // `len` comes from the payload, and no back reference makes more than
+    // MAX_REF bytes out of the bytes it takes
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(MAX_REF)));
+    let mut pos = 0;
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < MAX_LITERAL {
+            let run = input.get(pos..pos + ctrl + 1).ok_or_else(corrupt)?;
+            if out.len() + run.len() > len {
+                return Err(corrupt());
+            }
+            out.extend_from_slice(run);
+            pos += ctrl + 1;
+            continue;
+        }
+        let mut count = ctrl >> 5;
+        if count == 7 {
+            count += *input.get(pos).ok_or_else(corrupt)? as usize;
+            pos += 1;
+        }
+        let low = *input.get(pos).ok_or_else(corrupt)? as usize;
+        pos += 1;
+        let offset = ((ctrl & 0x1F) << 8 | low) + 1;
+        let start = out.len().checked_sub(offset).ok_or_else(corrupt)?;
+        if out.len() + count + 2 > len {
+            return Err(corrupt());
+        }
+        // byte by byte, since the copy may overlap what it produces
+        for i in 0..count + 2 {
+            out.push(out[start + i]);
+        }
+    }
+    if out.len() != len {
+        return Err(anyhow!(
+            "LZF string came to {} bytes instead of {len}",
+            out.len()
+        ));
+    }
+    Ok(out)
+}
+
+/// Compresses as liblzf does, `None` unless it saves at least 4 bytes,
+/// which is when Redis bothers.
+pub fn compress(input: &[u8]) -> Option<Vec<u8>> {
+    let limit = input.len().checked_sub(4)?;
+    let mut table = vec![usize::MAX; 1 << HASH_LOG];
+    let hash = |at: usize| {
+        let v = u32::from_be_bytes([0, input[at], input[at + 1], input[at + 2]]);
+        (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+    };
+
+    // each literal run is preceded by its length, patched in once known
+    let mut out = vec![0];
+    let mut run_start = 0;
+    let mut run = 0;
+    let mut pos = 0;
+    while pos < input.len() {
+        let found = (pos + 2 < input.len())
+            .then(|| std::mem::replace(&mut table[hash(pos)], pos))
+            .filter(|&candidate| {
+                candidate != usize::MAX
+                    && pos - candidate <= MAX_OFFSET
+                    && input[candidate..candidate + 3] == input[pos..pos + 3]
+            });
+        match found {
+            Some(candidate) => {
+                let max = MAX_REF.min(input.len() - pos);
+                let mut len = 3;
+                while len < max && input[candidate + len] == input[pos + len] {
+                    len += 1;
+                }
+                if run == 0 {
+                    out.pop();
+                } else {
+                    out[run_start] = (run - 1) as u8;
+                }
+                let offset = pos - candidate - 1;
+                let count = len - 2;
+                if count < 7 {
+                    out.push((count << 5 | offset >> 8) as u8);
+                } else {
+                    out.push((7 << 5 | offset >> 8) as u8);
+                    out.push((count - 7) as u8);
+                }
+                out.push(offset as u8);
+                pos += len;
+                run_start = out.len();
+                run = 0;
+                out.push(0);
+            }
+            None => {
+                out.push(input[pos]);
+                run += 1;
+                pos += 1;
+                if run == MAX_LITERAL {
+                    out[run_start] = (run - 1) as u8;
+                    run_start = out.len();
+                    run = 0;
+                    out.push(0);
+                }
+            }
+        }
+        if out.len() > limit {
+            return None;
+        }
+    }
+    if run == 0 {
+        out.pop();
+    } else {
+        out[run_start] = (run - 1) as u8;
+    }
+    Some(out)
+}
@@ -3,13 +3,70 @@ use std::sync::Arc;
 use anyhow::{anyhow, Result};
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::time;
 
 use crate::command::{Command, Replconf};
 use crate::db::DB;
-use crate::parse::array;
-use crate::{parse, Server};
+use crate::master::{Replicas, Waiters};
+use crate::parse::{array, array_bytes};
+use crate::{exec, parse, rdb, Server};
 
-pub async fn sync_with_master(mut stream: TcpStream, server: Arc<Server>, db: DB) -> Result<()> {
+const RECONNECT_DELAY: time::Duration = time::Duration::from_secs(1);
+
+/// Where this replica is in the master's replication stream, kept across
+/// reconnects so it can ask for a partial resync.
+#[derive(Debug)]
+pub struct MasterState {
+    pub replid: String,
+    pub offset: usize,
+}
+
+/// Keeps the replica attached to its master, reconnecting after failures.
+/// `synced` fires once the first sync with the master has completed.
+pub async fn replicate(
+    master_addr: String,
+    server: Arc<Server>,
+    db: DB,
+    replicas: Replicas,
+    waiters: Waiters,
+    mut synced: Option<oneshot::Sender<()>>,
+) {
+    let mut state = server.take_resume();
+    loop {
+        match TcpStream::connect(&master_addr).await {
+            Ok(stream) => {
+                let result = sync_with_master(
+                    stream,
+                    server.clone(),
+                    db.clone(),
+                    replicas.clone(),
+                    &waiters,
+                    &mut state,
+                    &mut synced,
+                )
+                .await;
+                if let Err(err) = result {
+                    eprintln!("[ERROR] Replica: Disconnected from master with error: {err}")
+                } else {
+                    eprintln!("[INFO] Replica: Disconnected from master")
+                }
+            }
+            Err(err) => eprintln!("[ERROR] Replica: Can't connect to master {master_addr}: {err}"),
+        }
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+pub async fn sync_with_master(
+    mut stream: TcpStream,
+    server: Arc<Server>,
+    db: DB,
+    mut replicas: Replicas,
+    waiters: &Waiters,
+    state: &mut Option<MasterState>,
+    synced: &mut Option<oneshot::Sender<()>>,
+) -> Result<()> {
     let (mut reader, mut writer) = stream.split();
     let mut reader = BufReader::new(&mut reader);
     let mut response = String::new();
@@ -19,13 +76,26 @@ pub async fn sync_with_master(mut stream: TcpStream, server: Arc<Server>, db: DB
     writer.write_all(array(&vec!["ping"]).as_bytes()).await?;
     response.clear();
     reader.read_line(&mut response).await?;
-    if response.to_lowercase() != "+pong\r\n".to_lowercase() {
+    // a master with a password only answers once we AUTH, as below
+    if response.to_lowercase() != "+pong\r\n".to_lowercase() && !response.starts_with("-NOAUTH") {
         return Err(anyhow!("expected pong, but got: {response}"));
     }
 
+    let masterauth = server.config.get("masterauth");
+    if !masterauth.is_empty() {
+        writer
+            .write_all(array(&vec!["AUTH", &masterauth]).as_bytes())
+            .await?;
+        response.clear();
+        reader.read_line(&mut response).await?;
+        if response != "+OK\r\n" {
+            return Err(anyhow!("AUTH with the master failed: {response:?}"));
+        }
+    }
+
     // ConfPort
     writer
-        .write_all(array(&vec!["REPLCONF", "listening-port", &server.port]).as_bytes())
+        .write_all(array(&vec!["REPLCONF", "listening-port", &server.port()]).as_bytes())
         .await?;
     response.clear();
     reader.read_line(&mut response).await?;
@@ -43,41 +113,83 @@ pub async fn sync_with_master(mut stream: TcpStream, server: Arc<Server>, db: DB
         return Err(anyhow!("expected ok, but got: {response:?}"));
     }
 
-    // SyncFile
+    // SyncFile, asking to continue where we left off if we synced before
+    let (replid, psync_offset) = match state {
+        Some(state) => (state.replid.clone(), (state.offset + 1).to_string()),
+        None => ("?".to_string(), "-1".to_string()),
+    };
     response.clear();
     writer
-        .write_all(array(&vec!["PSYNC", "?", "-1"]).as_bytes())
+        .write_all(array(&vec!["PSYNC", &replid, &psync_offset]).as_bytes())
         .await?;
     reader.read_line(&mut response).await?;
-    // todo assert response
 
-    // read file length
-    response.clear();
-    reader.read_line(&mut response).await?;
-    println!("file length {:?}", response);
-    let file_length = response[1..response.len() - 2].parse()?;
+    let words: Vec<&str> = response.split_whitespace().collect();
+    match (words.as_slice(), state.as_mut()) {
+        (["+CONTINUE"], Some(_)) => {
+            eprintln!("[INFO] Replica: partial resync accepted");
+        }
+        (["+CONTINUE", new_replid], Some(state)) => {
+            eprintln!("[INFO] Replica: partial resync accepted, master replid is now {new_replid}");
+            state.replid = new_replid.to_string();
+            server.switch_replid(new_replid);
+        }
+        (["+FULLRESYNC", replid, offset], _) => {
+            *state = Some(MasterState {
+                replid: replid.to_string(),
+                offset: offset.parse()?,
+            });
+            // the snapshot replaces whatever we had, and what our own
+            // replicas got of it
+            db.clear();
+            replicas.reset();
+            server.adopt_replid(replid);
 
-    // read file
-    let mut file_buff = vec![0; file_length];
-    println!("file buff {:?}", file_buff);
-    let _ = reader.read_exact(&mut file_buff).await;
+            // read file length
+            response.clear();
+            reader.read_line(&mut response).await?;
+            let file_length = response[1..response.len() - 2].parse()?;
+
+            // read file
+            let mut file_buff = vec![0; file_length];
+            reader.read_exact(&mut file_buff).await?;
+            let rdb =
+                rdb::parse(&file_buff).map_err(|err| anyhow!("bad RDB from master: {err}"))?;
+            let entries = rdb.databases.into_iter().flat_map(|db| db.entries);
+            let keys = db.load(entries.collect());
+            eprintln!("[INFO] Replica: loaded {keys} keys from the master's snapshot");
+        }
+        _ => return Err(anyhow!("unexpected PSYNC reply: {response:?}")),
+    }
+    let state = state.as_mut().expect("set by the PSYNC reply");
+    server.set_offset(state.offset as u64);
+    // keys past their expiry are still there for the master's commands
+    let master_db = db.master_view();
+    if let Some(synced) = synced.take() {
+        let _ = synced.send(());
+    }
 
-    let mut offset: usize = 0;
     // Handshake ended now wait for commands
     while let Some((tokenz, count)) = parse::tokenize(&mut reader).await? {
         let command = Command::parse(&tokenz);
+        let write = command.is_write();
         match command {
-            Command::Set { key, value, ex } => {
-                db.set(key.to_owned(), value.to_string(), ex.to_owned());
-                println!("Replica: wrote {key} {value}")
+            // the master's stream carries writes only, their replies go nowhere
+            ref write if write.is_write() => {
+                exec::execute(&master_db, write);
+                // clients blocked here get what the master's clients pushed
+                waiters.wake_for(write);
             }
             Command::Replconf(Replconf::GetAck(_val)) => {
-                let response = array(&vec!["REPLCONF", "ACK", format!("{offset}").as_ref()]);
+                let offset = state.offset.to_string();
+                let response = array(&vec!["REPLCONF", "ACK", &offset]);
                 writer.write_all(response.as_bytes()).await?;
             }
             _ => {}
         }
-        offset += count;
+        replicas.proxy(&array_bytes(&tokenz), write);
+        state.offset += count;
+        server.set_offset(state.offset as u64);
     }
 
     Ok(())
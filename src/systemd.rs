@@ -0,0 +1,47 @@
+use std::env;
+use std::os::fd::FromRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+// first file descriptor passed by socket activation, after stdin/out/err
+const LISTEN_FDS_START: i32 = 3;
+
+/// Takes over the listening socket passed by systemd socket activation,
+/// if this process was started that way.
+pub fn inherited_listener() -> Option<std::net::TcpListener> {
+    let pid = env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    let fds = env::var("LISTEN_FDS").ok()?.parse::<i32>().ok()?;
+    // the variables are only meant for the process systemd started
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    if pid != std::process::id() || fds < 1 {
+        return None;
+    }
+    if fds > 1 {
+        eprintln!("[WARN] systemd passed {fds} sockets, only the first one is used");
+    }
+
+    // SAFETY: systemd hands us ownership of the listening socket at fd 3
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener.set_nonblocking(true).ok()?;
+    Some(listener)
+}
+
+/// Sends a state update such as `READY=1` to systemd, when supervised by it.
+pub fn notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()),
+        None => SocketAddr::from_pathname(&path),
+    };
+    let result = addr.and_then(|addr| {
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    if let Err(err) = result {
+        eprintln!("[WARN] Failed to notify systemd at {path}: {err}");
+    }
+}
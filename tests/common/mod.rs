@@ -0,0 +1,135 @@
+// Each test binary uses only some of these
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A server on a port of its own, killed when dropped.
+pub struct Server {
+    child: Child,
+    pub port: u16,
+    dir: PathBuf,
+}
+
+impl Server {
+    pub fn start(args: &[&str]) -> Self {
+        Self::start_with_rdb(None, args)
+    }
+
+    /// Starts with `rdb` as the dump it loads at startup.
+    pub fn start_with_rdb(rdb: Option<&[u8]>, args: &[&str]) -> Self {
+        let (child, port, dir) = spawn(rdb, args, Stdio::null());
+        let server = Self { child, port, dir };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline, "server didn't start");
+            thread::sleep(Duration::from_millis(20));
+        }
+        server
+    }
+
+    /// Sends a command, returning the reply as it came.
+    pub fn call(&self, args: &[&str]) -> String {
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_bytes()).collect();
+        self.call_bytes(&args)
+    }
+
+    pub fn call_bytes(&self, args: &[&[u8]]) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend(*arg);
+            request.extend(b"\r\n");
+        }
+        stream.write_all(&request).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if let Some(len) = line
+            .strip_prefix('$')
+            .and_then(|n| n.trim().parse::<usize>().ok())
+        {
+            let mut body = vec![0; len + 2];
+            reader.read_exact(&mut body).unwrap();
+            line.push_str(&String::from_utf8(body).unwrap());
+        }
+        line
+    }
+
+    pub fn info(&self, field: &str) -> String {
+        let info = self.call(&["info", "replication"]);
+        info.lines()
+            .find_map(|line| line.strip_prefix(&format!("{field}:")))
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+/// CRC-64/Jones, as DUMP payloads and RDB files end with.
+pub fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc, byte| {
+        let mut crc = crc ^ *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95AC_9329_AC4B_C9B5
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+/// Starts a server with `rdb` as its dump, expecting it to refuse it, and
+/// returns what it logged.
+pub fn refuse_rdb(rdb: &[u8]) -> String {
+    let (mut child, _, dir) = spawn(Some(rdb), &[], Stdio::piped());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            panic!("server started with a bad RDB");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let output = child.wait_with_output().unwrap();
+    let _ = std::fs::remove_dir_all(dir);
+    assert!(!output.status.success());
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn spawn(rdb: Option<&[u8]>, args: &[&str], stderr: Stdio) -> (Child, u16, PathBuf) {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let dir = std::env::temp_dir().join(format!("redis-test-{port}"));
+    std::fs::create_dir_all(&dir).unwrap();
+    if let Some(rdb) = rdb {
+        std::fs::write(dir.join("dump.rdb"), rdb).unwrap();
+    }
+    let child = Command::new(env!("CARGO_BIN_EXE_redis-starter-rust"))
+        .args(["--port", &port.to_string(), "--save", "", "--dir"])
+        .arg(&dir)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+        .unwrap();
+    (child, port, dir)
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn import_restores_every_exported_type() {
+    let server = Server::start(&[]);
+    server.call(&["rpush", "list", "a", "b"]);
+    server.call(&["sadd", "set", "x"]);
+    server.call(&["hset", "hash", "field", "value"]);
+    server.call(&["zadd", "zset", "-inf", "low", "1.5", "high"]);
+    server.call(&["xadd", "stream", "1-1", "field", "value"]);
+    assert_eq!(server.call(&["export", "dump.json"]), ":5\r\n");
+
+    server.call(&["del", "list", "set", "hash", "zset", "stream"]);
+    assert_eq!(server.call(&["import", "dump.json"]), ":5\r\n");
+    assert_eq!(server.call(&["llen", "list"]), ":2\r\n");
+    assert_eq!(server.call(&["sismember", "set", "x"]), ":1\r\n");
+    assert_eq!(server.call(&["hget", "hash", "field"]), "$5\r\nvalue\r\n");
+    assert_eq!(server.call(&["zscore", "zset", "low"]), "$4\r\n-inf\r\n");
+    assert_eq!(server.call(&["type", "stream"]), "+stream\r\n");
+    // the stream goes on from its last entry
+    assert!(server
+        .call(&["xadd", "stream", "1-1", "field", "value"])
+        .starts_with("-ERR"));
+}
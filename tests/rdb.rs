@@ -0,0 +1,42 @@
+mod common;
+
+use common::Server;
+
+#[test]
+fn loads_a_dump_from_redis() {
+    // written by redis-server 7.2.0, aux fields only
+    let server = Server::start_with_rdb(Some(include_bytes!("fixtures/redis-7.2-empty.rdb")), &[]);
+    assert_eq!(server.call(&["keys", "*"]), "*0\r\n");
+}
+
+#[test]
+fn skips_eviction_hints() {
+    // the 7.2.0 dump with a key carrying an LRU idle time and one carrying
+    // an LFU frequency, as Redis writes them under those policies
+    let server = Server::start_with_rdb(Some(include_bytes!("fixtures/idle-freq.rdb")), &[]);
+    assert_eq!(server.call(&["get", "idle"]), "$3\r\none\r\n");
+    assert_eq!(server.call(&["get", "freq"]), "$3\r\ntwo\r\n");
+}
+
+#[test]
+fn refuses_function_libraries() {
+    let log = common::refuse_rdb(include_bytes!("fixtures/function.rdb"));
+    assert!(
+        log.contains("function libraries are not supported"),
+        "{log}"
+    );
+}
+
+#[test]
+fn restore_refuses_lzf_strings_longer_than_their_data() {
+    let server = Server::start(&[]);
+    // a string, LZF compressed: 2 bytes of data claiming to expand to 1TB
+    let mut payload = vec![0x00, 0xC3, 0x02, 0x81];
+    payload.extend((1u64 << 40).to_be_bytes());
+    payload.extend([0x00, b'a']);
+    payload.extend(11u16.to_le_bytes());
+    payload.extend(common::crc64(&payload).to_le_bytes());
+    let reply = server.call_bytes(&[b"restore", b"k", b"0", &payload]);
+    assert!(reply.starts_with("-"), "{reply}");
+    assert_eq!(server.call(&["ping"]), "+PONG\r\n");
+}
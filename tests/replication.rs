@@ -0,0 +1,61 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::Server;
+
+#[test]
+fn replica_offset_matches_master() {
+    let master = Server::start(&[]);
+    let replica = Server::start(&["--replicaof", &format!("127.0.0.1 {}", master.port)]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while master.info("connected_slaves") != "1" {
+        assert!(Instant::now() < deadline, "replica didn't attach");
+        thread::sleep(Duration::from_millis(20));
+    }
+    for i in 0..10 {
+        assert_eq!(
+            master.call(&["set", &format!("key{i}"), "value"]),
+            "+OK\r\n"
+        );
+    }
+    // its GETACK moves the offset too, which the replica must count alike
+    assert_eq!(master.call(&["wait", "1", "5000"]), ":1\r\n");
+
+    let offset = master.info("master_repl_offset");
+    assert_ne!(offset, "0");
+    while replica.info("master_repl_offset") != offset {
+        assert!(
+            Instant::now() < deadline,
+            "replica at offset {}, master at {offset}",
+            replica.info("master_repl_offset")
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+    // it goes on with its master's history, not one of its own
+    assert_eq!(replica.info("master_replid"), master.info("master_replid"));
+}
+
+#[test]
+fn replica_wakes_blocked_clients() {
+    let master = Server::start(&[]);
+    let replica = Server::start(&["--replicaof", &format!("127.0.0.1 {}", master.port)]);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while master.info("connected_slaves") != "1" {
+        assert!(Instant::now() < deadline, "replica didn't attach");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let started = Instant::now();
+    let reply = thread::scope(|scope| {
+        let blocked =
+            scope.spawn(|| replica.call(&["xread", "block", "30000", "streams", "events", "$"]));
+        thread::sleep(Duration::from_millis(200));
+        master.call(&["xadd", "events", "*", "type", "signup"]);
+        blocked.join().unwrap()
+    });
+    assert_eq!(reply, "*1\r\n");
+    assert!(started.elapsed() < Duration::from_secs(10));
+}